@@ -6,6 +6,12 @@ inline fun <R> YDoc.transact(origin: String? = null, block: (txn : YTransaction)
     }
 }
 
+inline fun <R> YDoc.readTransact(block: (txn : YTransaction) -> R): R {
+    return this.readTransaction().use { txn ->
+        block(txn)
+    }
+}
+
 fun createXmlElement(
     name: String,
     attributes: Map<String, YValue>? = null,
@@ -54,4 +60,28 @@ fun longYValue(value: Long) =
 fun numberYValue(value: Double) =
     YValue.Number(
         value
-    )
\ No newline at end of file
+    )
+
+fun YMap.set(key: String, value: YValue, txn: YTransaction? = null) =
+    this.set(key, YIn.Any(value), txn)
+
+fun YArray.insert(index: UInt, value: YValue, txn: YTransaction? = null) =
+    this.insert(index, YIn.Any(value), txn)
+
+fun YArray.insertRange(index: UInt, items: List<YValue>, txn: YTransaction? = null) =
+    this.insertRange(index, items.map { YIn.Any(it) }, txn)
+
+fun YArray.push(value: YValue, txn: YTransaction? = null) =
+    this.push(YIn.Any(value), txn)
+
+fun YText.insert(index: UInt, chunk: String, attributes: Map<String, YValue>, txn: YTransaction? = null) =
+    this.insertWithAttributes(index, chunk, attributes, txn)
+
+fun YText.insertEmbed(index: UInt, embed: YIn, attributes: Map<String, YValue>, txn: YTransaction? = null) =
+    this.insertEmbedWithAttributes(index, embed, attributes, txn)
+
+fun YText.format(index: UInt, length: UInt, attributes: Map<String, YValue>, txn: YTransaction? = null) =
+    this.formatWithAttributes(index, length, attributes, txn)
+
+fun YText.push(chunk: String, attributes: Map<String, YValue>, txn: YTransaction? = null) =
+    this.pushWithAttributes(chunk, attributes, txn)
@@ -0,0 +1,166 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertNull
+import kotlin.test.assertTrue
+
+class YArrayTest {
+    private fun numbers(vararg values: Long): List<YValue> = values.map { longYValue(it) }
+
+    private fun outs(vararg values: Long): List<YOut> = values.map { YOut.Any(longYValue(it)) }
+
+    @Test
+    fun TestInsertGet() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val array = doc.getArray("array")
+
+        array.push(longYValue(3))
+        array.insert(0u, longYValue(1))
+        array.insertRange(1u, numbers(2))
+
+        assertEquals(3u, array.length())
+        assertEquals(outs(1, 2, 3), array.toList())
+        assertEquals(YOut.Any(longYValue(2)), array.get(1u))
+        assertNull(array.get(3u))
+    }
+
+    @Test
+    fun TestDelete() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val array = doc.getArray("array")
+
+        doc.transact { txn ->
+            array.insertRange(0u, numbers(1, 2, 3, 4, 5), txn)
+            array.delete(1u, 3u, txn)
+        }
+
+        assertEquals(outs(1, 5), array.toList())
+    }
+
+    @Test
+    fun TestMove() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+
+        val a1 = doc.getArray("a1")
+        a1.insertRange(0u, numbers(1, 2, 3, 4))
+        a1.moveTo(0u, 2u)
+        assertEquals(outs(2, 1, 3, 4), a1.toList())
+
+        val a2 = doc.getArray("a2")
+        a2.insertRange(0u, numbers(1, 2, 3, 4))
+        a2.moveRangeTo(1u, 2u, 4u)
+        assertEquals(outs(1, 4, 2, 3), a2.toList())
+
+        // reversed range is rejected and leaves array untouched
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { a2.moveRangeTo(2u, 1u, 0u) }
+        assertEquals(outs(1, 4, 2, 3), a2.toList())
+    }
+
+    @Test
+    fun TestPrelim() {
+        val array = YArray.newWithItems(numbers(1, 2).map { YIn.Any(it) })
+        assertTrue(array.prelim())
+
+        array.push(longYValue(4))
+        array.insert(2u, longYValue(3))
+        array.delete(0u, 1u)
+
+        assertEquals(outs(2, 3, 4), array.toList())
+    }
+
+    @Test
+    fun TestSync() {
+        val d1 = YDoc(YDocOptions(1u, gc = false))
+        val a1 = d1.getArray("array")
+        a1.insertRange(0u, listOf(stringYValue("a"), booleanYValue(true), numberYValue(1.5)))
+
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, encodeStateAsUpdateV2(d1))
+
+        val a2 = d2.getArray("array")
+        assertEquals(a1.toList(), a2.toList())
+
+        // concurrent edits on both peers
+        a1.push(stringYValue("from d1"))
+        a2.insert(0u, stringYValue("from d2"))
+
+        val u1 = encodeStateAsUpdateV2(d1)
+        val u2 = encodeStateAsUpdateV2(d2)
+        applyUpdateV2(d2, u1)
+        applyUpdateV2(d1, u2)
+
+        assertEquals(5u, a1.length())
+        assertEquals(a1.toList(), a2.toList())
+    }
+
+    @Test
+    fun TestNestedOut() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val xml = doc.getXmlFragment("xml")
+        doc.transact { txn ->
+            xml.push(createXmlElement("p", emptyMap(), listOf(createXmlText("hello"))), txn)
+            xml.push(createXmlText("world"), txn)
+        }
+
+        // root type projected onto an array exposes XML nodes as nested shared types
+        val items = doc.getArray("xml").toList()
+        assertEquals(2, items.size)
+
+        val first = items[0] as YOut.XmlElement
+        assertEquals("p", first.v1.name())
+        assertEquals("<p>hello</p>", first.v1.toText())
+
+        val second = items[1] as YOut.XmlText
+        assertEquals("world", second.v1.toText())
+    }
+
+    @Test
+    fun TestNestedInsert() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val array = doc.getArray("array")
+
+        doc.transact { txn ->
+            array.push(YIn.Map(YMap.newWithEntries(mapOf("id" to YIn.Any(stringYValue("card-1"))))), txn)
+            array.insertRange(
+                0u,
+                listOf(YIn.Any(stringYValue("header")), YIn.XmlFragment(YXmlFragment(listOf(createXmlText("note"))))),
+                txn
+            )
+        }
+
+        val items = array.toList()
+        assertEquals(3, items.size)
+        assertEquals(YOut.Any(stringYValue("header")), items[0])
+        assertEquals("note", (items[1] as YOut.XmlFragment).v1.toText())
+        assertEquals(YOut.Any(stringYValue("card-1")), (items[2] as YOut.Map).v1.get("id"))
+    }
+
+    @Test
+    fun TestDuplicatePrelims() {
+        val doc = YDoc(YDocOptions(1u))
+        val array = doc.getArray("array")
+        val child = YXmlElement("b")
+        val parent = YXmlElement("a", children = listOf(YXmlChild.Element(child)))
+
+        assertThrows<com.planerist.ykt.Exception.DuplicatePrelim> {
+            array.insertRange(0u, listOf(YIn.XmlElement(child), YIn.XmlElement(child)))
+        }
+        assertThrows<com.planerist.ykt.Exception.DuplicatePrelim> {
+            array.insertRange(0u, listOf(YIn.XmlElement(parent), YIn.XmlElement(child)))
+        }
+        val subdoc = YDoc()
+        assertThrows<com.planerist.ykt.Exception.DuplicatePrelim> {
+            array.insertRange(0u, listOf(YIn.Doc(subdoc), YIn.Doc(subdoc)))
+        }
+        assertEquals(0u, array.length())
+        assertTrue(child.prelim())
+
+        array.push(YIn.XmlElement(parent))
+        assertFalse(child.prelim())
+        assertThrows<com.planerist.ykt.Exception.NotPrelim> {
+            YXmlElement("c", children = listOf(YXmlChild.Element(child)))
+        }
+    }
+}
@@ -0,0 +1,59 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertContentEquals
+import kotlin.test.assertEquals
+import kotlin.test.assertNull
+
+class YAwarenessTest {
+    @Test
+    fun TestSync() {
+        val a1 = YAwareness(YDoc(YDocOptions(1u)))
+        val a2 = YAwareness(YDoc(YDocOptions(2u)))
+
+        val events = mutableListOf<YAwarenessEvent>()
+        a2.observeChange(object : YAwarenessObserver {
+            override fun onChange(event: YAwarenessEvent) {
+                events.add(event)
+            }
+        }).use {
+            a1.setLocalState("{\"user\":\"alice\"}")
+            a2.applyUpdate(a1.encodeUpdate(), "network".encodeToByteArray())
+            assertEquals(mapOf(1uL to "{\"user\":\"alice\"}"), a2.states())
+
+            a1.setLocalState("{\"user\":\"alice\",\"cursor\":5}")
+            a2.applyUpdate(a1.encodeUpdate(listOf(1uL)))
+
+            // peer going offline
+            a1.setLocalState(null)
+            assertNull(a1.localState())
+            a2.applyUpdate(a1.encodeUpdate(listOf(1uL)))
+            assertEquals(emptyMap(), a2.states())
+        }
+
+        assertEquals(3, events.size)
+        assertEquals(listOf(1uL), events[0].added)
+        assertContentEquals("network".encodeToByteArray(), events[0].origin)
+        assertEquals(listOf(1uL), events[1].updated)
+        assertEquals(listOf(1uL), events[2].removed)
+    }
+
+    @Test
+    fun TestOutdatedStates() {
+        val a1 = YAwareness(YDoc(YDocOptions(1u)))
+        val a2 = YAwareness(YDoc(YDocOptions(2u)))
+        a1.setLocalState("{}")
+        a2.setLocalState("{}")
+        a2.applyUpdate(a1.encodeUpdate())
+
+        assertEquals(emptyList(), a2.removeOutdatedStates())
+        assertEquals(listOf(1uL), a2.removeOutdatedStates(0u))
+        assertEquals(setOf(2uL), a2.states().keys)
+    }
+
+    @Test
+    fun TestInvalidState() {
+        val awareness = YAwareness(YDoc())
+        assertThrows<com.planerist.ykt.Exception.Decode> { awareness.setLocalState("{not json") }
+    }
+}
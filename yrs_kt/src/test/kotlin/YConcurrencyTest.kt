@@ -0,0 +1,150 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import java.util.concurrent.CountDownLatch
+import java.util.concurrent.atomic.AtomicInteger
+import kotlin.concurrent.thread
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertTrue
+
+class YConcurrencyTest {
+    // retries an operation for as long as it fails due to contention with other threads
+    private fun <R> retry(contended: AtomicInteger, block: () -> R): R {
+        while (true) {
+            try {
+                return block()
+            } catch (e: com.planerist.ykt.Exception.AnotherTx) {
+                contended.incrementAndGet()
+                Thread.yield()
+            }
+        }
+    }
+
+    @Test
+    fun TestConcurrentWrites() {
+        val doc = YDoc(YDocOptions(1u))
+        val text = doc.getText("text")
+        val array = doc.getArray("array")
+        val threadCount = 8
+        val iterations = 200
+        val contended = AtomicInteger()
+        val errors = AtomicInteger()
+        val start = CountDownLatch(1)
+
+        val threads = (0 until threadCount).map { i ->
+            thread {
+                start.await()
+                try {
+                    repeat(iterations) {
+                        if (i % 2 == 0) {
+                            retry(contended) { text.push("x") }
+                        } else {
+                            retry(contended) {
+                                doc.transact { txn ->
+                                    array.push(longYValue(i.toLong()), txn)
+                                    // writers never hold the text handle exclusively
+                                    text.toText(txn)
+                                }
+                            }
+                        }
+                        // readers are allowed to fail only due to contention
+                        retry(contended) { text.length() }
+                    }
+                } catch (e: Throwable) {
+                    errors.incrementAndGet()
+                }
+            }
+        }
+        start.countDown()
+        threads.forEach { it.join() }
+
+        assertEquals(0, errors.get())
+        assertEquals((threadCount / 2 * iterations).toUInt(), text.length())
+        assertEquals((threadCount / 2 * iterations).toUInt(), array.length())
+    }
+
+    @Test
+    fun TestSharedTransaction() {
+        val doc = YDoc(YDocOptions(1u))
+        val text = doc.getText("text")
+        val inserted = AtomicInteger()
+        val errors = AtomicInteger()
+
+        val txn = doc.transaction()
+        val threads = (0 until 4).map {
+            thread {
+                repeat(100) {
+                    try {
+                        text.insert(0u, "a", txn = txn)
+                        inserted.incrementAndGet()
+                    } catch (e: com.planerist.ykt.Exception.AnotherTx) {
+                        // another thread is using the same transaction
+                    } catch (e: Throwable) {
+                        errors.incrementAndGet()
+                    }
+                }
+            }
+        }
+        threads.forEach { it.join() }
+        txn.commit()
+
+        assertEquals(0, errors.get())
+        assertTrue(inserted.get() > 0)
+        assertEquals(inserted.get().toUInt(), text.length())
+
+        assertThrows<com.planerist.ykt.Exception.TxnCommitted> { text.insert(0u, "b", txn = txn) }
+        assertThrows<com.planerist.ykt.Exception.TxnCommitted> { txn.encodeUpdate() }
+    }
+
+    @Test
+    fun TestContendedTransaction() {
+        val doc = YDoc(YDocOptions(1u))
+        val text = doc.getText("text")
+
+        val txn = doc.transaction()
+        var error: Throwable? = null
+        thread {
+            try {
+                text.insert(0u, "hello")
+            } catch (e: Throwable) {
+                error = e
+            }
+        }.join()
+        assertTrue(error is com.planerist.ykt.Exception.AnotherTx)
+        assertThrows<com.planerist.ykt.Exception.AnotherTx> { doc.transaction() }
+        txn.commit()
+
+        text.insert(0u, "hello")
+        assertEquals("hello", text.toText())
+    }
+
+    @Test
+    fun TestReadTransaction() {
+        val doc = YDoc(YDocOptions(1u))
+        val text = doc.getText("text")
+        text.insert(0u, "hello")
+
+        val other = doc.readTransaction()
+        doc.readTransact { txn ->
+            assertTrue(txn.readOnly())
+            // many read-only transactions may be active at the same time
+            assertEquals(5u, text.length(txn))
+            assertEquals("hello", text.toText(other))
+            assertEquals(listOf(YDelta.YInsert(YValue.String("hello"), null)), text.toDelta(txn = txn))
+            // implicit reads don't conflict with active readers
+            assertEquals("hello", text.toText())
+
+            assertThrows<com.planerist.ykt.Exception.InvalidTransactionCtx> { text.insert(0u, "x", txn = txn) }
+            assertThrows<com.planerist.ykt.Exception.InvalidTransactionCtx> { txn.encodeUpdate() }
+            assertThrows<com.planerist.ykt.Exception.AnotherTx> { text.insert(0u, "x") }
+            assertThrows<com.planerist.ykt.Exception.AnotherTx> { doc.transaction() }
+        }
+        other.commit()
+
+        text.insert(5u, "!")
+        doc.transact {
+            assertThrows<com.planerist.ykt.Exception.AnotherRwTx> { doc.readTransaction() }
+        }
+        assertEquals("hello!", text.toText())
+    }
+}
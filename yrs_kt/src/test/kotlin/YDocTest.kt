@@ -2,7 +2,11 @@ import com.planerist.ykt.*
 import com.planerist.ykt.YDelta.YInsert
 import org.junit.jupiter.api.assertThrows
 import kotlin.test.Test
+import kotlin.test.assertContentEquals
 import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertNull
+import kotlin.test.assertTrue
 
 class YDocTest {
     @Test
@@ -24,6 +28,43 @@ class YDocTest {
         assertEquals(txt2.toText(), "hello")
     }
 
+    @Test
+    fun TestForkAt() {
+        val d1 = YDoc(YDocOptions(1u, gc = false))
+
+        val text = d1.getText("text")
+        text.insert(0u, "hello")
+
+        val prev = snapshot(d1)
+        text.delete(0u, 2u)
+        text.insert(3u, " world")
+
+        val fork = d1.forkAt(prev)
+        assertTrue(fork.readOnly())
+        assertFalse(d1.readOnly())
+        assertEquals("hello", fork.getText("text").toText())
+        assertEquals("llo world", text.toText())
+
+        assertThrows<com.planerist.ykt.Exception.ReadOnlyDoc> { fork.transaction() }
+        assertThrows<com.planerist.ykt.Exception.ReadOnlyDoc> { fork.getText("text").insert(0u, "x") }
+        assertThrows<com.planerist.ykt.Exception.ReadOnlyDoc> { applyUpdateV2(fork, encodeStateAsUpdateV2(d1)) }
+
+        // roots missing in a snapshot are not created in a fork
+        assertThrows<com.planerist.ykt.Exception.ReadOnlyDoc> { fork.getMap("missing").set("key", stringYValue("value")) }
+        assertThrows<com.planerist.ykt.Exception.ReadOnlyDoc> { fork.getArray("missing") }
+        assertEquals("{\"text\":\"hello\"}", fork.toJson())
+
+        // shared types remain read-only after their fork has been closed
+        val forked = fork.getText("text")
+        fork.close()
+        assertThrows<com.planerist.ykt.Exception.ReadOnlyDoc> { forked.insert(0u, "x") }
+        assertEquals("hello", forked.toText())
+
+        val d2 = YDoc(YDocOptions(2u))
+        d2.getText("text").insert(0u, "hello")
+        assertThrows<com.planerist.ykt.Exception.GcEnabled> { d2.forkAt(snapshot(d2)) }
+    }
+
     @Test
     fun TestStateAsUpdateDefault() {
         val d1 = YDoc(YDocOptions(1u, gc = false))
@@ -166,6 +207,18 @@ class YDocTest {
             YInsert(YValue.String(" – "), null),
         ))
         assertEquals(5u, text2.length())
+
+        // preliminary text counts UTF-16 code units, same as integrated one
+        val prelim = YText.newWithText(" – ")
+        assertEquals(3u, prelim.length())
+        prelim.insert(2u, "x", null)
+        prelim.delete(1u, 1u)
+        assertEquals(" x ", prelim.toText())
+        val emoji = YText.newWithText("😀")
+        assertEquals(2u, emoji.length())
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { emoji.insert(1u, "x", null) }
+        doc.getArray("array").push(YIn.Text(emoji))
+        assertEquals(2u, emoji.length())
     }
 
     @Test
@@ -181,16 +234,271 @@ class YDocTest {
         )
     }
 
+    @Test
+    fun TestToJson() {
+        val doc = YDoc(YDocOptions(1u))
+        val text = doc.getText("text")
+        text.insert(0u, "hello")
+        text.insert(5u, " world", "{\"bold\":true}")
+
+        val map = doc.getMap("map")
+        map.set("count", numberYValue(2.0))
+        map.set("data", YValue.Buffer(byteArrayOf(1, 2, 3)))
+        map.set("items", YIn.Array(YArray.newWithItems(listOf(YIn.Any(stringYValue("a"))))))
+
+        val fragment = doc.getXmlFragment("xml")
+        fragment.push(createXmlElement("p", mapOf("class" to stringYValue("title")), listOf(createXmlText("hi"))))
+
+        assertEquals(
+            """{"map":{"count":2,"data":"AQID","items":["a"]},""" +
+                    """"text":"hello world",""" +
+                    """"xml":{"children":[{"attributes":{"class":"title"},"children":["hi"],"nodeName":"p"}]}}""",
+            doc.toJson()
+        )
+        assertEquals(
+            """[{"insert":"hello"},{"attributes":{"bold":true},"insert":" world"}]""",
+            text.toJson(includeFormatting = true)
+        )
+        assertEquals("""{"count":2,"data":"AQID","items":["a"]}""", map.toJson())
+
+        assertThrows<com.planerist.ykt.Exception.InvalidPrelimOp> { YMap.newWithEntries(emptyMap()).toJson() }
+    }
+
+    @Test
+    fun TestTypedAttributes() {
+        val doc = YDoc()
+        val text = doc.getText("text")
+        text.insert(0u, "hello", mapOf("bold" to YValue.Bool(true)))
+        text.push(" world", mapOf("size" to YValue.BigInt(12)))
+        text.format(0u, 2u, mapOf("bold" to YValue.Null))
+        text.insertEmbed(1u, YIn.Any(YValue.String("img.png")), mapOf("inline" to YValue.Bool(true)))
+
+        assertEquals(
+            listOf(
+                YInsert(YValue.String("h"), null),
+                YInsert(YValue.String("img.png"), mapOf("inline" to YValue.Bool(true))),
+                YInsert(YValue.String("e"), null),
+                YInsert(YValue.String("llo"), mapOf("bold" to YValue.Bool(true))),
+                YInsert(YValue.String(" world"), mapOf("size" to YValue.BigInt(12))),
+            ), text.toDelta()
+        )
+
+        val prelim = YText.newWithText("hello")
+        assertThrows<com.planerist.ykt.Exception.InvalidPrelimOp> {
+            prelim.insert(0u, "x", mapOf("bold" to YValue.Bool(true)))
+        }
+        prelim.push("!", emptyMap())
+        assertEquals("hello!", prelim.toText())
+    }
+
+    @Test
+    fun TestYValueJson() {
+        val value = YValue.AttrMap(
+            mapOf(
+                "nested" to YValue.AttrMap(mapOf("list" to YValue.Array(listOf(YValue.Null, YValue.Bool(false))))),
+                "buffer" to YValue.Buffer(byteArrayOf(0, 1, -2, -1)),
+                "bigint" to YValue.BigInt(Long.MIN_VALUE),
+                "number" to YValue.Number(2.0),
+                "string" to YValue.String("a \"quoted\" text"),
+            )
+        )
+
+        val json = yvalueToJson(value)
+        assertEquals(
+            """{"bigint":-9223372036854775808,"buffer":{"${'$'}buffer":"AAH+/w=="},"nested":{"list":[null,false]},""" +
+                    """"number":2.0,"string":"a \"quoted\" text"}""",
+            json
+        )
+        // buffers are compared by reference, so compare JSON representations instead
+        assertEquals(json, yvalueToJson(yvalueFromJson(json)))
+
+        assertEquals(YValue.BigInt(2), yvalueFromJson("2"))
+        assertEquals(YValue.Number(2.0), yvalueFromJson("2.0"))
+        assertEquals(YValue.String("x"), yvalueFromJson("\"x\""))
+        assertContentEquals(byteArrayOf(104, 105), (yvalueFromJson("{\"${'$'}buffer\":\"aGk=\"}") as YValue.Buffer).v1)
+        assertThrows<com.planerist.ykt.Exception.Decode> { yvalueFromJson("{") }
+
+        // map keys starting with `$` are escaped, so they are never confused with buffers
+        val map = YValue.AttrMap(mapOf("${'$'}buffer" to YValue.String("aGk=")))
+        assertEquals("{\"${'$'}${'$'}buffer\":\"aGk=\"}", yvalueToJson(map))
+        assertEquals(map, yvalueFromJson(yvalueToJson(map)))
+    }
+
     @Test
     fun TestInvalidOpTest() {
         val doc = YDoc()
         val text = doc.getText("text")
         text.insert(0u, "hello")
 
-        assertThrows<InternalException> { text.delete(4u, 3u) }
-        assertEquals("hell", text.toText())
+        val error = assertThrows<com.planerist.ykt.Exception.OutOfBounds> { text.delete(4u, 3u) }
+        assertEquals(7u, error.index)
+        assertEquals(5u, error.len)
+        // failed operation leaves the text untouched
+        assertEquals("hello", text.toText())
 
         text.insert(5u, " world")
-        assertEquals("hell world", text.toText())
+        assertEquals("hello world", text.toText())
+
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { text.insert(12u, "!") }
+
+        val array = doc.getArray("array")
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { array.insert(1u, longYValue(1)) }
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { array.moveTo(0u, 0u) }
+
+        val fragment = doc.getXmlFragment("xml")
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { fragment.delete(0u, 1u) }
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> {
+            fragment.insert(1u, YXmlChild.Text(YXmlText("text")))
+        }
+    }
+
+    @Test
+    fun TestInsertEmbed() {
+        val doc = YDoc()
+        val text = doc.getText("text")
+        text.insert(0u, "ab")
+
+        val image = YMap.newWithEntries(mapOf("src" to YIn.Any(YValue.String("img.png"))))
+        text.insertEmbed(1u, YIn.Map(image), "{\"inline\":true}")
+
+        val delta = text.toDelta()
+        assertEquals(3, delta.size)
+        assertEquals(YInsert(YValue.String("a"), null), delta[0])
+        assertEquals(YInsert(YValue.String("b"), null), delta[2])
+
+        val embed = delta[1] as YDelta.YInsertShared
+        assertEquals(mapOf("inline" to YValue.Bool(true)), embed.v2)
+        assertEquals(YOut.Any(YValue.String("img.png")), (embed.v1 as YOut.Map).v1.get("src"))
+    }
+
+    @Test
+    fun TestObserve() {
+        val doc = YDoc()
+        val text = doc.getText("text")
+
+        val events = mutableListOf<YTextEvent>()
+        val subscription = text.observe(object : YTextObserver {
+            override fun onChange(event: YTextEvent) {
+                events.add(event)
+            }
+        })
+
+        text.insert(0u, "hello")
+        doc.transact("local") { txn ->
+            text.insert(5u, " world", "{\"bold\":true}", txn)
+            text.delete(0u, 1u, txn)
+        }
+
+        assertEquals(2, events.size)
+        assertEquals(listOf(YInsert(YValue.String("hello"), null)), events[0].delta)
+        assertNull(events[0].origin)
+        assertEquals(
+            listOf(
+                YDelta.YDelete(1u),
+                YDelta.YRetain(4u, null),
+                YInsert(YValue.String(" world"), mapOf("bold" to YValue.Bool(true))),
+            ), events[1].delta
+        )
+        assertContentEquals("local".encodeToByteArray(), events[1].origin)
+        assertEquals("ello world", events[1].target.toText())
+
+        // remote changes are observed as well
+        val remote = YDoc()
+        applyUpdateV2(remote, encodeStateAsUpdateV2(doc))
+        remote.getText("text").insert(0u, "H")
+        applyUpdateV2(doc, encodeStateAsUpdateV2(remote))
+        assertEquals(listOf(YInsert(YValue.String("H"), null)), events[2].delta)
+
+        subscription.unsubscribe()
+        text.insert(0u, "!")
+        assertEquals(3, events.size)
+    }
+
+    @Test
+    fun TestObserveUpdate() {
+        val doc = YDoc(YDocOptions(1u))
+        val remote = YDoc(YDocOptions(2u))
+
+        val origins = mutableListOf<ByteArray?>()
+        val updates = doc.observeUpdateV2(object : YUpdateObserver {
+            override fun onUpdate(event: YUpdateEvent) {
+                origins.add(event.origin)
+                applyUpdateV2(remote, event.update)
+            }
+        })
+
+        val transactions = mutableListOf<YTransactionEvent>()
+        val afterTransaction = doc.observeAfterTransaction(object : YTransactionObserver {
+            override fun onTransaction(event: YTransactionEvent) {
+                transactions.add(event)
+            }
+        })
+
+        val text = doc.getText("text")
+        text.insert(0u, "hello")
+        doc.transact("local") { txn ->
+            text.delete(1u, 2u, txn)
+        }
+
+        assertEquals("hlo", remote.getText("text").toText())
+        assertEquals(2, origins.size)
+        assertContentEquals("local".encodeToByteArray(), origins[1])
+
+        val last = transactions.last()
+        assertEquals(mapOf(1uL to 5u), last.beforeState)
+        assertEquals(mapOf(1uL to 5u), last.afterState)
+        assertEquals(mapOf(1uL to listOf(YIdRange(1u, 3u))), last.deleteSet)
+
+        updates.close()
+        afterTransaction.close()
+        text.insert(0u, "!")
+        assertEquals("hlo", remote.getText("text").toText())
+    }
+
+    @Test
+    fun TestSubdocs() {
+        val doc = YDoc(YDocOptions(1u))
+        val events = mutableListOf<YSubdocsEvent>()
+        val subscription = doc.observeSubdocs(object : YSubdocsObserver {
+            override fun onChange(event: YSubdocsEvent) {
+                events.add(event)
+            }
+        })
+
+        val page = YDoc(YDocOptions(guid = "page-1"))
+        assertTrue(page.prelim())
+        doc.getMap("pages").set("page-1", YIn.Doc(page))
+        assertFalse(page.prelim())
+        assertEquals(doc.guid(), page.parentDoc()?.guid())
+        assertEquals(listOf("page-1"), doc.subdocGuids())
+        assertEquals(listOf("page-1"), doc.subdocs().map { it.guid() })
+
+        // locally created sub-documents are loaded right away
+        assertEquals(listOf("page-1"), events[0].added.map { it.guid() })
+        assertEquals(listOf("page-1"), events[0].loaded.map { it.guid() })
+
+        // remote peers need to load sub-documents explicitly
+        val remote = YDoc(YDocOptions(2u))
+        val remoteEvents = mutableListOf<YSubdocsEvent>()
+        remote.observeSubdocs(object : YSubdocsObserver {
+            override fun onChange(event: YSubdocsEvent) {
+                remoteEvents.add(event)
+            }
+        }).use {
+            applyUpdateV2(remote, encodeStateAsUpdateV2(doc))
+            val remotePage = (remote.getMap("pages").get("page-1") as YOut.Doc).v1
+            assertFalse(remotePage.shouldLoad())
+            assertEquals(listOf("page-1"), remoteEvents[0].added.map { it.guid() })
+            assertEquals(emptyList(), remoteEvents[0].loaded)
+
+            remotePage.load()
+            assertTrue(remotePage.shouldLoad())
+            assertEquals(listOf("page-1"), remoteEvents[1].loaded.map { it.guid() })
+        }
+
+        doc.getMap("pages").remove("page-1")
+        assertEquals(listOf("page-1"), events.last().removed.map { it.guid() })
+        assertEquals(emptyList(), doc.subdocGuids())
+        subscription.close()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,134 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertNull
+import kotlin.test.assertTrue
+
+class YMapTest {
+    @Test
+    fun TestSetGet() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val map = doc.getMap("map")
+
+        map.set("key1", stringYValue("value1"))
+        map.set("key2", longYValue(42))
+        map.set("key3", booleanYValue(false))
+
+        assertEquals(3u, map.length())
+        assertEquals(YOut.Any(stringYValue("value1")), map.get("key1"))
+        assertEquals(YOut.Any(longYValue(42)), map.get("key2"))
+        assertEquals(YOut.Any(booleanYValue(false)), map.get("key3"))
+        assertNull(map.get("missing"))
+    }
+
+    @Test
+    fun TestRemoveAndClear() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val map = doc.getMap("map")
+
+        doc.transact { txn ->
+            map.set("key1", stringYValue("value1"), txn)
+            map.set("key2", numberYValue(1.5), txn)
+            map.remove("key1", txn)
+        }
+
+        assertFalse(map.containsKey("key1"))
+        assertTrue(map.containsKey("key2"))
+        assertEquals(listOf("key2"), map.keys())
+        assertEquals(listOf(YOut.Any(numberYValue(1.5))), map.values())
+
+        map.clear()
+        assertEquals(0u, map.length())
+        assertEquals(emptyMap(), map.entries())
+    }
+
+    @Test
+    fun TestEntries() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val map = doc.getMap("map")
+
+        doc.transact { txn ->
+            map.set("a", stringYValue("x"), txn)
+            map.set("b", YValue.Array(listOf(longYValue(1), longYValue(2))), txn)
+            map.set("c", YValue.AttrMap(mapOf("nested" to booleanYValue(true))), txn)
+        }
+
+        assertEquals(
+            mapOf(
+                "a" to YOut.Any(stringYValue("x")),
+                "b" to YOut.Any(YValue.Array(listOf(longYValue(1), longYValue(2)))),
+                "c" to YOut.Any(YValue.AttrMap(mapOf("nested" to booleanYValue(true))))
+            ), map.entries()
+        )
+    }
+
+    @Test
+    fun TestPrelim() {
+        val map = YMap.newWithEntries(mapOf("key1" to YIn.Any(stringYValue("value1"))))
+        assertTrue(map.prelim())
+
+        map.set("key2", longYValue(2))
+        map.remove("key1")
+
+        assertEquals(1u, map.length())
+        assertEquals(mapOf("key2" to YOut.Any(longYValue(2))), map.entries())
+    }
+
+    @Test
+    fun TestSync() {
+        val d1 = YDoc(YDocOptions(1u, gc = false))
+        val m1 = d1.getMap("map")
+        m1.set("key1", stringYValue("value1"))
+        m1.set("key2", longYValue(42))
+
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, encodeStateAsUpdateV2(d1))
+
+        val m2 = d2.getMap("map")
+        assertEquals(m1.entries(), m2.entries())
+
+        m2.set("key1", stringYValue("value2"))
+        applyUpdateV2(d1, encodeStateAsUpdateV2(d2))
+
+        assertEquals(YOut.Any(stringYValue("value2")), m1.get("key1"))
+    }
+
+    @Test
+    fun TestNested() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val root = doc.getMap("root")
+
+        val text = YText.newWithText("hello")
+        val list = YArray.newWithItems(listOf(YIn.Any(longYValue(1)), YIn.Text(text)))
+        val child = YMap.newWithEntries(mapOf("list" to YIn.Array(list)))
+
+        // the whole tree is integrated at once
+        root.set("child", YIn.Map(child))
+        assertFalse(child.prelim())
+        assertFalse(list.prelim())
+        assertFalse(text.prelim())
+
+        text.insert(5u, " world")
+
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, encodeStateAsUpdateV2(doc))
+
+        val child2 = (d2.getMap("root").get("child") as YOut.Map).v1
+        val list2 = (child2.get("list") as YOut.Array).v1
+        assertEquals(YOut.Any(longYValue(1)), list2.get(0u))
+        assertEquals("hello world", (list2.get(1u) as YOut.Text).v1.toText())
+    }
+
+    @Test
+    fun TestNestedNotPrelim() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val root = doc.getMap("root")
+
+        val child = YMap.newWithEntries()
+        root.set("a", YIn.Map(child))
+
+        assertThrows<com.planerist.ykt.Exception.NotPrelim> { root.set("b", YIn.Map(child)) }
+    }
+}
@@ -0,0 +1,115 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertTrue
+
+class YPersistenceTest {
+    /** Storage implemented on the Kotlin side, which can be told to fail on writes. */
+    class FlakyStorage : YStorage {
+        val docs = HashMap<String, MutableList<ByteArray>>()
+        var failing = false
+
+        override fun putUpdate(docId: String, update: ByteArray) {
+            if (failing) throw com.planerist.ykt.Exception.Custom("storage unavailable")
+            docs.getOrPut(docId) { mutableListOf() }.add(update)
+        }
+
+        override fun getUpdates(docId: String): List<ByteArray> = docs[docId] ?: emptyList()
+
+        override fun replaceWithSnapshot(docId: String, snapshot: ByteArray) {
+            if (failing) throw com.planerist.ykt.Exception.Custom("storage unavailable")
+            docs[docId] = mutableListOf(snapshot)
+        }
+
+        override fun deleteDoc(docId: String) {
+            docs.remove(docId)
+        }
+    }
+
+    @Test
+    fun TestLoadAndWrite() {
+        val storage = memoryStorage()
+        val doc = YDoc(YDocOptions(1u))
+        val persistence = YPersistence(storage, doc, "doc")
+        val text = doc.getText("text")
+        text.insert(0u, "hello")
+        text.insert(5u, " world")
+        persistence.close()
+        text.insert(0u, "ignored ")
+
+        assertEquals(2, storage.getUpdates("doc").size)
+
+        val restored = YDoc(YDocOptions(2u))
+        YPersistence(storage, restored, "doc")
+        assertEquals("hello world", restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestExistingContent() {
+        val storage = memoryStorage()
+        val doc = YDoc(YDocOptions(1u))
+        doc.getText("text").insert(0u, "hello")
+        val persistence = YPersistence(storage, doc)
+        assertEquals(doc.guid(), persistence.docId())
+
+        val restored = YDoc(YDocOptions(2u))
+        YPersistence(storage, restored, doc.guid())
+        assertEquals("hello", restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestCompaction() {
+        val storage = memoryStorage()
+        val doc = YDoc(YDocOptions(1u))
+        val persistence = YPersistence(storage, doc, "doc", 3u)
+        val text = doc.getText("text")
+        for (i in 0 until 4) {
+            text.insert(0u, i.toString())
+        }
+        assertEquals(2, storage.getUpdates("doc").size)
+
+        persistence.compact()
+        assertEquals(1, storage.getUpdates("doc").size)
+
+        val restored = YDoc(YDocOptions(2u))
+        YPersistence(storage, restored, "doc")
+        assertEquals("3210", restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestWriteFailure() {
+        val storage = FlakyStorage()
+        val doc = YDoc(YDocOptions(1u))
+        val persistence = YPersistence(storage, doc, "doc")
+        val text = doc.getText("text")
+
+        storage.failing = true
+        text.insert(0u, "hello")
+        assertFalse(persistence.inSync())
+
+        storage.failing = false
+        text.insert(5u, " world")
+        assertTrue(persistence.inSync())
+
+        val restored = YDoc(YDocOptions(2u))
+        YPersistence(storage, restored, "doc")
+        assertEquals("hello world", restored.getText("text").toText())
+
+        storage.failing = true
+        assertThrows<com.planerist.ykt.Exception.Custom> { YPersistence(storage, doc, "other") }
+    }
+
+    @Test
+    fun TestDelete() {
+        val storage = memoryStorage()
+        val doc = YDoc(YDocOptions(1u))
+        val persistence = YPersistence(storage, doc, "doc")
+        doc.getText("text").insert(0u, "hello")
+        persistence.delete()
+        doc.getText("text").insert(0u, "ignored")
+
+        assertTrue(storage.getUpdates("doc").isEmpty())
+    }
+}
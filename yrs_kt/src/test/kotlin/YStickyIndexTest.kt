@@ -0,0 +1,77 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertContentEquals
+import kotlin.test.assertEquals
+import kotlin.test.assertNull
+
+class YStickyIndexTest {
+    @Test
+    fun TestConcurrentEdits() {
+        val d1 = YDoc(YDocOptions(1u))
+        val t1 = d1.getText("text")
+        t1.insert(0u, "hello world")
+
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, encodeStateAsUpdateV2(d1))
+        val t2 = d2.getText("text")
+
+        // selection over "world"
+        val start = t1.stickyIndex(6u, YAssoc.AFTER)!!
+        val end = t1.stickyIndex(11u, YAssoc.BEFORE)!!
+        assertNull(t1.stickyIndex(12u, YAssoc.AFTER))
+
+        t2.insert(0u, ">> ")
+        t2.insert(14u, "!")
+        applyUpdateV2(d1, encodeStateAsUpdateV2(d2))
+
+        assertEquals(">> hello world!", t1.toText())
+        assertEquals(9u, start.getOffset(d1))
+        assertEquals(14u, end.getOffset(d1))
+    }
+
+    @Test
+    fun TestEncoding() {
+        val doc = YDoc(YDocOptions(1u))
+        val text = doc.getText("text")
+        text.insert(0u, "hello")
+
+        val index = text.stickyIndex(2u, YAssoc.AFTER)!!
+        // same as Yjs encodeRelativePosition/relativePositionToJSON
+        assertContentEquals(byteArrayOf(0, 1, 2, 0), index.encode())
+        assertEquals("{\"item\":{\"client\":1,\"clock\":2},\"assoc\":0}", index.toJson())
+
+        val tail = text.stickyIndex(5u, YAssoc.AFTER)!!
+        assertEquals("{\"tname\":\"text\",\"assoc\":0}", tail.toJson())
+
+        text.insert(0u, "ab")
+        assertEquals(4u, YStickyIndex.decode(index.encode()).getOffset(doc))
+        assertEquals(4u, YStickyIndex.fromJson(index.toJson()).getOffset(doc))
+        assertEquals(7u, YStickyIndex.fromJson(tail.toJson()).getOffset(doc))
+        assertEquals(YAssoc.AFTER, YStickyIndex.decode(index.encode()).assoc())
+
+        assertThrows<com.planerist.ykt.Exception.Decode> { YStickyIndex.fromJson("{\"assoc\":0}") }
+    }
+
+    @Test
+    fun TestXmlFragment() {
+        val doc = YDoc(YDocOptions(1u))
+        val xml = doc.getXmlFragment("xml")
+        doc.transact { txn ->
+            xml.push(createXmlText("a"), txn)
+            xml.push(createXmlText("b"), txn)
+        }
+
+        val index = xml.stickyIndex(1u, YAssoc.AFTER)!!
+        xml.insert(0u, createXmlText("c"))
+        assertEquals(2u, index.getOffset(doc))
+
+        doc.transact { txn ->
+            assertEquals(2u, index.getOffset(doc, txn))
+        }
+
+        assertThrows<com.planerist.ykt.Exception.InvalidPrelimOp> {
+            YText.newWithText("prelim").stickyIndex(0u, YAssoc.AFTER)
+        }
+    }
+}
@@ -0,0 +1,124 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertContentEquals
+import kotlin.test.assertEquals
+import kotlin.test.assertTrue
+
+class YSyncProtocolTest {
+    @Test
+    fun TestSync() {
+        val d1 = YDoc(YDocOptions(1u))
+        d1.getText("text").insert(0u, "hello")
+        val p1 = YSyncProtocol(d1)
+        p1.getAwareness().setLocalState("{\"user\":\"alice\"}")
+
+        val d2 = YDoc(YDocOptions(2u))
+        val p2 = YSyncProtocol(d2)
+
+        // d2 connects to d1: sync step 1 is answered with sync step 2
+        val replies = p1.handle(p2.start(), "network".encodeToByteArray())
+        assertEquals(1, replies.size)
+        assertTrue(decodeSyncMessages(replies[0]).single() is YSyncMessage.SyncStep2)
+
+        for (reply in replies) {
+            assertTrue(p2.handle(reply).isEmpty())
+        }
+        assertEquals("hello", d2.getText("text").toText())
+
+        // d1 connects back and shares its awareness state
+        for (reply in p2.handle(p1.start())) {
+            p1.handle(reply)
+        }
+        assertEquals(mapOf(1uL to "{\"user\":\"alice\"}"), p2.getAwareness().states())
+    }
+
+    @Test
+    fun TestUpdates() {
+        val d1 = YDoc(YDocOptions(1u))
+        val d2 = YDoc(YDocOptions(2u))
+        val p2 = YSyncProtocol(d2)
+
+        d1.observeUpdateV1(object : YUpdateObserver {
+            override fun onUpdate(event: YUpdateEvent) {
+                val message = encodeSyncMessage(YSyncMessage.Update(event.update))
+                assertTrue(p2.handle(message).isEmpty())
+            }
+        }).use {
+            d1.getText("text").insert(0u, "hello")
+        }
+
+        assertEquals("hello", d2.getText("text").toText())
+    }
+
+    @Test
+    fun TestCodec() {
+        val payload = encodeSyncMessages(
+            listOf(
+                YSyncMessage.QueryAwareness,
+                YSyncMessage.Auth("denied"),
+                YSyncMessage.SyncStep2(byteArrayOf(0, 0))
+            )
+        )
+
+        // message tags are the same as in y-websocket
+        assertEquals(3.toByte(), payload[0])
+        assertEquals(2.toByte(), payload[1])
+
+        val messages = decodeSyncMessages(payload)
+        assertEquals(3, messages.size)
+        assertEquals(YSyncMessage.QueryAwareness, messages[0])
+        assertEquals(YSyncMessage.Auth("denied"), messages[1])
+        assertContentEquals(byteArrayOf(0, 0), (messages[2] as YSyncMessage.SyncStep2).update)
+
+        assertThrows<com.planerist.ykt.Exception.Decode> { decodeSyncMessages(byteArrayOf(0, 9)) }
+        val error = assertThrows<com.planerist.ykt.Exception.PermissionDenied> {
+            YSyncProtocol(YDoc(YDocOptions(3u))).handle(encodeSyncMessage(YSyncMessage.Auth("denied")))
+        }
+        assertEquals("denied", error.reason)
+    }
+
+    @Test
+    fun TestWireFormat() {
+        // fixtures below are encoded the same way as by y-protocols, with y-websocket message tags:
+        // an update produced by Yjs for a doc with client ID 1 and "hello" inserted into "text"
+        val update = byteArrayOf(1, 1, 1, 0, 4, 1, 4) + "text".encodeToByteArray() +
+                byteArrayOf(5) + "hello".encodeToByteArray() + byteArrayOf(0)
+
+        val d1 = YDoc(YDocOptions(1u))
+        d1.getText("text").insert(0u, "hello")
+        val p1 = YSyncProtocol(d1)
+
+        // sync step 1 with a state vector {1: 5}, followed by an empty awareness update
+        assertContentEquals(byteArrayOf(0, 0, 3, 1, 1, 5, 1, 1, 0), p1.start())
+
+        // sync step 1 with an empty state vector is answered with sync step 2 containing the whole doc
+        val replies = p1.handle(byteArrayOf(0, 0, 1, 0))
+        assertContentEquals(byteArrayOf(0, 1, update.size.toByte()) + update, replies.single())
+
+        val d2 = YDoc(YDocOptions(2u))
+        val p2 = YSyncProtocol(d2)
+        assertTrue(p2.handle(byteArrayOf(0, 1, update.size.toByte()) + update).isEmpty())
+        assertEquals("hello", d2.getText("text").toText())
+
+        // awareness update of client 42 at clock 1
+        val state = "{\"user\":\"alice\"}".encodeToByteArray()
+        val awareness = byteArrayOf(1, 42, 1, state.size.toByte()) + state
+        assertTrue(p2.handle(byteArrayOf(1, awareness.size.toByte()) + awareness).isEmpty())
+        assertEquals(mapOf(42uL to "{\"user\":\"alice\"}"), p2.getAwareness().states())
+
+        // awareness query is answered with states of all known peers
+        val query = p2.handle(byteArrayOf(3))
+        assertContentEquals(byteArrayOf(1, awareness.size.toByte()) + awareness, query.single())
+
+        // messages with custom tags are skipped without aborting the rest of a payload
+        val custom = p1.handle(byteArrayOf(5, 2, 7, 7, 0, 0, 1, 0))
+        assertContentEquals(byteArrayOf(0, 1, update.size.toByte()) + update, custom.single())
+
+        // permission denied message of y-protocols auth
+        val error = assertThrows<com.planerist.ykt.Exception.PermissionDenied> {
+            p2.handle(byteArrayOf(2, 0, 6) + "denied".encodeToByteArray())
+        }
+        assertEquals("denied", error.reason)
+    }
+}
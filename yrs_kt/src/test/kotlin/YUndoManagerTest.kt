@@ -0,0 +1,93 @@
+import com.planerist.ykt.*
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertNull
+import kotlin.test.assertTrue
+
+class YUndoManagerTest {
+    @Test
+    fun TestUndoRedo() {
+        val doc = YDoc()
+        val text = doc.getText("text")
+        val map = doc.getMap("map")
+        val mgr = YUndoManager(doc, listOf(YOut.Text(text), YOut.Map(map)))
+
+        text.insert(0u, "hello")
+        mgr.stopCapturing()
+        map.set("key", stringYValue("value"))
+
+        assertTrue(mgr.canUndo())
+        assertTrue(mgr.undo())
+        assertNull(map.get("key"))
+        assertEquals("hello", text.toText())
+
+        assertTrue(mgr.undo())
+        assertEquals("", text.toText())
+        assertFalse(mgr.canUndo())
+
+        assertTrue(mgr.redo())
+        assertEquals("hello", text.toText())
+
+        mgr.clear()
+        assertFalse(mgr.canUndo())
+        assertFalse(mgr.canRedo())
+    }
+
+    @Test
+    fun TestCaptureTimeout() {
+        val doc = YDoc()
+        val text = doc.getText("text")
+        val mgr = YUndoManager(doc, listOf(YOut.Text(text)), YUndoManagerOptions(captureTimeoutMillis = 60_000u))
+
+        // changes made within capture timeout are merged into a single undo step
+        text.insert(0u, "a")
+        text.insert(1u, "b")
+        mgr.undo()
+        assertEquals("", text.toText())
+    }
+
+    @Test
+    fun TestTrackedOrigins() {
+        val doc = YDoc()
+        val xml = doc.getXmlFragment("xml")
+        val mgr = YUndoManager(
+            doc,
+            listOf(YOut.XmlFragment(xml)),
+            YUndoManagerOptions(trackedOrigins = listOf("local".encodeToByteArray()))
+        )
+
+        doc.transact("remote") { txn -> xml.push(createXmlText("remote"), txn) }
+        assertFalse(mgr.canUndo())
+
+        doc.transact("local") { txn -> xml.push(createXmlText("local"), txn) }
+        assertTrue(mgr.undo())
+        assertEquals("remote", xml.toText())
+    }
+
+    @Test
+    fun TestCursorMeta() {
+        val doc = YDoc()
+        val text = doc.getText("text")
+        val mgr = YUndoManager(doc, listOf(YOut.Text(text)))
+
+        var cursor = 0L
+        mgr.observeItemAdded(object : YUndoObserver {
+            override fun onChange(event: YUndoEvent): Map<String, YValue>? =
+                mapOf("cursor" to longYValue(cursor))
+        }).use {
+            val restored = mutableListOf<YValue?>()
+            mgr.observeItemPopped(object : YUndoObserver {
+                override fun onChange(event: YUndoEvent): Map<String, YValue>? {
+                    restored.add(event.meta["cursor"])
+                    return null
+                }
+            }).use {
+                cursor = 3
+                text.insert(0u, "abc")
+                mgr.undo()
+                assertEquals(listOf<YValue?>(longYValue(3)), restored)
+            }
+        }
+    }
+}
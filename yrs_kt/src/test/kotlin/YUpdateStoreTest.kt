@@ -0,0 +1,123 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import java.io.File
+import kotlin.io.path.createTempDirectory
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertNotNull
+import kotlin.test.assertTrue
+
+class YUpdateStoreTest {
+    private fun tempLog(): File = File(createTempDirectory("ykt").toFile(), "doc.ylog")
+
+    @Test
+    fun TestAppendAndLoad() {
+        val file = tempLog()
+        val store = YUpdateStore(file.path)
+
+        val doc = YDoc(YDocOptions(1u))
+        store.load(doc)
+        val subscription = store.observe(doc)
+        val text = doc.getText("text")
+        text.insert(0u, "hello")
+        text.insert(5u, " world")
+        subscription.unsubscribe()
+        text.insert(0u, "ignored ")
+
+        assertEquals(2u, store.records())
+
+        val restored = YDoc(YDocOptions(2u))
+        YUpdateStore(file.path).load(restored)
+        assertEquals("hello world", restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestTornTail() {
+        val file = tempLog()
+        val doc = YDoc(YDocOptions(1u))
+        doc.getText("text").insert(0u, "hello")
+        YUpdateStore(file.path).append(encodeStateAsUpdateV2(doc))
+        val size = file.length()
+
+        // simulate a record which has not been completely written
+        file.appendBytes(byteArrayOf(100, 0, 0, 0, 1, 2, 3, 4, 5, 6))
+
+        val store = YUpdateStore(file.path)
+        assertEquals(size, file.length())
+        assertEquals(1u, store.records())
+
+        val restored = YDoc(YDocOptions(2u))
+        store.load(restored)
+        assertEquals("hello", restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestCompaction() {
+        val file = tempLog()
+        val store = YUpdateStore(file.path, compactionThreshold = 512u)
+
+        val doc = YDoc(YDocOptions(1u))
+        val subscription = store.observe(doc)
+        val text = doc.getText("text")
+        repeat(100) { text.push("$it,") }
+        subscription.unsubscribe()
+
+        assertTrue(store.records() < 100u)
+        assertEquals(file.length().toULong(), store.size())
+
+        store.compact()
+        assertEquals(1u, store.records())
+
+        val restored = YDoc(YDocOptions(2u))
+        YUpdateStore(file.path).load(restored)
+        assertEquals(text.toText(), restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestCompactionFailure() {
+        val file = tempLog()
+        val store = YUpdateStore(file.path, compactionThreshold = 512u)
+        // a directory in place of a temporary file makes every compaction fail
+        File(file.path + ".tmp").mkdir()
+
+        val doc = YDoc(YDocOptions(1u))
+        val subscription = store.observe(doc)
+        val text = doc.getText("text")
+        repeat(100) { text.push("$it,") }
+        subscription.unsubscribe()
+
+        // updates are still written, even though they could not have been compacted
+        assertEquals(100u, store.records())
+        assertTrue(store.inSync())
+        assertNotNull(store.lastError())
+
+        val restored = YDoc(YDocOptions(2u))
+        YUpdateStore(file.path).load(restored)
+        assertEquals(text.toText(), restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestTornHeader() {
+        val file = tempLog()
+        file.writeText("YRS")
+
+        // header which has not been completely written is treated as an empty log
+        val store = YUpdateStore(file.path)
+        assertEquals(0u, store.records())
+        assertEquals("YRSLOG01", file.readText())
+
+        val doc = YDoc(YDocOptions(1u))
+        doc.getText("text").insert(0u, "hello")
+        store.append(encodeStateAsUpdateV2(doc))
+        val restored = YDoc(YDocOptions(2u))
+        YUpdateStore(file.path).load(restored)
+        assertEquals("hello", restored.getText("text").toText())
+    }
+
+    @Test
+    fun TestInvalidFile() {
+        val file = tempLog()
+        file.writeText("not an update log")
+        assertThrows<com.planerist.ykt.Exception.InvalidData> { YUpdateStore(file.path) }
+    }
+}
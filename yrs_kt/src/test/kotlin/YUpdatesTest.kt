@@ -0,0 +1,146 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertContentEquals
+import kotlin.test.assertEquals
+import kotlin.test.assertNull
+
+class YUpdatesTest {
+    private fun collectUpdates(doc: YDoc, v2: Boolean, block: () -> Unit): List<ByteArray> {
+        val updates = mutableListOf<ByteArray>()
+        val observer = object : YUpdateObserver {
+            override fun onUpdate(event: YUpdateEvent) {
+                updates.add(event.update)
+            }
+        }
+        val subscription = if (v2) doc.observeUpdateV2(observer) else doc.observeUpdateV1(observer)
+        subscription.use { block() }
+        return updates
+    }
+
+    @Test
+    fun TestMergeUpdatesV1() {
+        val d1 = YDoc(YDocOptions(1u))
+        val text = d1.getText("text")
+        val updates = collectUpdates(d1, v2 = false) {
+            text.insert(0u, "hello")
+            text.insert(5u, " world")
+            text.delete(0u, 1u)
+        }
+        assertEquals(3, updates.size)
+
+        val merged = mergeUpdatesV1(updates)
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdate(d2, merged)
+        assertEquals(text.toText(), d2.getText("text").toText())
+        assertContentEquals(encodeStateVector(d1), encodeStateVector(d2))
+        assertContentEquals(encodeStateVector(d1), encodeStateVectorFromUpdateV1(merged))
+
+        assertThrows<com.planerist.ykt.Exception.Decode> { mergeUpdatesV1(listOf(byteArrayOf(1, 2, 3))) }
+    }
+
+    @Test
+    fun TestMergeUpdatesV2() {
+        val d1 = YDoc(YDocOptions(1u))
+        val array = d1.getArray("array")
+        val updates = collectUpdates(d1, v2 = true) {
+            array.push(longYValue(1))
+            array.push(stringYValue("a"))
+            array.delete(0u, 1u)
+        }
+
+        val merged = mergeUpdatesV2(updates)
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, merged)
+        assertEquals(array.toList(), d2.getArray("array").toList())
+        assertContentEquals(encodeStateVector2(d1), encodeStateVectorFromUpdateV2(merged))
+    }
+
+    @Test
+    fun TestDiffUpdate() {
+        val d1 = YDoc(YDocOptions(1u))
+        val text = d1.getText("text")
+        text.insert(0u, "hello")
+
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdate(d2, encodeStateAsUpdate(d1))
+        val d3 = YDoc(YDocOptions(3u))
+        applyUpdate(d3, encodeStateAsUpdate(d1))
+
+        text.insert(5u, " world")
+
+        // diff contains only changes that the other peer has not seen yet
+        val sv1 = encodeStateVector(d2)
+        val diff = diffUpdateV1(encodeStateAsUpdate(d1), sv1)
+        assertContentEquals(encodeStateAsUpdate(d1, decodeStateVector(sv1)), diff)
+        applyUpdate(d2, diff)
+        assertEquals("hello world", d2.getText("text").toText())
+
+        val sv2 = encodeStateVector2(d3)
+        val diff2 = diffUpdateV2(encodeStateAsUpdateV2(d1), sv2)
+        assertContentEquals(encodeStateAsUpdateV2(d1, decodeStateVector2(sv2)), diff2)
+        applyUpdateV2(d3, diff2)
+        assertEquals("hello world", d3.getText("text").toText())
+    }
+
+    @Test
+    fun TestConvertUpdate() {
+        val d1 = YDoc(YDocOptions(1u))
+        d1.getMap("map").set("key", stringYValue("value"))
+        d1.getText("text").insert(0u, "hello")
+
+        val v1 = encodeStateAsUpdate(d1)
+        val v2 = convertUpdateV1ToV2(v1)
+
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, v2)
+        assertEquals(d1.getMap("map").entries(), d2.getMap("map").entries())
+        assertEquals("hello", d2.getText("text").toText())
+
+        val d3 = YDoc(YDocOptions(3u))
+        applyUpdate(d3, convertUpdateV2ToV1(v2))
+        assertContentEquals(encodeStateVector(d1), encodeStateVector(d3))
+
+        assertThrows<com.planerist.ykt.Exception.Decode> { convertUpdateV1ToV2(byteArrayOf(9, 9, 9)) }
+    }
+
+    @Test
+    fun TestDecodeUpdate() {
+        val d1 = YDoc(YDocOptions(1u, gc = false))
+        val text = d1.getText("text")
+        val map = d1.getMap("map")
+        d1.transact { txn ->
+            text.insert(0u, "hello", txn = txn)
+            map.set("key", YIn.Map(YMap.newWithEntries(emptyMap())), txn)
+        }
+        text.delete(1u, 2u)
+
+        val decoded = decodeUpdateV1(encodeStateAsUpdate(d1))
+        assertEquals(decoded, decodeUpdateV2(encodeStateAsUpdateV2(d1)))
+
+        val blocks = decoded.blocks[1uL]!!
+        assertEquals(listOf(0u, 1u, 3u, 5u), blocks.map { it.id.clock })
+        assertEquals(6u, blocks.sumOf { it.length })
+
+        val first = blocks[0]
+        assertEquals(YBlockKind.ITEM, first.kind)
+        assertEquals(YBlockParent.Named("text"), first.parent)
+        assertEquals(YContentKind.STRING, first.contentKind)
+        assertEquals("'h'", first.content)
+        // blocks following their origin don't repeat the parent
+        assertEquals(YId(1uL, 0u), blocks[1].origin)
+        assertNull(blocks[1].parent)
+
+        val entry = blocks[3]
+        assertEquals(YBlockParent.Named("map"), entry.parent)
+        assertEquals("key", entry.parentSub)
+        assertEquals(YContentKind.TYPE, entry.contentKind)
+
+        assertEquals(mapOf(1uL to listOf(YIdRange(1u, 3u))), decoded.deleteSet)
+
+        assertThrows<com.planerist.ykt.Exception.Decode> { decodeUpdateV1(byteArrayOf(1, 2, 3)) }
+        // block ending past the largest clock value
+        val overflow = byteArrayOf(1, 1, 1, 0xF0.toByte(), 0xFF.toByte(), 0xFF.toByte(), 0xFF.toByte(), 0x0F, 0, 16, 0)
+        assertThrows<com.planerist.ykt.Exception.Decode> { decodeUpdateV1(overflow) }
+    }
+}
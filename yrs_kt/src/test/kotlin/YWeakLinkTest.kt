@@ -0,0 +1,125 @@
+import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
+import kotlin.test.Test
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertNull
+import kotlin.test.assertTrue
+
+class YWeakLinkTest {
+    @Test
+    fun TestQuoteText() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val text = doc.getText("text")
+        val map = doc.getMap("map")
+        text.insert(0u, "hello world")
+
+        val link = text.quote(0u, 4u)
+        assertTrue(link.prelim())
+        assertThrows<com.planerist.ykt.Exception.InvalidPrelimOp> { link.toText() }
+
+        map.set("quote", YIn.WeakLink(link))
+        assertFalse(link.prelim())
+        assertEquals("hello", link.toText())
+
+        text.insert(1u, "EE")
+        assertEquals("hEEello", link.toText())
+
+        // range boundaries must point to existing elements
+        assertThrows<com.planerist.ykt.Exception.OutOfBounds> { text.quote(0u, 20u) }
+
+        // link can only be read as a kind of collection it quotes
+        assertThrows<com.planerist.ykt.Exception.InvalidData> { link.unquote() }
+        assertThrows<com.planerist.ykt.Exception.InvalidData> { link.deref() }
+    }
+
+    @Test
+    fun TestObserveQuotedText() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val text = doc.getText("text")
+        val map = doc.getMap("map")
+        text.insert(0u, "hello world")
+
+        val link = text.quote(0u, 4u)
+        map.set("quote", YIn.WeakLink(link))
+        // the same preliminary link cannot be inserted twice
+        assertThrows<com.planerist.ykt.Exception.NotPrelim> { map.set("other", YIn.WeakLink(link)) }
+        val other = text.quote(6u, 7u)
+        assertThrows<com.planerist.ykt.Exception.DuplicatePrelim> {
+            doc.getArray("array").insertRange(0u, listOf(YIn.WeakLink(other), YIn.WeakLink(other)))
+        }
+
+        val events = mutableListOf<YWeakLinkEvent>()
+        link.observe(object : YWeakLinkObserver {
+            override fun onChange(event: YWeakLinkEvent) {
+                events.add(event)
+            }
+        }).use {
+            text.insert(1u, "EE")
+            text.insert(10u, "!")
+            text.format(2u, 2u, "{\"bold\":true}")
+            text.format(9u, 2u, "{\"bold\":true}")
+            text.insertEmbed(3u, YIn.Any(booleanYValue(true)))
+        }
+
+        // changes outside of the quoted range are not reported
+        assertEquals(3, events.size)
+        assertEquals("hEEello", events[0].target.toText())
+    }
+
+    @Test
+    fun TestQuoteArray() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val array = doc.getArray("array")
+        array.insertRange(0u, (0L..4L).map { longYValue(it) })
+
+        val link = array.quote(1u, 3u, YQuoteBounds(startInclusive = true, endInclusive = false))
+        array.push(YIn.WeakLink(link))
+        assertEquals(listOf(YOut.Any(longYValue(1)), YOut.Any(longYValue(2))), link.unquote())
+
+        val events = mutableListOf<YWeakLinkEvent>()
+        link.observe(object : YWeakLinkObserver {
+            override fun onChange(event: YWeakLinkEvent) {
+                events.add(event)
+            }
+        }).use {
+            array.insert(2u, longYValue(9))
+        }
+
+        assertEquals(1, events.size)
+        assertEquals(
+            listOf(YOut.Any(longYValue(1)), YOut.Any(longYValue(9)), YOut.Any(longYValue(2))),
+            link.unquote()
+        )
+    }
+
+    @Test
+    fun TestLinkMap() {
+        val d1 = YDoc(YDocOptions(1u, gc = false))
+        val map = d1.getMap("map")
+        map.set("key", stringYValue("value"))
+        val error = assertThrows<com.planerist.ykt.Exception.KeyNotFound> { map.link("missing") }
+        assertEquals("missing", error.key)
+
+        val link = map.link("key")
+        d1.getArray("array").push(YIn.WeakLink(link))
+        assertEquals(YOut.Any(stringYValue("value")), link.deref())
+
+        map.set("key", stringYValue("other"))
+        assertEquals(YOut.Any(stringYValue("other")), link.deref())
+        assertThrows<com.planerist.ykt.Exception.InvalidData> { link.toText() }
+
+        // links are preserved when synchronized with other peers
+        val d2 = YDoc(YDocOptions(2u))
+        applyUpdateV2(d2, encodeStateAsUpdateV2(d1))
+        d2.getMap("map")
+        val link2 = (d2.getArray("array").get(0u) as YOut.WeakLink).v1
+        assertEquals(YOut.Any(stringYValue("other")), link2.deref())
+
+        map.remove("key")
+        assertNull(link.deref())
+
+        // integrated links cannot be inserted again
+        assertThrows<com.planerist.ykt.Exception.NotPrelim> { map.set("again", YIn.WeakLink(link)) }
+    }
+}
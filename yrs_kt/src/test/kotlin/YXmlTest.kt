@@ -1,5 +1,7 @@
 import com.planerist.ykt.*
+import org.junit.jupiter.api.assertThrows
 import kotlin.test.Test
+import kotlin.test.assertContentEquals
 import kotlin.test.assertEquals
 import kotlin.test.assertFalse
 import kotlin.test.assertTrue
@@ -279,4 +281,212 @@ class YXmlTest {
 """, dumpFragment(xml)
         )
     }
-}
\ No newline at end of file
+
+    @Test
+    fun TestObserveDeep() {
+        val doc = YDoc()
+        val xml = doc.getXmlFragment("xml")
+
+        val shallow = mutableListOf<YXmlEvent>()
+        val deep = mutableListOf<YXmlEvent>()
+        xml.observe(object : YXmlObserver {
+            override fun onChange(event: YXmlEvent) {
+                shallow.add(event)
+            }
+        }).use {
+            xml.observeDeep(object : YXmlDeepObserver {
+                override fun onChange(events: List<YXmlEvent>) {
+                    deep.addAll(events)
+                }
+            }).use {
+                xml.push(createXmlElement("p", emptyMap(), listOf(createXmlText("hi"))))
+                assertEquals(1, shallow.size)
+                assertEquals(1, deep.size)
+                assertTrue(deep[0].delta[0] is YXmlDelta.YInsert)
+
+                // remote peer changes an attribute and text nested inside the fragment
+                val remote = YDoc()
+                applyUpdateV2(remote, encodeStateAsUpdateV2(doc))
+                val p = (remote.getXmlFragment("xml").firstChild() as YXmlChild.Element).v1
+                p.setAttribute("class", YValue.String("title"))
+                (p.firstChild() as YXmlChild.Text).v1.insert(2u, "!", null)
+                applyUpdateV2(doc, encodeStateAsUpdateV2(remote), "remote".encodeToByteArray())
+
+                // only the fragment's own children are reported to a shallow observer
+                assertEquals(1, shallow.size)
+                assertEquals(3, deep.size)
+
+                val attrEvent = deep[1]
+                assertTrue(attrEvent.target is YXmlChild.Element)
+                assertEquals(listOf<YPathSegment>(YPathSegment.Index(0u)), attrEvent.path)
+                assertEquals(YXmlAttributeChange(null, YValue.String("title")), attrEvent.keys["class"])
+                assertContentEquals("remote".encodeToByteArray(), attrEvent.origin)
+
+                val textEvent = deep[2]
+                assertTrue(textEvent.target is YXmlChild.Text)
+                assertEquals(listOf<YPathSegment>(YPathSegment.Index(0u), YPathSegment.Index(0u)), textEvent.path)
+                assertEquals(
+                    listOf(
+                        YXmlDelta.YRetain(2u, null),
+                        YXmlDelta.YInsert(YDeltaXmlChild.Embed(YValue.String("!"), null))
+                    ), textEvent.delta
+                )
+            }
+        }
+
+        // subscriptions are released by `use`
+        xml.push(createXmlText("ignored"))
+        assertEquals(1, shallow.size)
+        assertEquals(3, deep.size)
+    }
+
+    @Test
+    fun TestSnapshotDiff() {
+        val doc = YDoc(YDocOptions(1u, gc = false))
+        val root = doc.getXmlFragment("test")
+
+        val p = createXmlElement("p", mapOf("align" to stringYValue("left")))
+        val text = createXmlText("hello")
+        root.push(p)
+        root.push(createXmlElement("h1"))
+        p.v1.push(text)
+
+        val prev = snapshot(doc)
+        doc.transact { txn ->
+            text.v1.delete(0u, 1u, txn)
+            text.v1.insert(4u, " world", null, txn)
+            p.v1.setAttribute("align", stringYValue("right"), txn)
+            root.delete(1u, 1u, txn)
+            root.push(createXmlElement("h2"), txn)
+        }
+
+        val diff = root.diff(prev)
+        assertEquals(3, diff.size)
+
+        val para = diff[0] as YXmlDiff.Element
+        assertEquals("p", para.tag)
+        assertEquals(null, para.ychange)
+        assertEquals(mapOf("align" to stringYValue("right")), para.attributes)
+        assertEquals(
+            mapOf("align" to YXmlAttributeChange(stringYValue("left"), stringYValue("right"))),
+            para.attributeChanges
+        )
+
+        val delta = (para.children.single() as YXmlDiff.Text).delta
+        assertEquals(
+            listOf("h" to YChangeKind.REMOVED, "ello" to null, " world" to YChangeKind.ADDED),
+            delta.map { ((it.insert as YDeltaXmlChild.Embed).v1 as YValue.String).v1 to it.ychange?.kind }
+        )
+
+        val removed = diff[1] as YXmlDiff.Element
+        assertEquals("h1", removed.tag)
+        assertEquals(YChange(YChangeKind.REMOVED, removed.id), removed.ychange)
+
+        val added = diff[2] as YXmlDiff.Element
+        assertEquals("h2", added.tag)
+        assertEquals(YChange(YChangeKind.ADDED, added.id), added.ychange)
+
+        // element diff includes changes of the element itself
+        val element = p.v1.diff(prev) as YXmlDiff.Element
+        assertEquals(1, element.attributeChanges.size)
+
+        // current state is compared within a given transaction, including its uncommitted changes
+        doc.transact { txn ->
+            p.v1.setAttribute("lang", stringYValue("en"), txn)
+            val current = p.v1.diff(prev, txn = txn) as YXmlDiff.Element
+            assertEquals(2, current.attributeChanges.size)
+        }
+
+        // current state is compared without a need for a read-write transaction
+        doc.readTransact { txn ->
+            val current = p.v1.diff(prev, txn = txn) as YXmlDiff.Element
+            assertEquals(2, current.attributeChanges.size)
+        }
+
+        // read-only forks can be compared with their current state as well
+        val fork = doc.forkAt(snapshot(doc))
+        val forkRoot = fork.getXmlFragment("test")
+        val forkDiff = forkRoot.diff(prev)
+        assertEquals(3, forkDiff.size)
+        assertEquals(2, (forkDiff[0] as YXmlDiff.Element).attributeChanges.size)
+
+        // comparing snapshots requires garbage collection to be disabled
+        val gcDoc = YDoc(YDocOptions(2u))
+        val gcRoot = gcDoc.getXmlFragment("test")
+        assertThrows<com.planerist.ykt.Exception.GcEnabled> { gcRoot.diff(snapshot(gcDoc)) }
+    }
+
+    @Test
+    fun TestImportExportXml() {
+        val doc = YDoc(YDocOptions(1u))
+        val root = doc.getXmlFragment("test")
+        root.importXml(
+            """<?xml version="1.0"?><!-- comment --><doc id="1" class='a &amp; b'>""" +
+                    """<p>1 &lt; 2<b>!</b></p><![CDATA[<raw>]]><list><li>one</li><li>two</li></list></doc>"""
+        )
+
+        val doc0 = root.firstChild() as YXmlChild.Element
+        assertEquals("doc", doc0.v1.name())
+        assertEquals(YValue.String("a & b"), doc0.v1.getAttribute("class"))
+
+        val xml = root.exportXml()
+        assertEquals(
+            """<doc class="a &amp; b" id="1"><p>1 &lt; 2<b>!</b></p>&lt;raw&gt;""" +
+                    """<list><li>one</li><li>two</li></list></doc>""",
+            xml
+        )
+        assertEquals(
+            """<doc class="a &amp; b" id="1"><p>1 &lt; 2<b>!</b></p>&lt;raw&gt;<list>
+    <li>one</li>
+    <li>two</li>
+  </list></doc>""",
+            root.exportXml(pretty = true)
+        )
+
+        // exported XML can be imported again
+        val copy = doc.getXmlFragment("copy")
+        copy.importXml(xml)
+        assertEquals(xml, copy.exportXml())
+
+        // malformed input leaves fragment untouched
+        val error = assertThrows<com.planerist.ykt.Exception.Decode> { copy.importXml("<a><b></a>") }
+        assertEquals(YDecodeErrorKind.INVALID_XML, error.kind)
+        assertEquals(xml, copy.exportXml())
+
+        val deep = "<a>".repeat(1000) + "</a>".repeat(1000)
+        assertThrows<com.planerist.ykt.Exception.Decode> { copy.importXml(deep) }
+        val deepRaw = "<a>".repeat(256) + "<script>x</script>" + "</a>".repeat(256)
+        assertThrows<com.planerist.ykt.Exception.Decode> { copy.importXml(deepRaw, html = true) }
+
+        // whitespace added by pretty printing is read back as text nodes
+        val pretty = doc.getXmlFragment("pretty")
+        pretty.importXml("<ul><li>one</li><li>two</li></ul>")
+        val printed = pretty.exportXml(pretty = true)
+        val reimported = doc.getXmlFragment("reimported")
+        reimported.importXml(printed)
+        assertEquals(printed, reimported.exportXml())
+        assertEquals(2u, (pretty.firstChild() as YXmlChild.Element).v1.length())
+        assertEquals(5u, (reimported.firstChild() as YXmlChild.Element).v1.length())
+
+        // names, which would break the markup, are not exported
+        val invalid = doc.getXmlFragment("invalid")
+        invalid.push(YXmlChild.Element(YXmlElement("a b", emptyMap(), emptyList())))
+        assertThrows<com.planerist.ykt.Exception.InvalidXmlName> { invalid.exportXml() }
+        invalid.delete(0u, 1u)
+        invalid.push(YXmlChild.Element(YXmlElement("a", mapOf("x><y" to stringYValue("1")), emptyList())))
+        val nameError = assertThrows<com.planerist.ykt.Exception.InvalidXmlName> { invalid.exportXml() }
+        assertEquals("x><y", nameError.name)
+    }
+
+    @Test
+    fun TestImportHtml() {
+        val doc = YDoc(YDocOptions(1u))
+        val root = doc.getXmlFragment("test")
+        root.importXml("<DIV Class=x><p>one<br>two<img src=a.png hidden></DIV></span>", html = true)
+
+        assertEquals(
+            """<div class="x"><p>one<br></br>two<img hidden="" src="a.png"></img></p></div>""",
+            root.exportXml()
+        )
+    }
+}
@@ -0,0 +1,336 @@
+use crate::doc::DocRef;
+use crate::collection::{Integrated, SharedCollection, Writable};
+use crate::input::{PrelimSet, YIn};
+use crate::json::JsonEncoder;
+use crate::out::YOut;
+use crate::tools::{check_element, check_index, check_range, Error, Result};
+use crate::transaction::YTransaction;
+use crate::weak::{YQuoteBounds, YWeakLink};
+use crate::lock::TryLock;
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
+use yrs::types::TYPE_REFS_ARRAY;
+use yrs::branch::BranchPtr;
+use yrs::{Array, ArrayRef, Assoc, Quotable, ReadTxn, TransactionMut};
+
+/// A collection used to store data in an indexed sequence structure. This type is internally
+/// implemented as a double linked list, which may squash values inserted directly one after another
+/// into single list node upon transaction commit.
+///
+/// Reading a root-level type as an YArray means treating its sequence components as a list, where
+/// every countable element becomes an individual entity:
+///
+/// - JSON-like primitives (booleans, numbers, strings, JSON maps, arrays etc.) are counted
+///   individually.
+/// - Text chunks inserted by [YText] data structure: each character becomes an element of an
+///   array.
+/// - Embedded and binary values: they count as a single element even though they correspond of
+///   multiple bytes.
+///
+/// Like all Yrs shared data types, YArray is resistant to the problem of interleaving (situation
+/// when elements inserted one after another may interleave with other peers concurrent inserts
+/// after merging all updates together). In case of Yrs conflict resolution is solved by using
+/// unique document id to determine correct and consistent ordering.
+///
+/// [YText]: crate::text::YText
+#[derive(uniffi::Object)]
+#[repr(transparent)]
+pub struct YArray {
+    inner: Arc<TryLock<SharedCollection<Vec<YIn>, ArrayRef>>>,
+}
+
+impl YArray {
+    pub fn new(init: SharedCollection<Vec<YIn>, ArrayRef>) -> Self {
+        YArray {
+            inner: Arc::new(TryLock::new(init)),
+        }
+    }
+
+    pub(crate) fn branch<T: ReadTxn>(&self, txn: &T) -> Result<BranchPtr> {
+        self.inner.borrow()?.branch(txn)
+    }
+
+    /// Visits all shared types nested within this preliminary array.
+    pub(crate) fn visit_prelims(&self, visited: &mut PrelimSet) -> Result<()> {
+        if let SharedCollection::Prelim(items) = self.inner.borrow()?.deref() {
+            for item in items.iter() {
+                item.visit_prelim(visited)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn integrate(&self, txn: &mut TransactionMut, array_ref: ArrayRef) {
+        let doc = DocRef::from(txn.doc().clone());
+
+        let old_value = {
+            let mut guard = self.inner.lock_mut();
+            mem::replace(&mut *guard, SharedCollection::Integrated(Integrated::new(
+                array_ref.clone(),
+                doc,
+            )))
+        };
+
+        if let SharedCollection::Prelim(raw) = old_value {
+            for value in raw {
+                array_ref.push_back(txn, value);
+            }
+        }
+    }
+}
+
+#[uniffi::export]
+impl YArray {
+    /// Creates a new preliminary instance of a `YArray` shared data type, with its state
+    /// initialized to provided parameter.
+    ///
+    /// Preliminary instances can be nested into other shared data types such as `YArray` and `YMap`.
+    /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
+    /// document store and cannot be nested again: attempt to do so will result in an exception.
+    #[uniffi::constructor(default(items=None))]
+    pub fn new_with_items(items: Option<Vec<YIn>>) -> Result<Self> {
+        let items = items.unwrap_or_default();
+        YIn::assert_prelims(items.iter())?;
+        Ok(YArray::new(SharedCollection::prelim(items)))
+    }
+
+    #[inline]
+    pub fn get_type(&self) -> u8 {
+        TYPE_REFS_ARRAY
+    }
+
+    /// Returns true if this is a preliminary instance of `YArray`.
+    ///
+    /// Preliminary instances can be nested into other shared data types such as `YArray` and `YMap`.
+    /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
+    /// document store and cannot be nested again: attempt to do so will result in an exception.
+    pub fn prelim(&self) -> Result<bool> {
+        Ok(self.inner.borrow()?.is_prelim())
+    }
+
+    /// Checks if current YArray reference is alive and has not been deleted by its parent collection.
+    /// This method only works on already integrated shared types and will return false is current
+    /// type is preliminary (has not been integrated into document).
+    pub fn alive(&self, txn: &YTransaction) -> Result<bool> {
+        self.inner.borrow()?.is_alive(txn)
+    }
+
+    /// Returns a number of elements stored within this instance of `YArray`.
+    #[uniffi::method(default(txn=None))]
+    pub fn length(&self, txn: Option<Arc<YTransaction>>) -> Result<u32> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.len() as u32),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.len(txn))),
+        }
+    }
+
+    /// Returns contents of this integrated `YArray` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<String> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).array(c).to_string())
+            }),
+        }
+    }
+
+    /// Inserts a given `value` at the given `index`. Inserting at index `0` is equivalent to
+    /// prepending current array with given value, while inserting at array length is equivalent
+    /// to appending that value at the end of it.
+    #[uniffi::method(default(txn=None))]
+    pub fn insert(&self, index: u32, value: YIn, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        value.assert_prelim()?;
+
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_index(index, c.len() as u32)?;
+                c.insert(index as usize, value);
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
+                c.insert(txn, index, value);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Inserts given list of `items` into this `YArray` instance, starting at a given `index`.
+    #[uniffi::method(default(txn=None))]
+    pub fn insert_range(
+        &self,
+        index: u32,
+        items: Vec<YIn>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        YIn::assert_prelims(items.iter())?;
+
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_index(index, c.len() as u32)?;
+                let index = index as usize;
+                c.splice(index..index, items);
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
+                if items.iter().all(|item| matches!(item, YIn::Any(_))) {
+                    let values = items.into_iter().filter_map(|item| match item {
+                        YIn::Any(v) => Some(v),
+                        _ => None,
+                    });
+                    c.insert_range(txn, index, values);
+                } else {
+                    for (i, item) in items.into_iter().enumerate() {
+                        c.insert(txn, index + i as u32, item);
+                    }
+                }
+                Ok(())
+            }),
+        }
+    }
+
+    /// Appends a given `value` at the end of this `YArray` instance.
+    #[uniffi::method(default(txn=None))]
+    pub fn push(&self, value: YIn, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        value.assert_prelim()?;
+
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                c.push(value);
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                c.push_back(txn, value);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Deletes a range of items of given `length` from current `YArray` instance,
+    /// starting from given `index`.
+    #[uniffi::method(default(txn=None))]
+    pub fn delete(&self, index: u32, length: u32, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_range(index, length, c.len() as u32)?;
+                c.drain((index as usize)..((index + length) as usize));
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
+                c.remove_range(txn, index, length);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Returns an element stored under given `index`.
+    #[uniffi::method(default(txn=None))]
+    pub fn get(&self, index: u32, txn: Option<Arc<YTransaction>>) -> Result<Option<YOut>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.get(index as usize).map(|v| v.to_out())),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(c.get(txn, index).map(|out| YOut::from_out(out, txn.doc().clone())))
+            }),
+        }
+    }
+
+    /// Moves element found at `source` index into `target` index position. This method only works
+    /// on `YArray` instances that already have been integrated into document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn move_to(&self, source: u32, target: u32, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(_) => Err(Error::InvalidPrelimOp),
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                let len = c.len(txn);
+                check_element(source, len)?;
+                check_index(target, len)?;
+                c.move_to(txn, source, target);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Moves all elements found within `start`..`end` indexes range (both side inclusive) into
+    /// new position pointed by `target` index. All elements inserted concurrently by other peers
+    /// inside of moved range will be moved as well after synchronization (although it make take
+    /// more than one sync roundtrip to achieve convergence). Fails with `OutOfBounds` if `start`
+    /// is greater than `end`.
+    ///
+    /// This method only works on `YArray` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn move_range_to(
+        &self,
+        start: u32,
+        end: u32,
+        target: u32,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(_) => Err(Error::InvalidPrelimOp),
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                let len = c.len(txn);
+                check_element(start, len)?;
+                check_element(end, len)?;
+                check_index(target, len)?;
+                if start > end {
+                    // range must not be empty, `end` is the last valid start index
+                    return Err(Error::OutOfBounds {
+                        index: start,
+                        len: end + 1,
+                    });
+                }
+                c.move_range_to(txn, start, Assoc::After, end, Assoc::Before, target);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Returns a list of all elements stored within this instance of `YArray`.
+    #[uniffi::method(default(txn=None))]
+    pub fn to_list(&self, txn: Option<Arc<YTransaction>>) -> Result<Vec<YOut>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.iter().map(|v| v.to_out()).collect()),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let doc = txn.doc().clone();
+                Ok(c.iter(txn).map(|value| YOut::from_out(value, doc.clone())).collect())
+            }),
+        }
+    }
+
+    /// Returns a preliminary `YWeakLink` quoting a range of elements between `start` and `end`
+    /// indexes. By default both sides of the range are inclusive. Even if a side of the range is
+    /// exclusive, an index of that side must point to an existing element, as it's used as
+    /// a point of reference. The returned link can be inserted into any other shared collection.
+    ///
+    /// This method only works on `YArray` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(bounds=None, txn=None))]
+    pub fn quote(
+        &self,
+        start: u32,
+        end: u32,
+        bounds: Option<YQuoteBounds>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<Arc<YWeakLink>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let range = YQuoteBounds::check(bounds, start, end, c.len(txn))?;
+                let prelim = c.quote(txn, range).map_err(|_| Error::OutOfBounds {
+                    index: end,
+                    len: c.len(txn),
+                })?;
+                Ok(Arc::new(YWeakLink::from_prelim(prelim)))
+            }),
+        }
+    }
+}
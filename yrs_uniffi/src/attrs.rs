@@ -116,6 +116,16 @@ pub fn from_yattrs_opt(attr: &Option<HashMap<String, YValue>>) -> Option<Box<Att
     }
 }
 
+/// Converts typed `attrs` into formatting attributes, treating an empty map the same way as
+/// absent attributes.
+pub fn from_yattrs_non_empty(attrs: &YAttributes) -> Option<Attrs> {
+    if attrs.is_empty() {
+        None
+    } else {
+        Some(from_yattrs(attrs))
+    }
+}
+
 pub fn from_yattrs(attrs: &HashMap<String, YValue>) -> Attrs {
     let mut res : HashMap<Arc<str>, Any> = HashMap::new();
     for (k, v) in attrs {
@@ -0,0 +1,178 @@
+use crate::doc::YDoc;
+use crate::subscription::YSubscription;
+use crate::tools::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use yrs::sync::awareness::Event;
+use yrs::sync::time::SystemClock;
+use yrs::sync::{Awareness, AwarenessUpdate, Clock};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::Origin;
+
+/// Default time (in milliseconds) after which remote peers that didn't update their state are
+/// considered offline. Matches `outdatedTimeout` of y-protocols.
+const OUTDATED_TIMEOUT: u64 = 30_000;
+
+/// Event emitted by [YAwareness] whenever states of its clients have changed.
+#[derive(uniffi::Record)]
+pub struct YAwarenessEvent {
+    /// Clients which states have been seen for the first time.
+    pub added: Vec<u64>,
+    /// Clients which states have been updated.
+    pub updated: Vec<u64>,
+    /// Clients which states have been removed.
+    pub removed: Vec<u64>,
+    /// Origin of the change, if any was provided.
+    pub origin: Option<Vec<u8>>,
+}
+
+impl YAwarenessEvent {
+    fn new(event: &Event, origin: Option<&Origin>) -> Self {
+        YAwarenessEvent {
+            added: event.added().to_vec(),
+            updated: event.updated().to_vec(),
+            removed: event.removed().to_vec(),
+            origin: origin.map(|origin| origin.as_ref().to_vec()),
+        }
+    }
+}
+
+/// Callback interface used to receive [YAwarenessEvent]s.
+#[uniffi::export(callback_interface)]
+pub trait YAwarenessObserver: Send + Sync {
+    fn on_change(&self, event: YAwarenessEvent);
+}
+
+/// Awareness is used to propagate non-persistent information about connected peers, such as their
+/// presence, user names or cursor positions. Each peer owns a single JSON state identified by its
+/// document client id. Updates produced by awareness are binary compatible with `y-protocols`
+/// awareness implementation.
+#[derive(uniffi::Object)]
+pub struct YAwareness(pub(crate) Awareness);
+
+#[uniffi::export]
+impl YAwareness {
+    #[uniffi::constructor]
+    pub fn new(doc: &YDoc) -> Self {
+        YAwareness(Awareness::new((*doc.0).clone()))
+    }
+
+    /// Returns a client id of the local peer.
+    pub fn client_id(&self) -> u64 {
+        self.0.client_id()
+    }
+
+    /// Returns a document this awareness instance is bound to.
+    pub fn doc(&self) -> Arc<YDoc> {
+        Arc::new(YDoc::from(self.0.doc().clone()))
+    }
+
+    /// Returns a JSON state of the local peer or null if it was not set or has been removed.
+    pub fn local_state(&self) -> Option<String> {
+        self.0.local_state_raw().map(|json| json.to_string())
+    }
+
+    /// Sets a JSON state of the local peer. Passing null marks local peer as offline.
+    pub fn set_local_state(&self, json: Option<String>) -> Result<()> {
+        match json {
+            None => self.0.clean_local_state(),
+            Some(json) => {
+                serde_json::from_str::<serde_json::Value>(&json)?;
+                self.0.set_local_state_raw(json);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns JSON states of all known peers (including local one) keyed by their client ids.
+    /// Peers which have been removed are not included.
+    pub fn states(&self) -> HashMap<u64, String> {
+        self.0
+            .iter()
+            .filter_map(|(client_id, state)| Some((client_id, state.data?.to_string())))
+            .collect()
+    }
+
+    /// Marks states of given clients as removed.
+    pub fn remove_states(&self, clients: Vec<u64>) {
+        for client_id in clients {
+            self.0.remove_state(client_id);
+        }
+    }
+
+    /// Encodes states of given `clients` (or all known peers if not provided) into a binary
+    /// update, which can be sent to remote peers and applied via `apply_update`.
+    #[uniffi::method(default(clients=None))]
+    pub fn encode_update(&self, clients: Option<Vec<u64>>) -> Result<Vec<u8>> {
+        let update = match clients {
+            None => self.0.update(),
+            Some(clients) => self.0.update_with_clients(clients),
+        };
+        let update = update?;
+        Ok(update.encode_v1())
+    }
+
+    /// Applies a binary update produced by a remote peer.
+    #[uniffi::method(default(origin=None))]
+    pub fn apply_update(&self, update: &[u8], origin: Option<Vec<u8>>) -> Result<()> {
+        let update = AwarenessUpdate::decode_v1(update)?;
+        let result = match origin {
+            None => self.0.apply_update(update),
+            Some(origin) => self.0.apply_update_with(update, origin.as_slice()),
+        };
+        Ok(result?)
+    }
+
+    /// Renews the local state if it's about to expire and removes states of remote peers which
+    /// have not been updated for longer than `timeout_millis` (30 seconds by default). This method
+    /// is expected to be called periodically. Returns a list of removed clients.
+    #[uniffi::method(default(timeout_millis=None))]
+    pub fn remove_outdated_states(&self, timeout_millis: Option<u64>) -> Vec<u64> {
+        let timeout = timeout_millis.unwrap_or(OUTDATED_TIMEOUT);
+        let now = SystemClock.now();
+        let local_id = self.0.client_id();
+
+        let mut outdated = Vec::new();
+        let mut renew = false;
+        for (client_id, state) in self.0.iter() {
+            if state.data.is_none() {
+                continue;
+            }
+            let elapsed = now.saturating_sub(state.last_updated);
+            if client_id == local_id {
+                renew = elapsed >= timeout / 2;
+            } else if elapsed >= timeout {
+                outdated.push(client_id);
+            }
+        }
+
+        if renew {
+            if let Some(json) = self.0.local_state_raw() {
+                self.0.set_local_state_raw(json);
+            }
+        }
+        for client_id in outdated.iter() {
+            self.0.remove_state(*client_id);
+        }
+        outdated
+    }
+
+    /// Subscribes to changes of peer states. Events are emitted only if states have actually
+    /// changed.
+    pub fn observe_change(&self, observer: Box<dyn YAwarenessObserver>) -> Arc<YSubscription> {
+        let subscription = self.0.on_change(move |_, e, origin| {
+            observer.on_change(YAwarenessEvent::new(e, origin));
+        });
+        Arc::new(YSubscription::from(subscription))
+    }
+
+    /// Subscribes to all updates of peer states, including the ones that only renewed
+    /// an existing state without changing it.
+    pub fn observe_update(&self, observer: Box<dyn YAwarenessObserver>) -> Arc<YSubscription> {
+        let subscription = self.0.on_update(move |_, e, origin| {
+            observer.on_change(YAwarenessEvent::new(e, origin));
+        });
+        Arc::new(YSubscription::from(subscription))
+    }
+}
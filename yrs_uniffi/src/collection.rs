@@ -1,7 +1,10 @@
+use crate::doc::DocRef;
+use crate::lock::TryLock;
 use crate::tools::{Error, Result};
-use crate::transaction::YTransaction;
+use crate::transaction::{ReadTxnRef, YTransaction};
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::sync::{Arc, RwLockWriteGuard};
+use yrs::branch::BranchPtr;
 use yrs::{BranchID, Doc, Hook, ReadTxn, SharedRef, Transact, Transaction, TransactionMut};
 
 #[derive(Clone)]
@@ -17,7 +20,7 @@ impl<P, S: SharedRef + 'static> SharedCollection<P, S> {
     }
 
     #[inline]
-    pub fn integrated(shared_ref: S, doc: Doc) -> Self {
+    pub fn integrated(shared_ref: S, doc: DocRef) -> Self {
         SharedCollection::Integrated(Integrated::new(shared_ref, doc))
     }
 
@@ -32,13 +35,21 @@ impl<P, S: SharedRef + 'static> SharedCollection<P, S> {
         match self {
             SharedCollection::Integrated(i) => {
                 let branch_id = i.hook.id();
-                let doc = &i.doc;
+                let doc: &Doc = &i.doc;
                 Ok((branch_id, doc))
             }
             SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
         }
     }
 
+    /// Returns a pointer to the underlying branch of an integrated shared collection.
+    pub fn branch<T: ReadTxn>(&self, txn: &T) -> Result<BranchPtr> {
+        match self {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => Ok(BranchPtr::from(c.resolve(txn)?.as_ref())),
+        }
+    }
+
     #[inline]
     pub fn is_prelim(&self) -> bool {
         match self {
@@ -47,12 +58,12 @@ impl<P, S: SharedRef + 'static> SharedCollection<P, S> {
         }
     }
 
-    pub fn is_alive(&self, txn: &YTransaction) -> bool {
+    pub fn is_alive(&self, txn: &YTransaction) -> Result<bool> {
         match self {
-            SharedCollection::Prelim(_) => true,
+            SharedCollection::Prelim(_) => Ok(true),
             SharedCollection::Integrated(col) => {
                 let desc = &col.hook;
-                desc.get(txn.get_inner().borrow().deref()).is_some()
+                Ok(desc.get(txn.borrow()?.deref()).is_some())
             }
         }
     }
@@ -66,48 +77,98 @@ impl<P, S: SharedRef + 'static> SharedCollection<P, S> {
     }
 }
 
+/// A shared collection borrowed for a write operation, see [TryLock::borrow_write].
+pub enum Writable<'a, P, S> {
+    Prelim(PrelimGuard<'a, P, S>),
+    Integrated(Integrated<S>),
+}
+
+/// Exclusive access to the state of a preliminary shared collection.
+pub struct PrelimGuard<'a, P, S>(RwLockWriteGuard<'a, SharedCollection<P, S>>);
+
+impl<P, S> Deref for PrelimGuard<'_, P, S> {
+    type Target = P;
+
+    fn deref(&self) -> &Self::Target {
+        match self.0.deref() {
+            SharedCollection::Prelim(prelim) => prelim,
+            SharedCollection::Integrated(_) => unreachable!("checked by TryLock::borrow_write"),
+        }
+    }
+}
+
+impl<P, S> DerefMut for PrelimGuard<'_, P, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self.0.deref_mut() {
+            SharedCollection::Prelim(prelim) => prelim,
+            SharedCollection::Integrated(_) => unreachable!("checked by TryLock::borrow_write"),
+        }
+    }
+}
+
+impl<P, S: SharedRef + Clone + 'static> TryLock<SharedCollection<P, S>> {
+    /// Borrows a shared collection for a write operation. Integrated collections are modified
+    /// through document transactions, so they are not borrowed for the duration of the operation
+    /// and remain available to readers, while preliminary ones are borrowed exclusively.
+    pub fn borrow_write(&self) -> Result<Writable<'_, P, S>> {
+        if let SharedCollection::Integrated(c) = self.borrow()?.deref() {
+            return Ok(Writable::Integrated(c.clone()));
+        }
+        let guard = self.borrow_mut()?;
+        match guard.deref() {
+            // preliminary collection could have been integrated in the meantime
+            SharedCollection::Integrated(c) => Ok(Writable::Integrated(c.clone())),
+            SharedCollection::Prelim(_) => Ok(Writable::Prelim(PrelimGuard(guard))),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Integrated<S> {
     pub hook: Hook<S>,
-    pub doc: Doc,
+    pub doc: DocRef,
 }
 
 impl<S: SharedRef + 'static> Integrated<S> {
-    pub fn new(shared_ref: S, doc: Doc) -> Self {
+    pub fn new(shared_ref: S, doc: DocRef) -> Self {
         let desc = shared_ref.hook();
         Integrated { hook: desc, doc }
     }
 
+    /// Executes a read operation `f`, using a given transaction - which can be either read-only or
+    /// read-write - or a new read-only transaction if none was provided.
     pub fn readonly<F, R>(&self, txn: Option<Arc<YTransaction>>, f: F) -> Result<R>
     where
-        F: FnOnce(&S, &TransactionMut<'_>) -> Result<R>,
+        F: FnOnce(&S, &ReadTxnRef<'_>) -> Result<R>,
     {
         match txn {
             Some(txn) => {
-                let inner = txn.get_inner();
-                let txn = inner.borrow();
-                let txn = txn.deref();
-                let txn = txn.deref();
-                let shared_ref= self.resolve(txn)?;
-                f(&shared_ref, txn)
+                let txn = txn.borrow()?;
+                let txn = ReadTxnRef::new(txn.deref(), &self.doc);
+                let shared_ref = self.resolve(&txn)?;
+                f(&shared_ref, &txn)
             }
             None => {
-                let txn = self.transact_mut()?; // :( rust type inference problem
-                let shared_ref= self.resolve(&txn)?;
+                let txn = self.transact()?;
+                let txn = ReadTxnRef::new(&txn, &self.doc);
+                let shared_ref = self.resolve(&txn)?;
                 f(&shared_ref, &txn)
             }
         }
     }
 
+    /// Executes a write operation `f`, using a given transaction or a new one if none was
+    /// provided. Fails with [Error::InvalidTransactionCtx] if a given transaction is read-only or
+    /// with [Error::ReadOnlyDoc] if this collection belongs to a read-only document.
     pub fn mutably<F, T>(&self, txn: Option<Arc<YTransaction>>, f: F) -> Result<T>
     where
         F: FnOnce(&S, &mut TransactionMut<'_>) -> Result<T>,
     {
+        self.doc.assert_writable()?;
         match txn {
             Some(txn) => {
-                let inner = txn.get_inner();
-                let mut txn = inner.borrow_mut();
-                let txn = txn.deref_mut();
+                let mut txn = txn.borrow_mut()?;
+                let txn = txn.write()?;
                 let shared_ref = self.resolve(txn)?;
                 f(&shared_ref, txn)
             }
@@ -134,9 +195,6 @@ impl<S: SharedRef + 'static> Integrated<S> {
     }
 
     pub fn transact_mut(&self) -> Result<TransactionMut> {
-        match self.doc.try_transact_mut() {
-            Ok(tx) => Ok(tx),
-            Err(_) => Err(Error::AnotherTx),
-        }
+        self.doc.transact_mut()
     }
 }
@@ -1,18 +1,74 @@
-use crate::attrs::{from_yattrs_opt, YAttributes, YValue};
+use crate::doc::DocRef;
+use crate::attrs::{from_yattrs_opt, into_yattrs, YAttributes, YValue};
+use crate::out::YOut;
+use crate::tools::{check_range, Error, Result};
 use yrs::types::Delta;
-use yrs::Any;
+use yrs::{Any, OffsetKind, Out};
 
 #[derive(uniffi::Enum)]
 pub enum YDelta {
     YInsert(YValue, Option<YAttributes>),
     YDelete(u32),
     YRetain(u32, Option<YAttributes>),
+    /// Insertion of an embedded shared type (eg. nested `YMap` or `YXmlElement`).
+    YInsertShared(YOut, Option<YAttributes>),
 }
 
-pub fn y_into_delta(d: &YDelta) -> Delta<Any> {
+pub fn y_into_delta(d: &YDelta) -> Result<Delta<Any>> {
     match d {
-        YDelta::YInsert(v, attrs) => Delta::Inserted(v.into(), from_yattrs_opt(attrs)),
-        YDelta::YDelete(len) => Delta::Deleted(*len),
-        YDelta::YRetain(len, attrs) => Delta::Retain(*len, from_yattrs_opt(attrs)),
+        YDelta::YInsert(v, attrs) => Ok(Delta::Inserted(v.into(), from_yattrs_opt(attrs))),
+        YDelta::YDelete(len) => Ok(Delta::Deleted(*len)),
+        YDelta::YRetain(len, attrs) => Ok(Delta::Retain(*len, from_yattrs_opt(attrs))),
+        YDelta::YInsertShared(YOut::Any(v), attrs) => {
+            Ok(Delta::Inserted(v.into(), from_yattrs_opt(attrs)))
+        }
+        YDelta::YInsertShared(_, _) => Err(Error::InvalidDelta),
     }
-}
\ No newline at end of file
+}
+
+/// Checks if a given `delta` can be applied to a text of a given length (measured using a given
+/// offset `kind`): all retained and deleted ranges must fit within the text, including content
+/// inserted by preceding operations.
+pub fn check_delta(delta: &[Delta<Any>], len: u32, kind: OffsetKind) -> Result<()> {
+    let mut len = len;
+    let mut index = 0u32;
+    for d in delta {
+        match d {
+            Delta::Inserted(value, _) => {
+                let inserted = match value {
+                    Any::String(s) => match kind {
+                        OffsetKind::Bytes => s.len() as u32,
+                        OffsetKind::Utf16 => s.encode_utf16().count() as u32,
+                    },
+                    _ => 1,
+                };
+                index += inserted;
+                len += inserted;
+            }
+            Delta::Deleted(n) => {
+                check_range(index, *n, len)?;
+                len -= n;
+            }
+            Delta::Retain(n, _) => {
+                check_range(index, *n, len)?;
+                index += n;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn y_insert(value: Out, attrs: Option<YAttributes>, doc: DocRef) -> YDelta {
+    match YOut::from_out(value, doc) {
+        YOut::Any(v) => YDelta::YInsert(v, attrs),
+        other => YDelta::YInsertShared(other, attrs),
+    }
+}
+
+pub fn y_delta(d: Delta<Out>, doc: DocRef) -> YDelta {
+    match d {
+        Delta::Inserted(value, attrs) => y_insert(value, attrs.map(|a| into_yattrs(*a)), doc),
+        Delta::Deleted(len) => YDelta::YDelete(len),
+        Delta::Retain(len, attrs) => YDelta::YRetain(len, attrs.map(|a| into_yattrs(*a))),
+    }
+}
@@ -1,12 +1,18 @@
+use crate::array::YArray;
 use crate::collection::SharedCollection;
+use crate::json::JsonEncoder;
+use crate::map::YMap;
+use crate::snapshots::{restore_snapshot, y_delete_set, y_state_vector, YIdRange, YSnapshot};
+use crate::subscription::YSubscription;
 use crate::text::YText;
 use crate::tools::Error;
 use crate::tools::Result;
 use crate::transaction::YTransaction;
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::Arc;
 use yrs::types::TYPE_REFS_DOC;
-use yrs::{Doc, OffsetKind, Options, Transact};
+use yrs::{Doc, OffsetKind, Options, Origin, ReadTxn, SubdocsEvent, SubdocsEventIter, Transact, Transaction, TransactionMut};
 use crate::xml_frag::YXmlFragment;
 
 /// A ywasm document type. Documents are most important units of collaborative resources management.
@@ -34,8 +40,7 @@ use crate::xml_frag::YXmlFragment;
 /// }
 /// ```
 #[derive(uniffi::Object)]
-#[repr(transparent)]
-pub struct YDoc(pub(crate) Doc);
+pub struct YDoc(pub(crate) DocRef);
 
 impl Deref for YDoc {
     type Target = Doc;
@@ -46,12 +51,157 @@ impl Deref for YDoc {
     }
 }
 
+/// A document shared by all objects bound to it, along with its read-only status. Read-only
+/// documents are created by `YDoc.fork_at` and reject all modifications.
+#[derive(Clone)]
+pub struct DocRef {
+    doc: Doc,
+    read_only: bool,
+}
+
+impl DocRef {
+    pub fn new(doc: Doc, read_only: bool) -> Self {
+        DocRef { doc, read_only }
+    }
+
+    #[inline]
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fails with [Error::ReadOnlyDoc] if this document cannot be modified.
+    pub fn assert_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnlyDoc)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Starts a new read-write transaction. Fails with [Error::ReadOnlyDoc] if this document is
+    /// read-only or with [Error::AnotherTx] if another transaction is in progress.
+    pub fn transact_mut(&self) -> Result<TransactionMut<'_>> {
+        self.assert_writable()?;
+        self.doc.try_transact_mut().map_err(|_| Error::AnotherTx)
+    }
+
+    /// Starts a new read-write transaction with a given `origin`. See [DocRef::transact_mut].
+    pub fn transact_mut_with<T: Into<Origin>>(&self, origin: T) -> Result<TransactionMut<'_>> {
+        self.assert_writable()?;
+        self.doc.try_transact_mut_with(origin).map_err(|_| Error::AnotherTx)
+    }
+}
+
+impl Deref for DocRef {
+    type Target = Doc;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.doc
+    }
+}
+
+impl From<Doc> for DocRef {
+    fn from(doc: Doc) -> Self {
+        DocRef::new(doc, false)
+    }
+}
+
+impl YDoc {
+    /// Returns a root type of this document, creating it if necessary. Missing roots cannot be
+    /// created in read-only documents, in which case [Error::ReadOnlyDoc] is returned.
+    fn root<T>(&self, get: impl FnOnce(&Transaction) -> Option<T>, create: impl FnOnce(&Doc) -> T) -> Result<T> {
+        if !self.0.read_only() {
+            return Ok(create(&self.0));
+        }
+        let txn = self.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+        get(&txn).ok_or(Error::ReadOnlyDoc)
+    }
+}
+
 impl From<Doc> for YDoc {
     fn from(doc: Doc) -> Self {
-        YDoc(doc)
+        YDoc(doc.into())
+    }
+}
+
+/// Event emitted by `YDoc.observe_update_v1` and `YDoc.observe_update_v2` once a transaction
+/// which made any changes to the document has been committed.
+#[derive(uniffi::Record)]
+pub struct YUpdateEvent {
+    /// Binary update containing all changes made within committed transaction, encoded using
+    /// lib0 v1 or v2 encoding, depending on the observer.
+    pub update: Vec<u8>,
+    /// Origin of the committed transaction, if any was provided.
+    pub origin: Option<Vec<u8>>,
+}
+
+/// Event emitted by `YDoc.observe_after_transaction` and `YDoc.observe_transaction_cleanup`
+/// whenever a transaction is committed.
+#[derive(uniffi::Record)]
+pub struct YTransactionEvent {
+    /// State vector of the document at the beginning of the transaction.
+    pub before_state: HashMap<u64, u32>,
+    /// State vector of the document after the transaction has been committed.
+    pub after_state: HashMap<u64, u32>,
+    /// Ranges of blocks deleted within the transaction, grouped by client id.
+    pub delete_set: HashMap<u64, Vec<YIdRange>>,
+    /// Origin of the committed transaction, if any was provided.
+    pub origin: Option<Vec<u8>>,
+}
+
+impl YTransactionEvent {
+    fn new(txn: &TransactionMut) -> Self {
+        YTransactionEvent {
+            before_state: y_state_vector(txn.before_state()),
+            after_state: y_state_vector(txn.after_state()),
+            delete_set: y_delete_set(txn.delete_set()),
+            origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+        }
     }
 }
 
+/// Event emitted by `YDoc.observe_subdocs` whenever sub-documents have been added, removed or
+/// requested to be loaded within a committed transaction.
+#[derive(uniffi::Record)]
+pub struct YSubdocsEvent {
+    /// Sub-documents inserted into the document.
+    pub added: Vec<Arc<YDoc>>,
+    /// Sub-documents removed from the document.
+    pub removed: Vec<Arc<YDoc>>,
+    /// Sub-documents which requested to be loaded, ie. via `YDoc.load`.
+    pub loaded: Vec<Arc<YDoc>>,
+}
+
+impl YSubdocsEvent {
+    fn new(event: &SubdocsEvent) -> Self {
+        let docs = |iter: SubdocsEventIter| iter.map(|doc| Arc::new(YDoc::from(doc.clone()))).collect();
+        YSubdocsEvent {
+            added: docs(event.added()),
+            removed: docs(event.removed()),
+            loaded: docs(event.loaded()),
+        }
+    }
+}
+
+/// Callback interface used to receive [YUpdateEvent]s.
+#[uniffi::export(callback_interface)]
+pub trait YUpdateObserver: Send + Sync {
+    fn on_update(&self, event: YUpdateEvent);
+}
+
+/// Callback interface used to receive [YTransactionEvent]s.
+#[uniffi::export(callback_interface)]
+pub trait YTransactionObserver: Send + Sync {
+    fn on_transaction(&self, event: YTransactionEvent);
+}
+
+/// Callback interface used to receive [YSubdocsEvent]s.
+#[uniffi::export(callback_interface)]
+pub trait YSubdocsObserver: Send + Sync {
+    fn on_change(&self, event: YSubdocsEvent);
+}
+
 #[uniffi::export]
 impl YDoc {
     /// Creates a new ywasm document. If `id` parameter was passed it will be used as this document
@@ -85,7 +235,7 @@ impl YDoc {
     /// Returns a parent document of this document or null if current document is not sub-document.
     pub fn parent_doc(&self) -> Option<Arc<YDoc>> {
         let doc = self.0.parent_doc()?;
-        Some(Arc::new(YDoc(doc)))
+        Some(Arc::new(YDoc::from(doc)))
     }
 
     /// Gets unique peer identifier of this `YDoc` instance.
@@ -106,6 +256,94 @@ impl YDoc {
         self.0.auto_load()
     }
 
+    /// Requests this sub-document to be loaded. The request is reported to observers registered
+    /// via `observe_subdocs` on the parent document once `parent_txn` (or a transaction created
+    /// for this call) is committed. Calling this method on a document which is not a
+    /// sub-document only marks it as one that should be loaded.
+    #[uniffi::method(default(parent_txn=None))]
+    pub fn load(&self, parent_txn: Option<Arc<YTransaction>>) -> Result<()> {
+        match parent_txn {
+            Some(txn) => {
+                let mut txn = txn.borrow_mut()?;
+                self.0.load(txn.write()?);
+            }
+            None => match self.0.parent_doc() {
+                Some(parent) => {
+                    let parent = DocRef::from(parent);
+                    let mut txn = parent.transact_mut()?;
+                    self.0.load(&mut txn);
+                }
+                None => {
+                    // there's no parent to notify
+                    let mut txn = self.0.transact_mut()?;
+                    self.0.load(&mut txn);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Returns a list of sub-documents existing within the scope of this document.
+    #[uniffi::method(default(txn=None))]
+    pub fn subdocs(&self, txn: Option<Arc<YTransaction>>) -> Result<Vec<Arc<YDoc>>> {
+        let docs: Vec<Doc> = match txn {
+            Some(txn) => {
+                let txn = txn.borrow()?;
+                txn.subdocs().cloned().collect()
+            }
+            None => {
+                let txn = self.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+                txn.subdocs().cloned().collect()
+            }
+        };
+        Ok(docs.into_iter().map(|doc| Arc::new(YDoc::from(doc))).collect())
+    }
+
+    /// Returns a list of unique identifiers of sub-documents existing within the scope of this
+    /// document.
+    #[uniffi::method(default(txn=None))]
+    pub fn subdoc_guids(&self, txn: Option<Arc<YTransaction>>) -> Result<Vec<String>> {
+        match txn {
+            Some(txn) => {
+                let txn = txn.borrow()?;
+                Ok(txn.subdoc_guids().map(|guid| guid.to_string()).collect())
+            }
+            None => {
+                let txn = self.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+                Ok(txn.subdoc_guids().map(|guid| guid.to_string()).collect())
+            }
+        }
+    }
+
+    /// Returns all root types of this document as a JSON object string, keyed by their names.
+    /// The same mapping is used by `to_json` methods of all shared types:
+    ///
+    /// - `YText` and `YXmlText` are converted into strings or, if `include_formatting` is set,
+    ///   into lists of delta chunks (`{"insert": ..., "attributes": {...}}`).
+    /// - `YXmlElement` is converted into an object with `nodeName`, `attributes` and `children`
+    ///   fields, while `YXmlFragment` only has `children`.
+    /// - `YMap` and `YArray` are converted into JSON objects and arrays, recursively.
+    /// - `YWeakLink` is converted into a value it links to, or a list of quoted values.
+    /// - Subdocuments are converted into an object with their `guid`.
+    /// - Binary buffers are converted into base64 strings and integral numbers are written
+    ///   without a fraction.
+    ///
+    /// The result is plain JSON: use `yvalue_to_json` for a representation that can be parsed
+    /// back into the same values.
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(&self, include_formatting: bool, txn: Option<Arc<YTransaction>>) -> Result<String> {
+        match txn {
+            Some(txn) => {
+                let txn = txn.borrow()?;
+                Ok(JsonEncoder::new(&txn.read(), include_formatting).doc().to_string())
+            }
+            None => {
+                let txn = self.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+                Ok(JsonEncoder::new(&txn, include_formatting).doc().to_string())
+            }
+        }
+    }
+
     /// Returns a new transaction for this document. Ywasm shared data types execute their
     /// operations in a context of a given transaction. Each document can have only one active
     /// transaction at the time - subsequent attempts will cause exception to be thrown.
@@ -136,37 +374,174 @@ impl YDoc {
     #[uniffi::method(default(origin=None))]
     pub fn transaction(&self, origin: Option<String>) -> Result<YTransaction> {
         let inner = if let Some(origin) = origin {
-            self.try_transact_mut_with(yrs::Origin::from(origin))
+            self.0.transact_mut_with(yrs::Origin::from(origin))
         } else {
-            self.try_transact_mut()
-        }
-        .map_err(|_| Error::AnotherRwTx)?;
+            self.0.transact_mut()
+        }?;
 
         Ok(YTransaction::from(inner))
     }
 
+    /// Returns a new read-only transaction for this document. Many read-only transactions can be
+    /// active at the same time, but none of them can coexist with a read-write transaction.
+    ///
+    /// Read-only transactions are accepted by all read operations of shared data types, while
+    /// attempts to modify a document through them fail with `InvalidTransactionCtx`. Transaction
+    /// is released once it's committed or closed.
+    pub fn read_transaction(&self) -> Result<YTransaction> {
+        let txn = self.try_transact().map_err(|_| Error::AnotherRwTx)?;
+        Ok(YTransaction::new_read_only(txn, self.0.clone()))
+    }
+
+    /// Checks if this document is read-only, ie. it has been created using `YDoc.fork_at`.
+    pub fn read_only(&self) -> bool {
+        self.0.read_only()
+    }
+
+    /// Returns a new read-only document, which contains the state of this document at the moment
+    /// when a given `snapshot` was taken. It can be used to render historical versions of a
+    /// document.
+    ///
+    /// Snapshots can only be restored from documents created with garbage collection disabled
+    /// (`gc = false`), as otherwise contents of deleted blocks are no longer available - in such
+    /// case a `GcEnabled` exception is thrown.
+    ///
+    /// Returned document cannot be modified: starting a transaction and modifying its shared types
+    /// fails with `ReadOnlyDoc`, as well as getting root types which didn't exist at the time of
+    /// a snapshot.
+    pub fn fork_at(&self, snapshot: &YSnapshot) -> Result<YDoc> {
+        let doc = {
+            let txn = self.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+            restore_snapshot(&self.0, &txn, snapshot)?
+        };
+
+        Ok(YDoc(DocRef::new(doc, true)))
+    }
+
     /// Returns a `YText` shared data type, that's accessible for subsequent accesses using given
     /// `name`.
     ///
     /// If there was no instance with this name before, it will be created and then returned.
+    /// Read-only documents fail with `ReadOnlyDoc` instead.
     ///
     /// If there was an instance with this name, but it was of different type, it will be projected
     /// onto `YText` instance.
-    pub fn get_text(&self, name: &str) -> YText {
-        let shared_ref = self.get_or_insert_text(name);
-        YText::new(SharedCollection::integrated(shared_ref, self.0.clone()))
+    pub fn get_text(&self, name: &str) -> Result<YText> {
+        let shared_ref = self.root(|txn| txn.get_text(name), |doc| doc.get_or_insert_text(name))?;
+        Ok(YText::new(SharedCollection::integrated(shared_ref, self.0.clone())))
     }
 
     /// Returns a `YXmlFragment` shared data type, that's accessible for subsequent accesses using
     /// given `name`.
     ///
     /// If there was no instance with this name before, it will be created and then returned.
+    /// Read-only documents fail with `ReadOnlyDoc` instead.
     ///
     /// If there was an instance with this name, but it was of different type, it will be projected
     /// onto `YXmlFragment` instance.
-    pub fn get_xml_fragment(&self, name: &str) -> YXmlFragment {
-        let shared_ref = self.get_or_insert_xml_fragment(name);
-        YXmlFragment::new_with_collection(SharedCollection::integrated(shared_ref, self.0.clone()))
+    pub fn get_xml_fragment(&self, name: &str) -> Result<YXmlFragment> {
+        let shared_ref = self.root(|txn| txn.get_xml_fragment(name), |doc| doc.get_or_insert_xml_fragment(name))?;
+        Ok(YXmlFragment::new_with_collection(SharedCollection::integrated(shared_ref, self.0.clone())))
+    }
+
+    /// Returns a `YMap` shared data type, that's accessible for subsequent accesses using given
+    /// `name`.
+    ///
+    /// If there was no instance with this name before, it will be created and then returned.
+    /// Read-only documents fail with `ReadOnlyDoc` instead.
+    ///
+    /// If there was an instance with this name, but it was of different type, it will be projected
+    /// onto `YMap` instance.
+    pub fn get_map(&self, name: &str) -> Result<YMap> {
+        let shared_ref = self.root(|txn| txn.get_map(name), |doc| doc.get_or_insert_map(name))?;
+        Ok(YMap::new(SharedCollection::integrated(shared_ref, self.0.clone())))
+    }
+
+    /// Returns a `YArray` shared data type, that's accessible for subsequent accesses using given
+    /// `name`.
+    ///
+    /// If there was no instance with this name before, it will be created and then returned.
+    /// Read-only documents fail with `ReadOnlyDoc` instead.
+    ///
+    /// If there was an instance with this name, but it was of different type, it will be projected
+    /// onto `YArray` instance.
+    pub fn get_array(&self, name: &str) -> Result<YArray> {
+        let shared_ref = self.root(|txn| txn.get_array(name), |doc| doc.get_or_insert_array(name))?;
+        Ok(YArray::new(SharedCollection::integrated(shared_ref, self.0.clone())))
+    }
+
+    /// Subscribes given `observer` to be called whenever a transaction committed on this document
+    /// made any changes. The observer receives an incremental update encoded using lib0 v1
+    /// encoding, which can be sent to remote peers and applied via `apply_update`.
+    pub fn observe_update_v1(&self, observer: Box<dyn YUpdateObserver>) -> Result<Arc<YSubscription>> {
+        let subscription = self
+            .0
+            .observe_update_v1(move |txn, e| {
+                observer.on_update(YUpdateEvent {
+                    update: e.update.clone(),
+                    origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+                });
+            })
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Subscribes given `observer` to be called whenever a transaction committed on this document
+    /// made any changes. The observer receives an incremental update encoded using lib0 v2
+    /// encoding, which can be sent to remote peers and applied via `apply_update_v2`.
+    pub fn observe_update_v2(&self, observer: Box<dyn YUpdateObserver>) -> Result<Arc<YSubscription>> {
+        let subscription = self
+            .0
+            .observe_update_v2(move |txn, e| {
+                observer.on_update(YUpdateEvent {
+                    update: e.update.clone(),
+                    origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+                });
+            })
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Subscribes given `observer` to be called whenever sub-documents of this document have been
+    /// added, removed or requested to be loaded.
+    pub fn observe_subdocs(&self, observer: Box<dyn YSubdocsObserver>) -> Result<Arc<YSubscription>> {
+        let subscription = self
+            .0
+            .observe_subdocs(move |_, e| {
+                observer.on_change(YSubdocsEvent::new(e));
+            })
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Subscribes given `observer` to be called after every transaction committed on this
+    /// document, once all shared type observers have been notified.
+    pub fn observe_after_transaction(
+        &self,
+        observer: Box<dyn YTransactionObserver>,
+    ) -> Result<Arc<YSubscription>> {
+        let subscription = self
+            .0
+            .observe_after_transaction(move |txn| {
+                observer.on_transaction(YTransactionEvent::new(txn));
+            })
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Subscribes given `observer` to be called at the very end of every transaction committed on
+    /// this document, after its internal representation has been cleaned up.
+    pub fn observe_transaction_cleanup(
+        &self,
+        observer: Box<dyn YTransactionObserver>,
+    ) -> Result<Arc<YSubscription>> {
+        let subscription = self
+            .0
+            .observe_transaction_cleanup(move |txn, _| {
+                observer.on_transaction(YTransactionEvent::new(txn));
+            })
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(Arc::new(YSubscription::from(subscription)))
     }
 }
 
@@ -0,0 +1,172 @@
+use crate::array::YArray;
+use crate::attrs::YValue;
+use crate::doc::YDoc;
+use crate::map::YMap;
+use crate::out::YOut;
+use crate::text::YText;
+use crate::tools::{Error, Result};
+use crate::weak::YWeakLink;
+use crate::xml::YXmlChild;
+use crate::xml_elem::YXmlElement;
+use crate::xml_frag::YXmlFragment;
+use crate::xml_text::YXmlText;
+use std::collections::HashSet;
+use std::sync::Arc;
+use yrs::block::{EmbedPrelim, ItemContent, Prelim};
+use yrs::branch::{Branch, BranchPtr};
+use yrs::types::TypeRef;
+use yrs::{ArrayRef, MapRef, TextRef, TransactionMut, WeakRef};
+
+/// Value accepted by insert operations of shared collections. It can be either a plain JSON-like
+/// [YValue] or a preliminary instance of another shared type, which will be integrated into the
+/// document together with all of its contents within the same transaction.
+#[derive(uniffi::Enum)]
+#[derive(Clone)]
+pub enum YIn {
+    Any(YValue),
+    Text(Arc<YText>),
+    Array(Arc<YArray>),
+    Map(Arc<YMap>),
+    XmlElement(Arc<YXmlElement>),
+    XmlFragment(Arc<YXmlFragment>),
+    XmlText(Arc<YXmlText>),
+    Doc(Arc<YDoc>),
+    WeakLink(Arc<YWeakLink>),
+}
+
+/// Set of preliminary shared types, which are about to be integrated by a single operation.
+///
+/// Once integrated, a preliminary instance cannot be integrated again, so each of them can
+/// appear only once among inputs of an operation - including the ones nested in other
+/// preliminary types.
+#[derive(Default)]
+pub(crate) struct PrelimSet(HashSet<usize>);
+
+impl PrelimSet {
+    /// Adds a given shared type to this set, failing if it has already been visited before.
+    pub fn visit<T>(&mut self, value: &Arc<T>) -> Result<()> {
+        if self.0.insert(Arc::as_ptr(value) as usize) {
+            Ok(())
+        } else {
+            Err(Error::DuplicatePrelim)
+        }
+    }
+}
+
+impl YIn {
+    pub fn assert_prelim(&self) -> Result<()> {
+        self.visit_prelim(&mut PrelimSet::default())
+    }
+
+    /// Checks that this value and all shared types nested within it are preliminary and haven't
+    /// been already visited as part of the same operation.
+    pub(crate) fn visit_prelim(&self, visited: &mut PrelimSet) -> Result<()> {
+        match self {
+            YIn::Any(_) => Ok(()),
+            YIn::Doc(v) if v.prelim() => visited.visit(v),
+            YIn::Doc(_) => Err(Error::NotPrelim),
+            YIn::Text(v) if v.prelim()? => visited.visit(v),
+            YIn::Array(v) if v.prelim()? => {
+                visited.visit(v)?;
+                v.visit_prelims(visited)
+            }
+            YIn::Map(v) if v.prelim()? => {
+                visited.visit(v)?;
+                v.visit_prelims(visited)
+            }
+            YIn::WeakLink(v) if v.prelim()? => visited.visit(v),
+            YIn::XmlElement(_) | YIn::XmlFragment(_) | YIn::XmlText(_) => {
+                self.xml_child().unwrap().visit_prelim(visited)
+            }
+            _ => Err(Error::NotPrelim),
+        }
+    }
+
+    /// Checks a list of values, which are going to be inserted by the same operation.
+    pub(crate) fn assert_prelims<'a>(values: impl IntoIterator<Item = &'a YIn>) -> Result<()> {
+        let mut visited = PrelimSet::default();
+        for value in values {
+            value.visit_prelim(&mut visited)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a view over this input value, as it would be returned by read operations.
+    pub fn to_out(&self) -> YOut {
+        match self {
+            YIn::Any(v) => YOut::Any(v.clone()),
+            YIn::Text(v) => YOut::Text(v.clone()),
+            YIn::Array(v) => YOut::Array(v.clone()),
+            YIn::Map(v) => YOut::Map(v.clone()),
+            YIn::XmlElement(v) => YOut::XmlElement(v.clone()),
+            YIn::XmlFragment(v) => YOut::XmlFragment(v.clone()),
+            YIn::XmlText(v) => YOut::XmlText(v.clone()),
+            YIn::Doc(v) => YOut::Doc(v.clone()),
+            YIn::WeakLink(v) => YOut::WeakLink(v.clone()),
+        }
+    }
+
+    fn xml_child(&self) -> Option<YXmlChild> {
+        match self {
+            YIn::XmlElement(v) => Some(YXmlChild::Element(v.clone())),
+            YIn::XmlFragment(v) => Some(YXmlChild::Fragment(v.clone())),
+            YIn::XmlText(v) => Some(YXmlChild::Text(v.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<YXmlChild> for YIn {
+    fn from(value: YXmlChild) -> Self {
+        match value {
+            YXmlChild::Element(v) => YIn::XmlElement(v),
+            YXmlChild::Fragment(v) => YIn::XmlFragment(v),
+            YXmlChild::Text(v) => YIn::XmlText(v),
+        }
+    }
+}
+
+impl Prelim for YIn {
+    type Return = yrs::block::Unused;
+
+    fn into_content(self, txn: &mut TransactionMut) -> (ItemContent, Option<Self>) {
+        let type_ref = match &self {
+            YIn::Any(v) => return (ItemContent::Any(vec![v.into()]), None),
+            YIn::Doc(v) => return (ItemContent::Doc(None, (*v.0).clone()), None),
+            YIn::Text(_) => TypeRef::Text,
+            YIn::Array(_) => TypeRef::Array,
+            YIn::Map(_) => TypeRef::Map,
+            // inputs are checked with `assert_prelim` before being inserted, which also rejects
+            // the same link being inserted twice
+            YIn::WeakLink(v) => TypeRef::WeakLink(v.prelim_source().unwrap()),
+            YIn::XmlElement(_) | YIn::XmlFragment(_) | YIn::XmlText(_) => {
+                let child = self.xml_child().unwrap();
+                let (content, _) = child.into_content(txn);
+                return (content, Some(self));
+            }
+        };
+        (ItemContent::Type(Branch::new(type_ref)), Some(self))
+    }
+
+    fn integrate(self, txn: &mut TransactionMut, inner_ref: BranchPtr) {
+        match self {
+            YIn::Text(v) => v.integrate(txn, TextRef::from(inner_ref)),
+            YIn::Array(v) => v.integrate(txn, ArrayRef::from(inner_ref)),
+            YIn::Map(v) => v.integrate(txn, MapRef::from(inner_ref)),
+            YIn::WeakLink(v) => v.integrate(txn, WeakRef::from(inner_ref)),
+            YIn::XmlElement(_) | YIn::XmlFragment(_) | YIn::XmlText(_) => {
+                self.xml_child().unwrap().integrate(txn, inner_ref)
+            }
+            YIn::Any(_) | YIn::Doc(_) => { /* do nothing */ }
+        }
+    }
+}
+
+impl From<YIn> for EmbedPrelim<YIn> {
+    fn from(value: YIn) -> Self {
+        match value {
+            YIn::Any(v) => EmbedPrelim::Primitive(v.into()),
+            other => EmbedPrelim::Shared(other),
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::attrs::YValue;
+use crate::tools::Result;
+use serde_json::{Map, Number, Value};
+use yrs::branch::BranchPtr;
+use yrs::types::text::{Diff, YChange};
+use yrs::{
+    Any, Array, ArrayRef, Doc, Map as _, MapRef, Out, ReadTxn, Text, TextRef, WeakRef, Xml, XmlFragment,
+    XmlFragmentRef, XmlOut,
+};
+
+/// Alphabet of a standard (RFC 4648) base64 encoding.
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Key of a single-entry JSON object used to represent a binary buffer as a base64 string.
+const BUFFER_KEY: &str = "$buffer";
+
+/// Biggest integer, which can be represented by a 64-bit float without a loss of precision.
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+/// Converts contents of shared types into a plain JSON, using the mapping described by
+/// [YDoc::to_json](crate::doc::YDoc::to_json). Unlike [yvalue_to_json], the result cannot be
+/// converted back into the same values.
+pub(crate) struct JsonEncoder<'a, T: ReadTxn> {
+    txn: &'a T,
+    include_formatting: bool,
+}
+
+impl<'a, T: ReadTxn> JsonEncoder<'a, T> {
+    pub fn new(txn: &'a T, include_formatting: bool) -> Self {
+        JsonEncoder {
+            txn,
+            include_formatting,
+        }
+    }
+
+    /// Returns a JSON object with all root types of a document, keyed by their names.
+    pub fn doc(&self) -> Value {
+        let mut result = Map::new();
+        for (name, value) in self.txn.root_refs() {
+            let value = match value {
+                // root type which has only been received from a remote peer, but never defined
+                // locally: its contents can still be read either as a map or a sequence
+                Out::UndefinedRef(branch) => self.undefined(branch),
+                value => self.out(&value),
+            };
+            result.insert(name.to_string(), value);
+        }
+        Value::Object(result)
+    }
+
+    pub fn out(&self, value: &Out) -> Value {
+        match value {
+            Out::Any(v) => any_to_json(v),
+            Out::YText(v) => self.text(v),
+            Out::YArray(v) => self.array(v),
+            Out::YMap(v) => self.map(v),
+            Out::YXmlElement(v) => self.xml(&XmlOut::Element(v.clone())),
+            Out::YXmlFragment(v) => self.xml(&XmlOut::Fragment(v.clone())),
+            Out::YXmlText(v) => self.text(v),
+            Out::YDoc(v) => guid(v),
+            Out::YWeakLink(v) => self.weak(v),
+            Out::UndefinedRef(_) => Value::Null,
+        }
+    }
+
+    pub fn text<X: Text>(&self, text: &X) -> Value {
+        let delta: Vec<Diff<YChange>> = text.diff(self.txn, |change| change);
+        if self.include_formatting {
+            Value::Array(delta.iter().map(|d| self.chunk(d)).collect())
+        } else {
+            let mut result = String::new();
+            for d in delta.iter() {
+                if let Out::Any(Any::String(s)) = &d.insert {
+                    result.push_str(s);
+                }
+            }
+            Value::String(result)
+        }
+    }
+
+    pub fn array(&self, array: &ArrayRef) -> Value {
+        Value::Array(array.iter(self.txn).map(|v| self.out(&v)).collect())
+    }
+
+    pub fn map(&self, map: &MapRef) -> Value {
+        let mut result = Map::new();
+        for (key, value) in map.iter(self.txn) {
+            result.insert(key.to_string(), self.out(&value));
+        }
+        Value::Object(result)
+    }
+
+    pub fn xml(&self, node: &XmlOut) -> Value {
+        match node {
+            XmlOut::Element(v) => {
+                let mut attributes = Map::new();
+                for (name, value) in v.attributes(self.txn) {
+                    attributes.insert(name.to_string(), self.out(&value));
+                }
+                let mut result = Map::new();
+                result.insert("nodeName".to_string(), Value::String(v.tag().to_string()));
+                result.insert("attributes".to_string(), Value::Object(attributes));
+                result.insert("children".to_string(), self.children(v));
+                Value::Object(result)
+            }
+            XmlOut::Fragment(v) => {
+                let mut result = Map::new();
+                result.insert("children".to_string(), self.children(v));
+                Value::Object(result)
+            }
+            XmlOut::Text(v) => self.text(v),
+        }
+    }
+
+    pub fn weak(&self, link: &WeakRef<BranchPtr>) -> Value {
+        match link.try_source() {
+            None => Value::Null,
+            Some(source) if source.is_single() => {
+                let link: WeakRef<MapRef> = WeakRef::from(link.clone());
+                match link.try_deref_value(self.txn) {
+                    None => Value::Null,
+                    Some(value) => self.out(&value),
+                }
+            }
+            Some(_) => {
+                let link: WeakRef<ArrayRef> = WeakRef::from(link.clone());
+                Value::Array(link.unquote(self.txn).map(|v| self.out(&v)).collect())
+            }
+        }
+    }
+
+    fn children<X: XmlFragment>(&self, node: &X) -> Value {
+        Value::Array(node.children(self.txn).map(|c| self.xml(&c)).collect())
+    }
+
+    fn chunk(&self, diff: &Diff<YChange>) -> Value {
+        let mut result = Map::new();
+        result.insert("insert".to_string(), self.out(&diff.insert));
+        if let Some(attrs) = &diff.attributes {
+            let mut attributes = Map::new();
+            for (k, v) in attrs.iter() {
+                attributes.insert(k.to_string(), any_to_json(v));
+            }
+            result.insert("attributes".to_string(), Value::Object(attributes));
+        }
+        Value::Object(result)
+    }
+
+    fn undefined(&self, branch: BranchPtr) -> Value {
+        let map = MapRef::from(branch);
+        if map.len(self.txn) > 0 {
+            return self.map(&map);
+        }
+        let values: Vec<Out> = ArrayRef::from(branch).iter(self.txn).collect();
+        let is_text = |v: &Out| matches!(v, Out::Any(Any::String(_)));
+        let is_xml = |v: &Out| matches!(v, Out::YXmlElement(_) | Out::YXmlText(_));
+        if !values.is_empty() && values.iter().all(is_text) {
+            self.text(&TextRef::from(branch))
+        } else if !values.is_empty() && values.iter().all(is_xml) {
+            self.xml(&XmlOut::Fragment(XmlFragmentRef::from(branch)))
+        } else {
+            Value::Array(values.iter().map(|v| self.out(v)).collect())
+        }
+    }
+}
+
+pub(crate) fn any_to_json(value: &Any) -> Value {
+    match value {
+        Any::Null | Any::Undefined => Value::Null,
+        Any::Bool(v) => Value::Bool(*v),
+        // integral numbers are written without a fraction, the same way JavaScript does it
+        Any::Number(v) if v.fract() == 0.0 && v.abs() <= MAX_SAFE_INTEGER => {
+            Value::Number(Number::from(*v as i64))
+        }
+        // NaN and infinities have no JSON representation
+        Any::Number(v) => Number::from_f64(*v).map(Value::Number).unwrap_or(Value::Null),
+        Any::BigInt(v) => Value::Number(Number::from(*v)),
+        Any::String(v) => Value::String(v.to_string()),
+        Any::Buffer(v) => Value::String(base64_encode(v)),
+        Any::Array(v) => Value::Array(v.iter().map(any_to_json).collect()),
+        Any::Map(v) => Value::Object(v.iter().map(|(k, v)| (k.clone(), any_to_json(v))).collect()),
+    }
+}
+
+/// Parses a given JSON string into a [YValue]. This conversion is lossless when used together
+/// with [yvalue_to_json], which uses different conventions than JSON export of shared types
+/// (`to_json` methods):
+///
+/// - Numbers written with a fraction or an exponent (eg. `2.0`, `1e3`) are parsed as `Number`,
+///   while integers (eg. `2`) are parsed as `BigInt`.
+/// - Objects with a single `$buffer` field containing a base64 string are parsed as `Buffer`.
+/// - Object keys starting with `$$` are unescaped by removing the first `$`.
+#[uniffi::export]
+pub fn yvalue_from_json(json: String) -> Result<YValue> {
+    let value: Value = serde_json::from_str(&json)?;
+    Ok(json_to_yvalue(value))
+}
+
+/// Serializes a given [YValue] into a JSON string, which can be parsed back using
+/// [yvalue_from_json]. `Number`s are always written with a fraction (eg. `2.0`), `BigInt`s as
+/// integers and `Buffer`s as `{"$buffer": "<base64>"}` objects. Keys of `AttrMap`s starting
+/// with `$` are escaped with another `$`, so that they cannot be confused with buffers. Numbers
+/// which have no JSON representation (NaN and infinities) are written as `null`.
+///
+/// Unlike this function, `to_json` methods of shared types produce plain JSON: buffers are
+/// written as bare base64 strings and integral numbers without a fraction.
+#[uniffi::export]
+pub fn yvalue_to_json(value: YValue) -> String {
+    yvalue_to_json_value(&value).to_string()
+}
+
+fn json_to_yvalue(value: Value) -> YValue {
+    match value {
+        Value::Null => YValue::Null,
+        Value::Bool(v) => YValue::Bool(v),
+        Value::Number(v) => match v.as_i64() {
+            Some(v) => YValue::BigInt(v),
+            None => YValue::Number(v.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(v) => YValue::String(v),
+        Value::Array(v) => YValue::Array(v.into_iter().map(json_to_yvalue).collect()),
+        Value::Object(v) => {
+            if v.len() == 1 {
+                if let Some(Value::String(data)) = v.get(BUFFER_KEY) {
+                    if let Some(buffer) = base64_decode(data) {
+                        return YValue::Buffer(buffer);
+                    }
+                }
+            }
+            let entries = v.into_iter().map(|(k, v)| {
+                let key = match k.strip_prefix('$') {
+                    Some(unescaped) if unescaped.starts_with('$') => unescaped.to_string(),
+                    _ => k,
+                };
+                (key, json_to_yvalue(v))
+            });
+            YValue::AttrMap(entries.collect())
+        }
+    }
+}
+
+fn yvalue_to_json_value(value: &YValue) -> Value {
+    match value {
+        YValue::Null => Value::Null,
+        YValue::Bool(v) => Value::Bool(*v),
+        YValue::Number(v) => Number::from_f64(*v).map(Value::Number).unwrap_or(Value::Null),
+        YValue::BigInt(v) => Value::Number(Number::from(*v)),
+        YValue::String(v) => Value::String(v.clone()),
+        YValue::Buffer(v) => {
+            let mut result = Map::new();
+            result.insert(BUFFER_KEY.to_string(), Value::String(base64_encode(v)));
+            Value::Object(result)
+        }
+        YValue::Array(v) => Value::Array(v.iter().map(yvalue_to_json_value).collect()),
+        YValue::AttrMap(v) => Value::Object(
+            v.iter()
+                .map(|(k, v)| {
+                    let key = if k.starts_with('$') { format!("${k}") } else { k.clone() };
+                    (key, yvalue_to_json_value(v))
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn guid(doc: &Doc) -> Value {
+    let mut result = Map::new();
+    result.insert("guid".to_string(), Value::String(doc.guid().to_string()));
+    Value::Object(result)
+}
+
+/// Encodes binary `data` using a standard, padded base64 encoding.
+fn base64_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Decodes a standard, padded base64 string. Returns `None` if it's not a valid base64.
+fn base64_decode(data: &str) -> Option<Vec<u8>> {
+    let data = data.as_bytes();
+    if !data.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut result = Vec::with_capacity(data.len() / 4 * 3);
+    for (i, chunk) in data.chunks(4).enumerate() {
+        let last = i == data.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let digit = BASE64.iter().position(|&d| d == c)?;
+            n = n << 6 | digit as u32;
+        }
+        n <<= 6 * padding as u32;
+        result.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(result)
+}
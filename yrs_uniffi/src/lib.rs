@@ -1,6 +1,13 @@
+mod array;
+mod awareness;
 mod attrs;
 mod collection;
 mod doc;
+mod input;
+mod json;
+mod map;
+mod out;
+mod persistence;
 mod snapshots;
 mod text;
 mod tools;
@@ -10,5 +17,13 @@ mod xml_frag;
 mod xml_elem;
 mod xml_text;
 mod xml;
+mod xml_diff;
+mod xml_markup;
+mod weak;
+mod subscription;
+mod undo;
+mod sync;
+mod sticky;
+mod lock;
 
 uniffi::setup_scaffolding!();
@@ -0,0 +1,50 @@
+use crate::tools::{Error, Result};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+/// A state of an object exposed over FFI, which may be accessed concurrently from many foreign
+/// threads.
+///
+/// Exported methods never wait for a lock: if the state is already borrowed in a conflicting way -
+/// either by another thread or by a callback reentering the same object - [Error::AnotherTx] is
+/// returned instead, matching how Yrs reports contended document transactions.
+pub struct TryLock<T>(RwLock<T>);
+
+impl<T> TryLock<T> {
+    pub fn new(value: T) -> Self {
+        TryLock(RwLock::new(value))
+    }
+
+    /// Acquires shared access to the underlying state. Many readers may hold it at the same time.
+    pub fn borrow(&self) -> Result<RwLockReadGuard<'_, T>> {
+        match self.0.try_read() {
+            Ok(guard) => Ok(guard),
+            // a panic caught at the FFI boundary must not render an object unusable
+            Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(Error::AnotherTx),
+        }
+    }
+
+    /// Acquires exclusive access to the underlying state.
+    pub fn borrow_mut(&self) -> Result<RwLockWriteGuard<'_, T>> {
+        match self.0.try_write() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(Error::AnotherTx),
+        }
+    }
+
+    /// Acquires exclusive access to the underlying state, waiting for other borrowers to finish.
+    ///
+    /// Only meant for integrating preliminary objects from within Yrs callbacks, which cannot
+    /// report errors. Such objects are not borrowed by the calling thread at that point and other
+    /// threads only hold them for the duration of a single preliminary operation.
+    pub fn lock_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Acquires shared access to the underlying state, waiting for an exclusive borrower to
+    /// finish. See [TryLock::lock_mut] for when it's safe to use.
+    pub fn lock(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,261 @@
+use crate::doc::DocRef;
+use crate::collection::{Integrated, SharedCollection, Writable};
+use crate::input::{PrelimSet, YIn};
+use crate::json::JsonEncoder;
+use crate::out::YOut;
+use crate::tools::{Error, Result};
+use crate::transaction::YTransaction;
+use crate::weak::YWeakLink;
+use crate::lock::TryLock;
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
+use yrs::types::TYPE_REFS_MAP;
+use yrs::branch::BranchPtr;
+use yrs::{Map, MapRef, ReadTxn, TransactionMut};
+
+/// Collection used to store key-value entries in an unordered manner. Keys are always represented
+/// as UTF-8 strings. Values can be any value type supported by Yrs: JSON-like primitives as well as
+/// shared data types.
+///
+/// In terms of conflict resolution, [YMap] uses logical last-write-wins principle, meaning the past
+/// updates are automatically overridden and discarded by newer ones, while concurrent updates made
+/// by different peers are resolved into a single value using document id seniority to establish
+/// order.
+#[derive(uniffi::Object)]
+#[repr(transparent)]
+pub struct YMap {
+    inner: Arc<TryLock<SharedCollection<HashMap<String, YIn>, MapRef>>>,
+}
+
+impl YMap {
+    pub fn new(init: SharedCollection<HashMap<String, YIn>, MapRef>) -> Self {
+        YMap {
+            inner: Arc::new(TryLock::new(init)),
+        }
+    }
+
+    pub(crate) fn branch<T: ReadTxn>(&self, txn: &T) -> Result<BranchPtr> {
+        self.inner.borrow()?.branch(txn)
+    }
+
+    /// Visits all shared types nested within this preliminary map.
+    pub(crate) fn visit_prelims(&self, visited: &mut PrelimSet) -> Result<()> {
+        if let SharedCollection::Prelim(entries) = self.inner.borrow()?.deref() {
+            for value in entries.values() {
+                value.visit_prelim(visited)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn integrate(&self, txn: &mut TransactionMut, map_ref: MapRef) {
+        let doc = DocRef::from(txn.doc().clone());
+
+        let old_value = {
+            let mut guard = self.inner.lock_mut();
+            mem::replace(&mut *guard, SharedCollection::Integrated(Integrated::new(
+                map_ref.clone(),
+                doc,
+            )))
+        };
+
+        if let SharedCollection::Prelim(raw) = old_value {
+            for (key, value) in raw {
+                map_ref.insert(txn, key, value);
+            }
+        }
+    }
+}
+
+#[uniffi::export]
+impl YMap {
+    /// Creates a new preliminary instance of a `YMap` shared data type, with its state
+    /// initialized to provided parameter.
+    ///
+    /// Preliminary instances can be nested into other shared data types such as `YArray` and `YMap`.
+    /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
+    /// document store and cannot be nested again: attempt to do so will result in an exception.
+    #[uniffi::constructor(default(init=None))]
+    pub fn new_with_entries(init: Option<HashMap<String, YIn>>) -> Result<Self> {
+        let entries = init.unwrap_or_default();
+        YIn::assert_prelims(entries.values())?;
+        Ok(YMap::new(SharedCollection::prelim(entries)))
+    }
+
+    #[inline]
+    pub fn get_type(&self) -> u8 {
+        TYPE_REFS_MAP
+    }
+
+    /// Returns true if this is a preliminary instance of `YMap`.
+    ///
+    /// Preliminary instances can be nested into other shared data types such as `YArray` and `YMap`.
+    /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
+    /// document store and cannot be nested again: attempt to do so will result in an exception.
+    pub fn prelim(&self) -> Result<bool> {
+        Ok(self.inner.borrow()?.is_prelim())
+    }
+
+    /// Checks if current YMap reference is alive and has not been deleted by its parent collection.
+    /// This method only works on already integrated shared types and will return false is current
+    /// type is preliminary (has not been integrated into document).
+    pub fn alive(&self, txn: &YTransaction) -> Result<bool> {
+        self.inner.borrow()?.is_alive(txn)
+    }
+
+    /// Returns a number of entries stored within this instance of `YMap`.
+    #[uniffi::method(default(txn=None))]
+    pub fn length(&self, txn: Option<Arc<YTransaction>>) -> Result<u32> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.len() as u32),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.len(txn))),
+        }
+    }
+
+    /// Returns entries of this integrated `YMap` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<String> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).map(c).to_string())
+            }),
+        }
+    }
+
+    /// Sets a given `key`-`value` entry within this instance of `YMap`. If another entry was
+    /// already stored under given `key`, it will be overridden with new `value`.
+    #[uniffi::method(default(txn=None))]
+    pub fn set(&self, key: &str, value: YIn, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        value.assert_prelim()?;
+
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                c.insert(key.to_string(), value);
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                c.insert(txn, key, value);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Removes an entry identified by a given `key` from this instance of `YMap`, if such exists.
+    #[uniffi::method(default(txn=None))]
+    pub fn remove(&self, key: &str, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                c.remove(key);
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                c.remove(txn, key);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Returns value of an entry stored under given `key` within this instance of `YMap`,
+    /// or `null` if no such entry existed.
+    #[uniffi::method(default(txn=None))]
+    pub fn get(&self, key: &str, txn: Option<Arc<YTransaction>>) -> Result<Option<YOut>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.get(key).map(|v| v.to_out())),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(c.get(txn, key).map(|out| YOut::from_out(out, txn.doc().clone())))
+            }),
+        }
+    }
+
+    /// Checks if an entry with a given `key` exists within this instance of `YMap`.
+    #[uniffi::method(default(txn=None))]
+    pub fn contains_key(&self, key: &str, txn: Option<Arc<YTransaction>>) -> Result<bool> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.contains_key(key)),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.contains_key(txn, key))),
+        }
+    }
+
+    /// Returns a list of keys of all entries stored within this instance of `YMap`, in
+    /// unspecified order.
+    #[uniffi::method(default(txn=None))]
+    pub fn keys(&self, txn: Option<Arc<YTransaction>>) -> Result<Vec<String>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.keys().cloned().collect()),
+            SharedCollection::Integrated(c) => {
+                c.readonly(txn, |c, txn| Ok(c.keys(txn).map(|k| k.to_string()).collect()))
+            }
+        }
+    }
+
+    /// Returns a list of values of all entries stored within this instance of `YMap`, in
+    /// unspecified order.
+    #[uniffi::method(default(txn=None))]
+    pub fn values(&self, txn: Option<Arc<YTransaction>>) -> Result<Vec<YOut>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c.values().map(|v| v.to_out()).collect()),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let doc = txn.doc().clone();
+                Ok(c.iter(txn)
+                    .map(|(_, value)| YOut::from_out(value, doc.clone()))
+                    .collect())
+            }),
+        }
+    }
+
+    /// Returns all entries stored within this instance of `YMap`.
+    #[uniffi::method(default(txn=None))]
+    pub fn entries(&self, txn: Option<Arc<YTransaction>>) -> Result<HashMap<String, YOut>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(c
+                .iter()
+                .map(|(k, v)| (k.clone(), v.to_out()))
+                .collect()),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let doc = txn.doc().clone();
+                Ok(c.iter(txn)
+                    .map(|(key, value)| (key.to_string(), YOut::from_out(value, doc.clone())))
+                    .collect())
+            }),
+        }
+    }
+
+    /// Removes all entries from this instance of `YMap`.
+    #[uniffi::method(default(txn=None))]
+    pub fn clear(&self, txn: Option<Arc<YTransaction>>) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                c.clear();
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                c.clear(txn);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Returns a preliminary `YWeakLink` to an entry stored under a given `key`. Fails with
+    /// `KeyNotFound` if no such entry exists. The link always points to the most recent value of that entry and can
+    /// be inserted into any other shared collection.
+    ///
+    /// This method only works on `YMap` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn link(&self, key: &str, txn: Option<Arc<YTransaction>>) -> Result<Arc<YWeakLink>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| match c.link(txn, key) {
+                Some(prelim) => Ok(Arc::new(YWeakLink::from_prelim(prelim))),
+                None => Err(Error::KeyNotFound { key: key.to_string() }),
+            }),
+        }
+    }
+}
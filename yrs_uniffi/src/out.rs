@@ -0,0 +1,65 @@
+use crate::array::YArray;
+use crate::attrs::{into_yvalue, YValue};
+use crate::collection::SharedCollection;
+use crate::doc::{DocRef, YDoc};
+use crate::map::YMap;
+use crate::text::YText;
+use crate::weak::YWeakLink;
+use crate::xml_elem::YXmlElement;
+use crate::xml_frag::YXmlFragment;
+use crate::xml_text::YXmlText;
+use crate::tools::{Error, Result};
+use std::sync::Arc;
+use yrs::branch::BranchPtr;
+use yrs::{Out, ReadTxn};
+
+/// Value returned from read operations over shared collections. It can be either a plain
+/// JSON-like [YValue] or a handle to another shared type integrated into the document.
+#[derive(uniffi::Enum)]
+#[derive(Clone)]
+pub enum YOut {
+    Any(YValue),
+    Text(Arc<YText>),
+    Array(Arc<YArray>),
+    Map(Arc<YMap>),
+    XmlElement(Arc<YXmlElement>),
+    XmlFragment(Arc<YXmlFragment>),
+    XmlText(Arc<YXmlText>),
+    Doc(Arc<YDoc>),
+    WeakLink(Arc<YWeakLink>),
+    /// Shared collection of a type, which has not been defined locally.
+    UndefinedRef,
+}
+
+impl YOut {
+    pub fn from_out(value: Out, doc: DocRef) -> Self {
+        match value {
+            Out::Any(v) => YOut::Any(into_yvalue(&v)),
+            Out::YText(v) => YOut::Text(Arc::new(YText::new(SharedCollection::integrated(v, doc)))),
+            Out::YArray(v) => YOut::Array(Arc::new(YArray::new(SharedCollection::integrated(v, doc)))),
+            Out::YMap(v) => YOut::Map(Arc::new(YMap::new(SharedCollection::integrated(v, doc)))),
+            Out::YXmlElement(v) => YOut::XmlElement(Arc::new(YXmlElement::from_ref(v, doc))),
+            Out::YXmlFragment(v) => YOut::XmlFragment(Arc::new(YXmlFragment::from_ref(v, doc))),
+            Out::YXmlText(v) => YOut::XmlText(Arc::new(YXmlText::from_ref(v, doc))),
+            Out::YDoc(v) => YOut::Doc(Arc::new(YDoc::from(v))),
+            Out::YWeakLink(v) => YOut::WeakLink(Arc::new(YWeakLink::from_ref(v, doc))),
+            Out::UndefinedRef(_) => YOut::UndefinedRef,
+        }
+    }
+
+    /// Returns a pointer to the branch of an integrated shared type referenced by this value.
+    pub(crate) fn branch<T: ReadTxn>(&self, txn: &T) -> Result<BranchPtr> {
+        match self {
+            YOut::Text(v) => v.branch(txn),
+            YOut::Array(v) => v.branch(txn),
+            YOut::Map(v) => v.branch(txn),
+            YOut::XmlElement(v) => v.0.borrow()?.branch(txn),
+            YOut::XmlFragment(v) => v.0.borrow()?.branch(txn),
+            YOut::XmlText(v) => v.0.borrow()?.branch(txn),
+            YOut::WeakLink(v) => v.0.borrow()?.branch(txn),
+            YOut::Any(_) | YOut::Doc(_) | YOut::UndefinedRef => {
+                Err(Error::InvalidData("expected a shared type".to_string()))
+            }
+        }
+    }
+}
@@ -0,0 +1,538 @@
+use crate::doc::{DocRef, YDoc};
+use crate::subscription::YSubscription;
+use crate::tools::{Error, Result};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use yrs::updates::decoder::Decode;
+use yrs::{ReadTxn, StateVector, Subscription, Transact, TransactionMut, Update};
+
+/// Header identifying update log files and a version of their format.
+const HEADER: &[u8; 8] = b"YRSLOG01";
+
+/// Default number of updates written to a storage by `YPersistence`, after which all of them are
+/// replaced with a single snapshot.
+const DEFAULT_COMPACTION_INTERVAL: u32 = 100;
+
+/// Size of a record header: length of its payload followed by its CRC-32 checksum, both stored
+/// as little endian 32-bit integers.
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Default size of a log file (in bytes), after which it gets compacted.
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+
+/// Append-only file log of document updates, used to persist a `YDoc` on a local disk.
+///
+/// Each incremental update (encoded using lib0 v2 encoding) is stored as a separate record,
+/// prefixed with its length and a CRC-32 checksum. A record which has not been written
+/// completely - ie. because an application has been killed in the middle of a write - is
+/// detected and truncated once the log is opened again.
+///
+/// Once a log file grows over a compaction threshold, all of its records are merged into
+/// a single update. To avoid compacting on every write when a document itself is bigger than
+/// the threshold, log is compacted again only after it doubled its size since the last
+/// compaction.
+///
+/// If writing an update received by an observer fails, the next observed change rewrites the log
+/// with a full state of a document. Until that succeeds, `in_sync` returns false.
+///
+/// A basic workflow sample:
+///
+/// ```kotlin
+/// val store = YUpdateStore("/path/to/doc.ylog")
+/// val doc = YDoc()
+/// store.load(doc)
+/// val subscription = store.observe(doc)
+/// ```
+#[derive(uniffi::Object)]
+pub struct YUpdateStore(Mutex<UpdateLog>);
+
+struct UpdateLog {
+    path: PathBuf,
+    file: File,
+    /// Length of a log file, which is also an end of its last complete record.
+    len: u64,
+    records: u32,
+    compaction_threshold: u64,
+    /// Length of a log file right after it has been compacted for the last time.
+    compacted_len: u64,
+    /// Set when some of the updates received by an observer could not have been written.
+    out_of_sync: bool,
+    /// The most recent failure of writing to a log, which could not have been reported at that
+    /// time.
+    last_error: Option<Error>,
+}
+
+#[uniffi::export]
+impl YUpdateStore {
+    /// Opens an update log stored in a file under a given `path`, creating it if it doesn't exist.
+    /// Incomplete or corrupted records found at the end of a file are truncated.
+    ///
+    /// `compaction_threshold` is a file size (in bytes) over which log is compacted into a single
+    /// update. By default it's 1MiB.
+    #[uniffi::constructor(default(compaction_threshold=None))]
+    pub fn new(path: String, compaction_threshold: Option<u64>) -> Result<Self> {
+        let log = UpdateLog::open(
+            PathBuf::from(path),
+            compaction_threshold.unwrap_or(DEFAULT_COMPACTION_THRESHOLD),
+        )?;
+        Ok(YUpdateStore(Mutex::new(log)))
+    }
+
+    /// Applies all updates stored in this log to a given `doc`. It should be called before
+    /// `observe`, as otherwise updates loaded into a document would be stored again.
+    pub fn load(&self, doc: &YDoc) -> Result<()> {
+        let updates = self.log().read_updates()?;
+        let mut txn = doc.0.transact_mut()?;
+        for update in updates {
+            txn.apply_update(Update::decode_v2(&update)?)?;
+        }
+        Ok(())
+    }
+
+    /// Appends an `update` encoded using lib0 v2 encoding to this log, compacting it if it has
+    /// grown over the compaction threshold. A failed compaction doesn't fail this method, as the
+    /// update has been already written: it's reported by `last_error` instead.
+    pub fn append(&self, update: Vec<u8>) -> Result<()> {
+        self.log().append(&update)
+    }
+
+    /// Subscribes this log to updates of a given `doc`, appending every committed change. Returned
+    /// subscription keeps the log attached: drop it or call `unsubscribe` to stop writing.
+    pub fn observe(self: Arc<Self>, doc: &YDoc) -> Result<Arc<YSubscription>> {
+        let subscription = doc
+            .0
+            .observe_update_v2(move |txn, e| {
+                let mut log = self.log();
+                let result = if log.out_of_sync {
+                    // some updates have been lost, so a full document state is stored instead
+                    log.rewrite(&txn.encode_state_as_update_v2(&StateVector::default()))
+                } else {
+                    log.append(&e.update)
+                };
+                match result {
+                    Ok(()) => log.out_of_sync = false,
+                    Err(e) => {
+                        log.out_of_sync = true;
+                        log.last_error = Some(e);
+                    }
+                }
+            })
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Checks if all changes received by observers registered via `observe` have been written.
+    pub fn in_sync(&self) -> bool {
+        !self.log().out_of_sync
+    }
+
+    /// Returns a message of the most recent failure of writing changes received by an observer
+    /// or of compacting this log automatically, if any happened.
+    pub fn last_error(&self) -> Option<String> {
+        self.log().last_error.as_ref().map(|e| e.to_string())
+    }
+
+    /// Merges all updates stored in this log into a single one, regardless of the compaction
+    /// threshold.
+    pub fn compact(&self) -> Result<()> {
+        self.log().compact()
+    }
+
+    /// Returns a number of records currently stored in this log.
+    pub fn records(&self) -> u32 {
+        self.log().records
+    }
+
+    /// Returns a current size of a log file in bytes.
+    pub fn size(&self) -> u64 {
+        self.log().len
+    }
+}
+
+impl YUpdateStore {
+    fn log(&self) -> MutexGuard<'_, UpdateLog> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl UpdateLog {
+    fn open(path: PathBuf, compaction_threshold: u64) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let (len, records) = if HEADER.starts_with(&data) {
+            // empty file or a header, which has not been completely written
+            file.set_len(0)?;
+            file.write_all(HEADER)?;
+            file.sync_data()?;
+            (HEADER.len() as u64, 0)
+        } else if !data.starts_with(HEADER) {
+            return Err(Error::InvalidData(format!(
+                "{} is not an update log",
+                path.display()
+            )));
+        } else {
+            let records = read_records(&data);
+            let len = records.last().map(|r| r.end).unwrap_or(HEADER.len());
+            if len < data.len() {
+                // drop a torn tail, so that new records are not appended after it
+                file.set_len(len as u64)?;
+                file.sync_data()?;
+            }
+            (len as u64, records.len() as u32)
+        };
+
+        Ok(UpdateLog {
+            path,
+            file,
+            len,
+            records,
+            compaction_threshold,
+            compacted_len: len,
+            out_of_sync: false,
+            last_error: None,
+        })
+    }
+
+    fn read_updates(&self) -> Result<Vec<Vec<u8>>> {
+        let data = fs::read(&self.path)?;
+        let data = &data[..(self.len as usize).min(data.len())];
+        Ok(read_records(data)
+            .into_iter()
+            .map(|r| data[r.start..r.end].to_vec())
+            .collect())
+    }
+
+    fn append(&mut self, update: &[u8]) -> Result<()> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + update.len());
+        record.extend_from_slice(&(update.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(update).to_le_bytes());
+        record.extend_from_slice(update);
+
+        let written = self.file.write_all(&record).and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            // best effort: don't leave a partially written record behind
+            let _ = self.file.set_len(self.len);
+            return Err(e.into());
+        }
+        self.len += record.len() as u64;
+        self.records += 1;
+
+        if self.len >= self.compaction_threshold && self.len >= 2 * self.compacted_len {
+            // the update itself is already stored, so a failed compaction can be retried later
+            if let Err(e) = self.compact() {
+                self.last_error = Some(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        if self.records <= 1 {
+            return Ok(());
+        }
+        let merged = yrs::merge_updates_v2(self.read_updates()?)?;
+        self.rewrite(&merged)
+    }
+
+    /// Replaces all records of this log with a single `update`.
+    fn rewrite(&mut self, update: &[u8]) -> Result<()> {
+        // write new log aside and replace the original one only once it's complete
+        let tmp_path = tmp_path(&self.path);
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(HEADER)?;
+        tmp.write_all(&(update.len() as u32).to_le_bytes())?;
+        tmp.write_all(&crc32(update).to_le_bytes())?;
+        tmp.write_all(update)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent(&self.path)?;
+
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        self.len = (HEADER.len() + RECORD_HEADER_LEN + update.len()) as u64;
+        self.records = 1;
+        self.compacted_len = self.len;
+        Ok(())
+    }
+}
+
+/// Storage of document updates, which can be implemented by a host application (eg. on top of
+/// SQLite or a key-value store) and bound to a document using `YPersistence`. Documents are
+/// identified by `doc_id`. All updates are encoded using lib0 v2 encoding.
+///
+/// Implementations may be called from any thread, but never concurrently for the same document.
+#[uniffi::export(with_foreign)]
+pub trait YStorage: Send + Sync {
+    /// Appends an incremental `update` to updates stored for a given document.
+    fn put_update(&self, doc_id: String, update: Vec<u8>) -> Result<()>;
+
+    /// Returns all updates stored for a given document, in order of their insertion. Returns an
+    /// empty list if nothing has been stored for that document yet.
+    fn get_updates(&self, doc_id: String) -> Result<Vec<Vec<u8>>>;
+
+    /// Replaces all updates stored for a given document with a single `snapshot` update,
+    /// containing a full state of a document.
+    fn replace_with_snapshot(&self, doc_id: String, snapshot: Vec<u8>) -> Result<()>;
+
+    /// Removes all updates stored for a given document.
+    fn delete_doc(&self, doc_id: String) -> Result<()>;
+}
+
+/// Reference implementation of [YStorage] keeping all updates in memory.
+#[derive(Default)]
+struct MemoryStorage(Mutex<HashMap<String, Vec<Vec<u8>>>>);
+
+impl MemoryStorage {
+    fn docs(&self) -> MutexGuard<'_, HashMap<String, Vec<Vec<u8>>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl YStorage for MemoryStorage {
+    fn put_update(&self, doc_id: String, update: Vec<u8>) -> Result<()> {
+        self.docs().entry(doc_id).or_default().push(update);
+        Ok(())
+    }
+
+    fn get_updates(&self, doc_id: String) -> Result<Vec<Vec<u8>>> {
+        Ok(self.docs().get(&doc_id).cloned().unwrap_or_default())
+    }
+
+    fn replace_with_snapshot(&self, doc_id: String, snapshot: Vec<u8>) -> Result<()> {
+        self.docs().insert(doc_id, vec![snapshot]);
+        Ok(())
+    }
+
+    fn delete_doc(&self, doc_id: String) -> Result<()> {
+        self.docs().remove(&doc_id);
+        Ok(())
+    }
+}
+
+/// Returns a new [YStorage] keeping all updates in memory. Meant for tests and as a reference for
+/// implementing custom storages.
+#[uniffi::export]
+pub fn memory_storage() -> Arc<dyn YStorage> {
+    Arc::new(MemoryStorage::default())
+}
+
+/// Binds a document to a [YStorage]. When created, it loads all updates stored for a document,
+/// then writes every committed change as an incremental update. Once a configured number of
+/// updates has been written, they are replaced with a single snapshot of a document state.
+///
+/// Failing to write an update doesn't interrupt a transaction that produced it. Instead, next
+/// change triggers a compaction, which stores a full state of a document again. Until that
+/// succeeds, `in_sync` returns false.
+#[derive(uniffi::Object)]
+pub struct YPersistence {
+    state: Arc<PersistenceState>,
+    /// Bound document. It's not a part of the state shared with an observer, as that would create
+    /// a reference cycle between a document and its own observer.
+    doc: DocRef,
+    subscription: Mutex<Option<Subscription>>,
+}
+
+struct PersistenceState {
+    storage: Arc<dyn YStorage>,
+    doc_id: String,
+    compaction_interval: u32,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Default)]
+struct Progress {
+    /// Number of updates written since the last compaction.
+    written: u32,
+    /// Set when some of the updates could not have been written.
+    out_of_sync: bool,
+}
+
+#[uniffi::export]
+impl YPersistence {
+    /// Binds a given `doc` to a `storage`, loading all updates stored for it. Document is
+    /// identified by a given `doc_id`, or its `guid` if none was provided. If a document had any
+    /// contents before, the ones missing in a storage are written to it.
+    ///
+    /// `compaction_interval` is a number of written updates, after which they are replaced with
+    /// a single snapshot. By default it's 100.
+    #[uniffi::constructor(default(doc_id=None, compaction_interval=None))]
+    pub fn new(
+        storage: Arc<dyn YStorage>,
+        doc: &YDoc,
+        doc_id: Option<String>,
+        compaction_interval: Option<u32>,
+    ) -> Result<Self> {
+        let state = Arc::new(PersistenceState {
+            storage,
+            doc_id: doc_id.unwrap_or_else(|| doc.guid()),
+            compaction_interval: compaction_interval.unwrap_or(DEFAULT_COMPACTION_INTERVAL).max(1),
+            progress: Mutex::new(Progress::default()),
+        });
+        state.load(&doc.0)?;
+
+        let observer = state.clone();
+        let subscription = doc
+            .0
+            .observe_update_v2(move |txn, e| observer.write(txn, &e.update))
+            .map_err(|_| Error::AnotherTx)?;
+        Ok(YPersistence {
+            state,
+            doc: doc.0.clone(),
+            subscription: Mutex::new(Some(subscription)),
+        })
+    }
+
+    /// Returns an identifier of a persisted document.
+    pub fn doc_id(&self) -> String {
+        self.state.doc_id.clone()
+    }
+
+    /// Checks if all changes made to a document have been written to a storage.
+    pub fn in_sync(&self) -> bool {
+        !self.state.progress().out_of_sync
+    }
+
+    /// Replaces all updates stored for a bound document with a single snapshot of its current
+    /// state.
+    pub fn compact(&self) -> Result<()> {
+        let txn = self.doc.try_transact().map_err(|_| Error::AnotherRwTx)?;
+        self.state.compact(&txn)
+    }
+
+    /// Stops writing changes of a document to a storage. Calling this method more than once has
+    /// no effect.
+    pub fn close(&self) {
+        let subscription = self.subscription.lock().unwrap_or_else(|e| e.into_inner()).take();
+        drop(subscription);
+    }
+
+    /// Stops writing changes of a document and removes all of its updates from a storage.
+    pub fn delete(&self) -> Result<()> {
+        self.close();
+        self.state.storage.delete_doc(self.state.doc_id.clone())
+    }
+}
+
+impl PersistenceState {
+    fn progress(&self) -> MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn load(&self, doc: &DocRef) -> Result<()> {
+        let updates = self.storage.get_updates(self.doc_id.clone())?;
+        let mut txn = doc.transact_mut()?;
+        let was_empty = txn.state_vector() == StateVector::default();
+        let stored = if updates.is_empty() {
+            StateVector::default()
+        } else {
+            let update = Update::decode_v2(&yrs::merge_updates_v2(updates)?)?;
+            let stored = update.state_vector();
+            txn.apply_update(update)?;
+            stored
+        };
+        if !was_empty {
+            self.storage
+                .put_update(self.doc_id.clone(), txn.encode_diff_v2(&stored))?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, txn: &TransactionMut, update: &[u8]) {
+        let compact = {
+            let mut progress = self.progress();
+            progress.written += 1;
+            progress.out_of_sync || progress.written >= self.compaction_interval
+        };
+        // compaction stores the full state, so there's no need to write an update separately
+        let result = if compact {
+            self.compact(txn)
+        } else {
+            self.storage.put_update(self.doc_id.clone(), update.to_vec())
+        };
+        if result.is_err() {
+            self.progress().out_of_sync = true;
+        }
+    }
+
+    fn compact<T: ReadTxn>(&self, txn: &T) -> Result<()> {
+        let snapshot = txn.encode_state_as_update_v2(&StateVector::default());
+        self.storage.replace_with_snapshot(self.doc_id.clone(), snapshot)?;
+        *self.progress() = Progress::default();
+        Ok(())
+    }
+}
+
+/// Returns payload ranges of all complete and valid records stored in a log `data`, stopping at
+/// the first one which is not.
+fn read_records(data: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let mut records = Vec::new();
+    let mut offset = HEADER.len();
+    while data.len() - offset >= RECORD_HEADER_LEN {
+        let header = &data[offset..offset + RECORD_HEADER_LEN];
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let start = offset + RECORD_HEADER_LEN;
+        if data.len() - start < len || crc32(&data[start..start + len]) != checksum {
+            break;
+        }
+        records.push(start..start + len);
+        offset = start + len;
+    }
+    records
+}
+
+/// Flushes a directory containing a given `path`, so that a file renamed into it survives a crash.
+/// Directories cannot be opened as files on Windows, where this step is skipped.
+fn sync_parent(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3) checksum of given `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
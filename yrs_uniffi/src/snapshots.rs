@@ -2,16 +2,238 @@ use crate::doc::YDoc;
 use crate::tools;
 use crate::tools::Error;
 use crate::tools::Result;
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::Arc;
-use yrs::updates::decoder::{Decode, DecoderV1};
+use yrs::block::{
+    ItemContent, BLOCK_GC_REF_NUMBER, BLOCK_SKIP_REF_NUMBER, HAS_ORIGIN, HAS_PARENT_SUB,
+    HAS_RIGHT_ORIGIN,
+};
+use yrs::encoding::read;
+use yrs::encoding::read::Cursor;
+use yrs::updates::decoder::{Decode, Decoder, DecoderV1, DecoderV2};
 use yrs::updates::encoder::{Encode, Encoder, EncoderV1, EncoderV2};
-use yrs::{ReadTxn, StateVector, Transact, Update};
+use yrs::{DeleteSet, Doc, OffsetKind, Options, ReadTxn, StateVector, Transact, Update, ID};
 
 #[derive(uniffi::Object)]
 #[repr(transparent)]
 pub struct YStateVector(pub(crate) StateVector);
 
+/// A continuous range of clock values `start`..`end` (exclusive) produced by a single client.
+#[derive(uniffi::Record)]
+#[derive(Clone, PartialEq)]
+pub struct YIdRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Unique identifier of a block: id of a client that created it and a clock value of its first
+/// element.
+#[derive(uniffi::Record)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct YId {
+    pub client: u64,
+    pub clock: u32,
+}
+
+impl From<ID> for YId {
+    fn from(id: ID) -> Self {
+        YId {
+            client: id.client,
+            clock: id.clock,
+        }
+    }
+}
+
+/// Kind of a block decoded by [decode_update_v1].
+#[derive(uniffi::Enum)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YBlockKind {
+    /// Block carrying inserted content.
+    Item,
+    /// Garbage collected block: its content has been deleted and discarded.
+    Gc,
+    /// Placeholder for a range of clock values which is not part of the update.
+    Skip,
+}
+
+/// Kind of a content stored within an item block.
+#[derive(uniffi::Enum)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YContentKind {
+    Any,
+    Binary,
+    Deleted,
+    Doc,
+    Embed,
+    Format,
+    Json,
+    Move,
+    String,
+    Type,
+}
+
+impl From<&ItemContent> for YContentKind {
+    fn from(content: &ItemContent) -> Self {
+        match content {
+            ItemContent::Any(_) => YContentKind::Any,
+            ItemContent::Binary(_) => YContentKind::Binary,
+            ItemContent::Deleted(_) => YContentKind::Deleted,
+            ItemContent::Doc(_, _) => YContentKind::Doc,
+            ItemContent::JSON(_) => YContentKind::Json,
+            ItemContent::Embed(_) => YContentKind::Embed,
+            ItemContent::Format(_, _) => YContentKind::Format,
+            ItemContent::String(_) => YContentKind::String,
+            ItemContent::Type(_) => YContentKind::Type,
+            ItemContent::Move(_) => YContentKind::Move,
+        }
+    }
+}
+
+/// Parent collection of an item block, as it was encoded within an update.
+#[derive(uniffi::Enum)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum YBlockParent {
+    /// Root-level collection, identified by its name.
+    Named { name: String },
+    /// Nested collection, identified by the id of a block that created it.
+    Id { id: YId },
+}
+
+/// Summary of a single block decoded by [decode_update_v1].
+#[derive(uniffi::Record)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct YBlock {
+    pub id: YId,
+    /// Number of clock values occupied by this block.
+    pub length: u32,
+    pub kind: YBlockKind,
+    /// Id of an element, which was a left neighbor of this block at the moment of insertion.
+    pub origin: Option<YId>,
+    /// Id of an element, which was a right neighbor of this block at the moment of insertion.
+    pub right_origin: Option<YId>,
+    /// Parent collection of this block. It's not encoded when it can be inferred from the block's
+    /// neighbors, in which case it's null.
+    pub parent: Option<YBlockParent>,
+    /// Key of a map entry this block was assigned to, if parent is a map-like collection.
+    pub parent_sub: Option<String>,
+    pub content_kind: Option<YContentKind>,
+    /// Human-readable summary of the block content.
+    pub content: Option<String>,
+}
+
+/// Structured representation of an update payload returned by [decode_update_v1].
+#[derive(uniffi::Record)]
+#[derive(Clone, PartialEq)]
+pub struct YDecodedUpdate {
+    /// Blocks grouped by client id, ordered by their clock values.
+    pub blocks: HashMap<u64, Vec<YBlock>>,
+    /// Ranges of clock values deleted by this update, grouped by client id.
+    pub delete_set: HashMap<u64, Vec<YIdRange>>,
+}
+
+impl YDecodedUpdate {
+    fn decode<D: Decoder>(decoder: &mut D) -> std::result::Result<Self, read::Error> {
+        let clients_len: u32 = decoder.read_var()?;
+        let mut blocks = HashMap::new();
+        for _ in 0..clients_len {
+            let blocks_len: u32 = decoder.read_var()?;
+            let client = decoder.read_client()?;
+            let mut clock: u32 = decoder.read_var()?;
+            let client_blocks: &mut Vec<YBlock> = blocks.entry(client).or_default();
+            for _ in 0..blocks_len {
+                let block = Self::decode_block(ID::new(client, clock), decoder)?;
+                // empty blocks have no effect on the document store, same as in `Update::decode`
+                if block.length > 0 {
+                    clock = clock.checked_add(block.length).ok_or_else(|| {
+                        read::Error::Custom(format!("clock of client {} overflows", client))
+                    })?;
+                    client_blocks.push(block);
+                }
+            }
+        }
+        let delete_set = DeleteSet::decode(decoder)?;
+        Ok(YDecodedUpdate {
+            blocks,
+            delete_set: y_delete_set(&delete_set),
+        })
+    }
+
+    fn decode_block<D: Decoder>(id: ID, decoder: &mut D) -> std::result::Result<YBlock, read::Error> {
+        let info = decoder.read_info()?;
+        let mut block = YBlock {
+            id: id.into(),
+            length: 0,
+            kind: YBlockKind::Item,
+            origin: None,
+            right_origin: None,
+            parent: None,
+            parent_sub: None,
+            content_kind: None,
+            content: None,
+        };
+        match info {
+            BLOCK_SKIP_REF_NUMBER => {
+                block.kind = YBlockKind::Skip;
+                block.length = decoder.read_var()?;
+            }
+            BLOCK_GC_REF_NUMBER => {
+                block.kind = YBlockKind::Gc;
+                block.length = decoder.read_len()?;
+            }
+            info => {
+                let cant_copy_parent_info = info & (HAS_ORIGIN | HAS_RIGHT_ORIGIN) == 0;
+                if info & HAS_ORIGIN != 0 {
+                    block.origin = Some(decoder.read_left_id()?.into());
+                }
+                if info & HAS_RIGHT_ORIGIN != 0 {
+                    block.right_origin = Some(decoder.read_right_id()?.into());
+                }
+                if cant_copy_parent_info {
+                    block.parent = Some(if decoder.read_parent_info()? {
+                        YBlockParent::Named {
+                            name: decoder.read_string()?.to_string(),
+                        }
+                    } else {
+                        YBlockParent::Id {
+                            id: decoder.read_left_id()?.into(),
+                        }
+                    });
+                    if info & HAS_PARENT_SUB != 0 {
+                        block.parent_sub = Some(decoder.read_string()?.to_string());
+                    }
+                }
+                let content = ItemContent::decode(decoder, info)?;
+                block.length = content.len(OffsetKind::Utf16);
+                block.content_kind = Some(YContentKind::from(&content));
+                block.content = Some(content.to_string());
+            }
+        }
+        Ok(block)
+    }
+}
+
+/// Converts a state vector into a map of client ids and their corresponding clock values.
+pub(crate) fn y_state_vector(sv: &StateVector) -> HashMap<u64, u32> {
+    sv.iter().map(|(client, clock)| (*client, *clock)).collect()
+}
+
+/// Converts a delete set into a map of client ids and ranges of deleted clock values.
+pub(crate) fn y_delete_set(ds: &DeleteSet) -> HashMap<u64, Vec<YIdRange>> {
+    ds.iter()
+        .map(|(client, ranges)| {
+            let ranges = ranges
+                .iter()
+                .map(|r| YIdRange {
+                    start: r.start,
+                    end: r.end,
+                })
+                .collect();
+            (*client, ranges)
+        })
+        .collect()
+}
+
 /// Encodes a state vector of a given ywasm document into its binary representation using lib0 v1
 /// encoding. State vector is a compact representation of updates performed on a given document and
 /// can be used by `encode_state_as_update` on remote peer to generate a delta update payload to
@@ -51,7 +273,7 @@ fn decode_state_vector(vector: Option<Vec<u8>>) -> Result<YStateVector> {
     if let Some(v) = vector {
         match StateVector::decode_v1(v.as_slice()) {
             Ok(sv) => Ok(YStateVector(sv)),
-            Err(e) => Err(Error::InvalidData(e.to_string())),
+            Err(e) => Err(e.into()),
         }
     } else {
         Ok(YStateVector(StateVector::default()))
@@ -63,7 +285,7 @@ fn decode_state_vector2(vector: Option<Vec<u8>>) -> Result<YStateVector> {
     if let Some(v) = vector {
         match StateVector::decode_v2(v.as_slice()) {
             Ok(sv) => Ok(YStateVector(sv)),
-            Err(e) => Err(Error::InvalidData(e.to_string())),
+            Err(e) => Err(e.into()),
         }
     } else {
         Ok(YStateVector(StateVector::default()))
@@ -76,7 +298,7 @@ pub fn debug_update_v1(update: &[u8]) -> Result<String> {
     let mut decoder = DecoderV1::from(update);
     match Update::decode(&mut decoder) {
         Ok(update) => Ok(format!("{:#?}", update)),
-        Err(e) => Err(Error::InvalidData(e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -85,7 +307,90 @@ pub fn debug_update_v1(update: &[u8]) -> Result<String> {
 pub fn debug_update_v2(update: &[u8]) -> Result<String> {
     match Update::decode_v2(update) {
         Ok(update) => Ok(format!("{:#?}", update)),
-        Err(e) => Err(Error::InvalidData(e.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Decodes a given `update` encoded using lib0 v1 encoding into a structured representation,
+/// which describes blocks inserted by each client and ranges of deleted elements. Unlike
+/// `debug_update_v1`, the result is meant to be inspected programmatically.
+#[uniffi::export]
+pub fn decode_update_v1(update: &[u8]) -> Result<YDecodedUpdate> {
+    let mut decoder = DecoderV1::new(Cursor::new(update));
+    YDecodedUpdate::decode(&mut decoder).map_err(Error::from)
+}
+
+/// Decodes a given `update` encoded using lib0 v2 encoding into a structured representation,
+/// which describes blocks inserted by each client and ranges of deleted elements. Unlike
+/// `debug_update_v2`, the result is meant to be inspected programmatically.
+#[uniffi::export]
+pub fn decode_update_v2(update: &[u8]) -> Result<YDecodedUpdate> {
+    let result = DecoderV2::new(Cursor::new(update))
+        .and_then(|mut decoder| YDecodedUpdate::decode(&mut decoder));
+    result.map_err(Error::from)
+}
+
+/// Merges a list of `updates` encoded using lib0 v1 encoding into a single, compacted update
+/// (also lib0 v1 encoded) without a need to instantiate a document. Applying the result is
+/// equivalent to applying all of the input updates.
+#[uniffi::export]
+pub fn merge_updates_v1(updates: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    yrs::merge_updates_v1(updates).map_err(Error::from)
+}
+
+/// Merges a list of `updates` encoded using lib0 v2 encoding into a single, compacted update
+/// (also lib0 v2 encoded) without a need to instantiate a document. Applying the result is
+/// equivalent to applying all of the input updates.
+#[uniffi::export]
+pub fn merge_updates_v2(updates: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    yrs::merge_updates_v2(updates).map_err(Error::from)
+}
+
+/// Given an `update` of document A and a `state_vector` of document B (both encoded using lib0 v1
+/// encoding), returns a lib0 v1 encoded update containing all changes from A which have not been
+/// observed by B yet.
+#[uniffi::export]
+pub fn diff_update_v1(update: &[u8], state_vector: &[u8]) -> Result<Vec<u8>> {
+    yrs::diff_updates_v1(update, state_vector).map_err(Error::from)
+}
+
+/// Given an `update` of document A and a `state_vector` of document B (both encoded using lib0 v2
+/// encoding), returns a lib0 v2 encoded update containing all changes from A which have not been
+/// observed by B yet.
+#[uniffi::export]
+pub fn diff_update_v2(update: &[u8], state_vector: &[u8]) -> Result<Vec<u8>> {
+    yrs::diff_updates_v2(update, state_vector).map_err(Error::from)
+}
+
+/// Returns a state vector (lib0 v1 encoded) of a document that would be produced by applying
+/// a given lib0 v1 encoded `update` to an empty document.
+#[uniffi::export]
+pub fn encode_state_vector_from_update_v1(update: &[u8]) -> Result<Vec<u8>> {
+    yrs::encode_state_vector_from_update_v1(update).map_err(Error::from)
+}
+
+/// Returns a state vector (lib0 v2 encoded) of a document that would be produced by applying
+/// a given lib0 v2 encoded `update` to an empty document.
+#[uniffi::export]
+pub fn encode_state_vector_from_update_v2(update: &[u8]) -> Result<Vec<u8>> {
+    yrs::encode_state_vector_from_update_v2(update).map_err(Error::from)
+}
+
+/// Converts an `update` encoded using lib0 v1 encoding into lib0 v2 encoding.
+#[uniffi::export]
+pub fn convert_update_v1_to_v2(update: &[u8]) -> Result<Vec<u8>> {
+    match Update::decode_v1(update) {
+        Ok(update) => Ok(update.encode_v2()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Converts an `update` encoded using lib0 v2 encoding into lib0 v1 encoding.
+#[uniffi::export]
+pub fn convert_update_v2_to_v1(update: &[u8]) -> Result<Vec<u8>> {
+    match Update::decode_v2(update) {
+        Ok(update) => Ok(update.encode_v1()),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -175,17 +480,16 @@ pub fn encode_state_as_update_v2(doc: &YDoc, vector: Option<Arc<YStateVector>>)
 #[uniffi::export(default(origin=None))]
 pub fn apply_update(doc: &YDoc, update: &[u8], origin: Option<Vec<u8>>) -> Result<()> {
     let mut txn = if let Some(origin) = origin {
-        doc.0.try_transact_mut_with(origin.as_slice())
+        doc.0.transact_mut_with(origin.as_slice())
     } else {
-        doc.0.try_transact_mut()
-    }
-    .map_err(|_| Error::AnotherRwTx)?;
+        doc.0.transact_mut()
+    }?;
 
     match Update::decode_v1(update) {
         Ok(update) => txn
             .apply_update(update)
-            .map_err(|e| Error::InvalidData(e.to_string())),
-        Err(e) => Err(Error::InvalidData(e.to_string())),
+            .map_err(Error::from),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -210,17 +514,16 @@ pub fn apply_update(doc: &YDoc, update: &[u8], origin: Option<Vec<u8>>) -> Resul
 #[uniffi::export(default(origin=None))]
 pub fn apply_update_v2(doc: &YDoc, update: &[u8], origin: Option<Vec<u8>>) -> Result<()> {
     let mut txn = if let Some(origin) = origin {
-        doc.0.try_transact_mut_with(origin.as_slice())
+        doc.0.transact_mut_with(origin.as_slice())
     } else {
-        doc.0.try_transact_mut()
-    }
-    .map_err(|_| Error::AnotherRwTx)?;
+        doc.0.transact_mut()
+    }?;
 
     match Update::decode_v2(update) {
         Ok(update) => txn
             .apply_update(update)
-            .map_err(|e| tools::Error::InvalidData(e.to_string())),
-        Err(e) => Err(tools::Error::InvalidData(e.to_string())),
+            .map_err(tools::Error::from),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -237,9 +540,9 @@ impl Deref for YSnapshot {
 }
 
 #[uniffi::export]
-pub fn snapshot(doc: &YDoc) -> Arc<YSnapshot> {
-    let snapshot = doc.0.transact().snapshot();
-    Arc::new(YSnapshot(snapshot))
+pub fn snapshot(doc: &YDoc) -> Result<Arc<YSnapshot>> {
+    let txn = doc.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+    Ok(Arc::new(YSnapshot(txn.snapshot())))
 }
 
 #[uniffi::export]
@@ -254,9 +557,7 @@ pub fn encode_snapshot_v1(snapshot: &YSnapshot) -> Vec<u8> {
 
 #[uniffi::export]
 pub fn decode_snapshot_v1(snapshot: &[u8]) -> Result<YSnapshot> {
-    let snap = yrs::Snapshot::decode_v1(snapshot).map_err(|_| {
-        Error::InvalidData("failed to deserialize snapshot using lib0 v2 decoding".into())
-    })?;
+    let snap = yrs::Snapshot::decode_v1(snapshot)?;
     Ok(YSnapshot(snap))
 }
 
@@ -267,20 +568,15 @@ pub fn encode_snapshot_v2(snapshot: &YSnapshot) -> Vec<u8> {
 
 #[uniffi::export]
 pub fn decode_snapshot_v2(snapshot: &[u8]) -> Result<YSnapshot> {
-    let snap = yrs::Snapshot::decode_v2(snapshot).map_err(|_| {
-        Error::InvalidData("failed to deserialize snapshot using lib0 v2 decoding".into())
-    })?;
+    let snap = yrs::Snapshot::decode_v2(snapshot)?;
     Ok(YSnapshot(snap))
 }
 
 #[uniffi::export]
 pub fn encode_state_from_snapshot_v1(doc: &YDoc, snapshot: &YSnapshot) -> Result<Vec<u8>> {
     let mut encoder = EncoderV1::new();
-    match doc
-        .0
-        .transact()
-        .encode_state_from_snapshot(&snapshot.0, &mut encoder)
-    {
+    let txn = doc.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+    match txn.encode_state_from_snapshot(&snapshot.0, &mut encoder) {
         Ok(_) => Ok(encoder.to_vec()),
         Err(e) => Err(Error::InvalidData(e.to_string())),
     }
@@ -289,12 +585,30 @@ pub fn encode_state_from_snapshot_v1(doc: &YDoc, snapshot: &YSnapshot) -> Result
 #[uniffi::export]
 pub fn encode_state_from_snapshot_v2(doc: &YDoc, snapshot: &YSnapshot) -> Result<Vec<u8>> {
     let mut encoder = EncoderV2::new();
-    match doc
-        .0
-        .transact()
-        .encode_state_from_snapshot(&snapshot.0, &mut encoder)
-    {
+    let txn = doc.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+    match txn.encode_state_from_snapshot(&snapshot.0, &mut encoder) {
         Ok(_) => Ok(encoder.to_vec()),
         Err(e) => Err(Error::InvalidData(e.to_string())),
     }
 }
+
+/// Creates a new document containing the state of a `doc` read by `txn` at the moment when
+/// a given `snapshot` was taken. Snapshots can only be restored from documents which don't
+/// garbage collect contents of deleted blocks.
+pub(crate) fn restore_snapshot<T: ReadTxn>(doc: &Doc, txn: &T, snapshot: &yrs::Snapshot) -> Result<Doc> {
+    if !doc.skip_gc() {
+        return Err(Error::GcEnabled);
+    }
+    let mut encoder = EncoderV1::new();
+    txn.encode_state_from_snapshot(snapshot, &mut encoder)
+        .map_err(|e| Error::InvalidData(e.to_string()))?;
+    let update = Update::decode_v1(&encoder.to_vec())?;
+
+    let fork = Doc::with_options(Options {
+        offset_kind: doc.offset_kind(),
+        skip_gc: true,
+        ..Options::default()
+    });
+    fork.transact_mut().apply_update(update)?;
+    Ok(fork)
+}
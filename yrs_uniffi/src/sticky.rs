@@ -0,0 +1,126 @@
+use crate::doc::YDoc;
+use crate::tools::{Error, Result};
+use crate::transaction::YTransaction;
+use std::ops::Deref;
+use std::sync::Arc;
+use yrs::branch::{Branch, BranchPtr};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Assoc, IndexScope, ReadTxn, StickyIndex, Transact};
+
+/// Association type of a [YStickyIndex]. When another peer inserts new content exactly at the
+/// position referenced by a sticky index, it decides if the index should stick to the content
+/// placed before or after it.
+#[derive(uniffi::Enum)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YAssoc {
+    /// Sticky index points to a space after the referenced position.
+    After,
+    /// Sticky index points to a space before the referenced position.
+    Before,
+}
+
+impl From<YAssoc> for Assoc {
+    fn from(value: YAssoc) -> Self {
+        match value {
+            YAssoc::After => Assoc::After,
+            YAssoc::Before => Assoc::Before,
+        }
+    }
+}
+
+impl From<Assoc> for YAssoc {
+    fn from(value: Assoc) -> Self {
+        match value {
+            Assoc::After => YAssoc::After,
+            Assoc::Before => YAssoc::Before,
+        }
+    }
+}
+
+/// A position within a sequential shared type (text, XML text or XML fragment), which - unlike
+/// a plain index - remains valid when concurrent changes are made: it's bound to an element
+/// rather than to an offset. It's equivalent of Yjs `RelativePosition`.
+///
+/// Sticky index can be created ie. from `YText.stickyIndex`, and resolved back to a current
+/// offset via `getOffset`.
+#[derive(uniffi::Object)]
+pub struct YStickyIndex(StickyIndex);
+
+impl YStickyIndex {
+    /// Creates a sticky index pointing to a given `index` within a collection represented by
+    /// `branch`. Returns None if `index` is beyond the length of that collection.
+    pub(crate) fn at<T: ReadTxn, B: AsRef<Branch>>(
+        txn: &T,
+        branch: &B,
+        index: u32,
+        assoc: YAssoc,
+    ) -> Option<Arc<YStickyIndex>> {
+        let branch = BranchPtr::from(branch.as_ref());
+        let sticky = match StickyIndex::at(txn, branch, index, assoc.into()) {
+            Some(sticky) => sticky,
+            // same as Yjs: index placed at the end of a collection sticks to the collection itself
+            None if index == branch.content_len => {
+                StickyIndex::new(IndexScope::from_branch(branch), assoc.into())
+            }
+            None => return None,
+        };
+        Some(Arc::new(YStickyIndex(sticky)))
+    }
+}
+
+#[uniffi::export]
+impl YStickyIndex {
+    /// Decodes a sticky index from its binary representation. Binary format is compatible with
+    /// Yjs `encodeRelativePosition`/`decodeRelativePosition`.
+    #[uniffi::constructor]
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(YStickyIndex(StickyIndex::decode_v1(bytes)?))
+    }
+
+    /// Parses a sticky index from its JSON representation. JSON format is compatible with Yjs
+    /// `relativePositionToJSON`/`createRelativePositionFromJSON`.
+    #[uniffi::constructor]
+    pub fn from_json(json: String) -> Result<Self> {
+        Ok(YStickyIndex(serde_json::from_str::<StickyIndex>(&json)?))
+    }
+
+    /// Encodes this sticky index into a binary representation.
+    pub fn encode(&self) -> Vec<u8> {
+        self.0.encode_v1()
+    }
+
+    /// Returns a JSON representation of this sticky index.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(&self.0).map_err(|e| Error::InvalidData(e.to_string()))
+    }
+
+    pub fn assoc(&self) -> YAssoc {
+        self.0.assoc.into()
+    }
+
+    /// Resolves this sticky index into a current offset within its collection in the context of
+    /// a given document. Returns None if the collection this index refers to doesn't exist in
+    /// `doc`.
+    #[uniffi::method(default(txn=None))]
+    pub fn get_offset(&self, doc: &YDoc, txn: Option<Arc<YTransaction>>) -> Result<Option<u32>> {
+        let offset = match txn {
+            Some(txn) => {
+                let txn = txn.borrow()?;
+                self.0.get_offset(txn.deref())
+            }
+            None => {
+                let txn = doc.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+                self.0.get_offset(&txn)
+            }
+        };
+        Ok(offset.map(|offset| {
+            if self.0.is_nested() && offset.assoc == Assoc::After {
+                // index sticking to the end of a nested collection
+                offset.branch.content_len
+            } else {
+                offset.index
+            }
+        }))
+    }
+}
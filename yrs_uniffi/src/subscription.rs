@@ -0,0 +1,24 @@
+use std::sync::Mutex;
+use yrs::Subscription;
+
+/// A handle to an active event observer. Callbacks registered via `observe` methods stay active
+/// for as long as this handle is alive: dropping it or calling [YSubscription::unsubscribe] will
+/// stop any further notifications.
+#[derive(uniffi::Object)]
+pub struct YSubscription(Mutex<Option<Subscription>>);
+
+impl From<Subscription> for YSubscription {
+    fn from(value: Subscription) -> Self {
+        YSubscription(Mutex::new(Some(value)))
+    }
+}
+
+#[uniffi::export]
+impl YSubscription {
+    /// Stops the observer associated with this subscription. Calling this method more than once
+    /// has no effect.
+    pub fn unsubscribe(&self) {
+        let subscription = self.0.lock().unwrap_or_else(|e| e.into_inner()).take();
+        drop(subscription);
+    }
+}
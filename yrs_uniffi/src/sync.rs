@@ -0,0 +1,222 @@
+use crate::awareness::YAwareness;
+use crate::doc::{DocRef, YDoc};
+use crate::tools::{Error, Result};
+use std::sync::Arc;
+use yrs::encoding::read::Cursor;
+use yrs::sync::protocol::{MessageReader, MSG_SYNC, MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_SYNC_UPDATE};
+use yrs::sync::{Awareness, AwarenessUpdate, Message, SyncMessage};
+use yrs::updates::decoder::{Decode, DecoderV1};
+use yrs::updates::encoder::{Encode, Encoder, EncoderV1};
+use yrs::{ReadTxn, StateVector, Transact, Update};
+
+/// A single message of y-sync protocol, as used by `y-websocket` and compatible providers.
+/// All binary payloads use lib0 v1 encoding.
+#[derive(uniffi::Enum)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum YSyncMessage {
+    /// Sync step 1: a state vector of the sender, asking a remote peer for missing updates.
+    SyncStep1 { state_vector: Vec<u8> },
+    /// Sync step 2: an update containing changes missing on the remote peer.
+    SyncStep2 { update: Vec<u8> },
+    /// An incremental document update.
+    Update { update: Vec<u8> },
+    /// An awareness update, as produced by `YAwareness.encodeUpdate`.
+    Awareness { update: Vec<u8> },
+    /// Authorization message. If `deny_reason` is provided, the permission has been denied.
+    Auth { deny_reason: Option<String> },
+    /// A request for sending back the full awareness state.
+    QueryAwareness,
+    /// A message with a custom tag not handled by y-sync protocol itself.
+    Custom { tag: u8, data: Vec<u8> },
+}
+
+impl From<Message> for YSyncMessage {
+    fn from(value: Message) -> Self {
+        match value {
+            Message::Sync(SyncMessage::SyncStep1(sv)) => YSyncMessage::SyncStep1 {
+                state_vector: sv.encode_v1(),
+            },
+            Message::Sync(SyncMessage::SyncStep2(update)) => YSyncMessage::SyncStep2 { update },
+            Message::Sync(SyncMessage::Update(update)) => YSyncMessage::Update { update },
+            Message::Awareness(update) => YSyncMessage::Awareness {
+                update: update.encode_v1(),
+            },
+            Message::Auth(deny_reason) => YSyncMessage::Auth { deny_reason },
+            Message::AwarenessQuery => YSyncMessage::QueryAwareness,
+            Message::Custom(tag, data) => YSyncMessage::Custom { tag, data },
+        }
+    }
+}
+
+impl YSyncMessage {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        match self {
+            YSyncMessage::SyncStep1 { state_vector } => {
+                // make sure that the payload is a valid state vector
+                StateVector::decode_v1(state_vector)?;
+                encoder.write_var(MSG_SYNC);
+                encoder.write_var(MSG_SYNC_STEP_1);
+                encoder.write_buf(state_vector);
+            }
+            YSyncMessage::SyncStep2 { update } => {
+                encoder.write_var(MSG_SYNC);
+                encoder.write_var(MSG_SYNC_STEP_2);
+                encoder.write_buf(update);
+            }
+            YSyncMessage::Update { update } => {
+                encoder.write_var(MSG_SYNC);
+                encoder.write_var(MSG_SYNC_UPDATE);
+                encoder.write_buf(update);
+            }
+            YSyncMessage::Awareness { update } => {
+                let update = AwarenessUpdate::decode_v1(update)?;
+                Message::Awareness(update).encode(encoder);
+            }
+            YSyncMessage::Auth { deny_reason } => {
+                Message::Auth(deny_reason.clone()).encode(encoder);
+            }
+            YSyncMessage::QueryAwareness => Message::AwarenessQuery.encode(encoder),
+            YSyncMessage::Custom { tag, data } => {
+                Message::Custom(*tag, data.clone()).encode(encoder);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a single y-sync protocol message into its binary representation.
+#[uniffi::export]
+pub fn encode_sync_message(message: YSyncMessage) -> Result<Vec<u8>> {
+    encode_sync_messages(vec![message])
+}
+
+/// Encodes given y-sync protocol messages one after another into a single binary payload.
+#[uniffi::export]
+pub fn encode_sync_messages(messages: Vec<YSyncMessage>) -> Result<Vec<u8>> {
+    let mut encoder = EncoderV1::new();
+    for message in messages.iter() {
+        message.encode(&mut encoder)?;
+    }
+    Ok(encoder.to_vec())
+}
+
+/// Decodes all y-sync protocol messages stored one after another within a binary `payload`.
+#[uniffi::export]
+pub fn decode_sync_messages(payload: &[u8]) -> Result<Vec<YSyncMessage>> {
+    let mut decoder = DecoderV1::new(Cursor::new(payload));
+    MessageReader::new(&mut decoder)
+        .map(|message| {
+            message
+                .map(YSyncMessage::from)
+                .map_err(Error::from)
+        })
+        .collect()
+}
+
+/// Implementation of y-sync protocol, binary compatible with `y-websocket`. It keeps a document
+/// (and awareness of its peers) in sync with a remote side by handling incoming messages and
+/// producing replies, which should be sent back to the sender.
+///
+/// Transport is not a part of this protocol: it's up to the caller to deliver messages produced
+/// by this type and to pass messages received from remote side into `handle`.
+#[derive(uniffi::Object)]
+pub struct YSyncProtocol {
+    doc: DocRef,
+    awareness: Arc<YAwareness>,
+}
+
+impl YSyncProtocol {
+    fn awareness(&self) -> &Awareness {
+        &self.awareness.0
+    }
+
+    fn reply(&self, message: Message, origin: Option<&[u8]>) -> Result<Option<Message>> {
+        match message {
+            Message::Sync(SyncMessage::SyncStep1(sv)) => {
+                let txn = self.doc.try_transact().map_err(|_| Error::AnotherRwTx)?;
+                let update = txn.encode_state_as_update_v1(&sv);
+                Ok(Some(Message::Sync(SyncMessage::SyncStep2(update))))
+            }
+            Message::Sync(SyncMessage::SyncStep2(update))
+            | Message::Sync(SyncMessage::Update(update)) => {
+                let update = Update::decode_v1(&update)?;
+                let mut txn = match origin {
+                    None => self.doc.transact_mut(),
+                    Some(origin) => self.doc.transact_mut_with(origin),
+                }?;
+                txn.apply_update(update)?;
+                Ok(None)
+            }
+            Message::Auth(None) => Ok(None),
+            Message::Auth(Some(reason)) => Err(Error::PermissionDenied { reason }),
+            Message::AwarenessQuery => {
+                let update = self.awareness().update()?;
+                Ok(Some(Message::Awareness(update)))
+            }
+            Message::Awareness(update) => {
+                let result = match origin {
+                    None => self.awareness().apply_update(update),
+                    Some(origin) => self.awareness().apply_update_with(update, origin),
+                };
+                result?;
+                Ok(None)
+            }
+            // custom messages belong to the application, which can read them using
+            // `decode_sync_messages`
+            Message::Custom(_, _) => Ok(None),
+        }
+    }
+}
+
+#[uniffi::export]
+impl YSyncProtocol {
+    /// Creates a protocol instance for a given document. If `awareness` is not provided, a new
+    /// awareness instance bound to `doc` is created. Otherwise it must be bound to the same `doc`.
+    #[uniffi::constructor(default(awareness=None))]
+    pub fn new(doc: &YDoc, awareness: Option<Arc<YAwareness>>) -> Self {
+        let awareness = awareness.unwrap_or_else(|| Arc::new(YAwareness::new(doc)));
+        YSyncProtocol {
+            doc: doc.0.clone(),
+            awareness,
+        }
+    }
+
+    /// Returns an awareness instance used by this protocol.
+    pub fn get_awareness(&self) -> Arc<YAwareness> {
+        self.awareness.clone()
+    }
+
+    /// Returns a payload that should be sent to the remote side once connection has been
+    /// established: sync step 1 with a state vector of the document, followed by an awareness
+    /// update with states of all known peers.
+    pub fn start(&self) -> Result<Vec<u8>> {
+        let sv = {
+            let txn = self.doc.try_transact().map_err(|_| Error::AnotherRwTx)?;
+            txn.state_vector()
+        };
+        let update = self.awareness().update()?;
+        let mut encoder = EncoderV1::new();
+        Message::Sync(SyncMessage::SyncStep1(sv)).encode(&mut encoder);
+        Message::Awareness(update).encode(&mut encoder);
+        Ok(encoder.to_vec())
+    }
+
+    /// Handles a binary `payload` received from the remote side, which may contain multiple
+    /// messages. Document and awareness updates are applied using provided `origin`.
+    /// Returns a list of encoded replies, which should be sent back to the sender. Messages with
+    /// custom tags are skipped. Fails with `PermissionDenied` if the remote side has denied access
+    /// to the document.
+    #[uniffi::method(default(origin=None))]
+    pub fn handle(&self, payload: &[u8], origin: Option<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+        let mut decoder = DecoderV1::new(Cursor::new(payload));
+        let mut replies = Vec::new();
+        for message in MessageReader::new(&mut decoder) {
+            let message = message?;
+            if let Some(reply) = self.reply(message, origin.as_deref())? {
+                replies.push(reply.encode_v1());
+            }
+        }
+        Ok(replies)
+    }
+
+}
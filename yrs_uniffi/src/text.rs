@@ -1,16 +1,23 @@
-use crate::attrs::{into_yattrs, into_yvalue, parse_attrs};
-use crate::collection::SharedCollection;
-use crate::delta::YDelta::YInsert;
-use crate::delta::{y_into_delta, YDelta};
+use crate::doc::DocRef;
+use crate::attrs::{from_yattrs, from_yattrs_non_empty, into_yattrs, parse_attrs, YAttributes};
+use crate::collection::{Integrated, SharedCollection, Writable};
+use crate::delta::{check_delta, y_delta, y_insert, y_into_delta, YDelta};
+use crate::input::YIn;
+use crate::json::JsonEncoder;
 use crate::snapshots::YSnapshot;
-use crate::tools::Error;
-use crate::tools::Result;
+use crate::sticky::{YAssoc, YStickyIndex};
+use crate::subscription::YSubscription;
+use crate::tools::{check_index, check_range, check_str_index, check_str_range, str_len, Error, Result};
 use crate::transaction::YTransaction;
-use std::cell::RefCell;
-use std::ops::{Deref, DerefMut};
+use crate::weak::{YQuoteBounds, YWeakLink};
+use crate::lock::TryLock;
+use std::mem;
+use std::ops::Deref;
 use std::sync::Arc;
-use yrs::types::TYPE_REFS_TEXT;
-use yrs::{GetString, Out, Snapshot, Text, TextRef};
+use yrs::types::{Attrs, TYPE_REFS_TEXT};
+use yrs::types::text::{Diff, TextEvent, YChange};
+use yrs::branch::BranchPtr;
+use yrs::{GetString, Observable, Quotable, ReadTxn, Snapshot, Text, TextRef, TransactionMut};
 
 /// A shared data type used for collaborative text editing. It enables multiple users to add and
 /// remove chunks of text in efficient manner. This type is internally represented as a mutable
@@ -18,8 +25,10 @@ use yrs::{GetString, Out, Snapshot, Text, TextRef};
 /// allows to squash multiple consecutively inserted characters together as a single chunk of text
 /// even between transaction boundaries in order to preserve more efficient memory model.
 ///
-/// `YText` structure internally uses UTF-8 encoding and its length is described in a number of
-/// bytes rather than individual characters (a single UTF-8 code point can consist of many bytes).
+/// `YText` length and indexes are described in a number of UTF-16 code units rather than individual
+/// characters, the same way as in Kotlin strings, unless a document was created with
+/// `bytes_offset` set, in which case they count UTF-8 bytes. Preliminary instances always count
+/// UTF-16 code units.
 ///
 /// Like all Yrs shared data types, `YText` is resistant to the problem of interleaving (situation
 /// when characters inserted one after another may interleave with other peers concurrent inserts
@@ -28,20 +37,171 @@ use yrs::{GetString, Out, Snapshot, Text, TextRef};
 #[derive(uniffi::Object)]
 #[repr(transparent)]
 pub struct YText {
-    inner: Arc<RefCell<SharedCollection<String, TextRef>>>,
+    inner: Arc<TryLock<SharedCollection<String, TextRef>>>,
 }
 
-unsafe impl Sync for YText {}
-unsafe impl Send for YText {}
-
 impl YText {
     pub fn new(init: SharedCollection<String, TextRef>) -> Self {
         YText {
-            inner: Arc::new(RefCell::new(init)),
+            inner: Arc::new(TryLock::new(init)),
+        }
+    }
+
+    pub(crate) fn branch<T: ReadTxn>(&self, txn: &T) -> Result<BranchPtr> {
+        self.inner.borrow()?.branch(txn)
+    }
+
+    pub fn integrate(&self, txn: &mut TransactionMut, text_ref: TextRef) {
+        let doc = DocRef::from(txn.doc().clone());
+
+        let old_value = {
+            let mut guard = self.inner.lock_mut();
+            mem::replace(&mut *guard, SharedCollection::Integrated(Integrated::new(
+                text_ref.clone(),
+                doc,
+            )))
+        };
+
+        if let SharedCollection::Prelim(raw) = old_value {
+            text_ref.insert(txn, 0, &raw);
+        }
+    }
+
+    fn insert_attrs(
+        &self,
+        index: u32,
+        chunk: &str,
+        attributes: Option<Attrs>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                if attributes.is_none() {
+                    let index = check_str_index(&c, index)?;
+                    c.insert_str(index, chunk);
+                    Ok(())
+                } else {
+                    Err(Error::InvalidPrelimOp)
+                }
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
+                if let Some(attrs) = attributes {
+                    c.insert_with_attributes(txn, index, chunk, attrs);
+                    Ok(())
+                } else {
+                    c.insert(txn, index, chunk);
+                    Ok(())
+                }
+            }),
+        }
+    }
+
+    fn insert_embed_attrs(
+        &self,
+        index: u32,
+        embed: YIn,
+        attributes: Option<Attrs>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        embed.assert_prelim()?;
+
+        match self.inner.borrow_write()? {
+            Writable::Prelim(_) => Err(Error::InvalidPrelimOp),
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
+                if let Some(attrs) = attributes {
+                    c.insert_embed_with_attributes(txn, index, embed, attrs);
+                } else {
+                    c.insert_embed(txn, index, embed);
+                }
+                Ok(())
+            }),
+        }
+    }
+
+    fn format_attrs(
+        &self,
+        index: u32,
+        length: u32,
+        attrs: Attrs,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(_) => Err(Error::InvalidPrelimOp),
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
+                c.format(txn, index, length, attrs);
+                Ok(())
+            }),
+        }
+    }
+
+    fn push_attrs(
+        &self,
+        chunk: &str,
+        attributes: Option<Attrs>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                if attributes.is_some() {
+                    Err(Error::InvalidPrelimOp)
+                } else {
+                    c.push_str(chunk);
+                    Ok(())
+                }
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                if let Some(attrs) = attributes {
+                    let len = c.len(txn);
+                    c.insert_with_attributes(txn, len, chunk, attrs);
+                    Ok(())
+                } else {
+                    c.push(txn, chunk);
+                    Ok(())
+                }
+            }),
         }
     }
 }
 
+/// Event generated by [YText::observe] method. Emitted during transaction commit phase.
+#[derive(uniffi::Record)]
+pub struct YTextEvent {
+    /// The `YText` instance which changes triggered this event.
+    pub target: Arc<YText>,
+    /// List of text changes made over the event target, expressed in Quill delta format.
+    pub delta: Vec<YDelta>,
+    /// Origin of the transaction that produced this event, if any was provided.
+    pub origin: Option<Vec<u8>>,
+}
+
+impl YTextEvent {
+    fn new(event: &TextEvent, txn: &TransactionMut) -> Self {
+        let doc = DocRef::from(txn.doc().clone());
+        let target = YText::new(SharedCollection::integrated(event.target().clone(), doc.clone()));
+        let delta = event
+            .delta(txn)
+            .iter()
+            .map(|d| y_delta(d.clone(), doc.clone()))
+            .collect();
+        YTextEvent {
+            target: Arc::new(target),
+            delta,
+            origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+        }
+    }
+}
+
+/// Callback interface used to receive [YTextEvent]s. Callbacks are invoked synchronously on the
+/// thread committing the transaction, so they should not try to open new transactions on the same
+/// document.
+#[uniffi::export(callback_interface)]
+pub trait YTextObserver: Send + Sync {
+    fn on_change(&self, event: YTextEvent);
+}
+
 #[uniffi::export]
 impl YText {
     /// Creates a new preliminary instance of a `YText` shared data type, with its state initialized
@@ -53,7 +213,7 @@ impl YText {
     #[uniffi::constructor]
     pub fn new_with_text(init: Option<String>) -> Self {
         YText {
-            inner: Arc::new(RefCell::new(SharedCollection::prelim(
+            inner: Arc::new(TryLock::new(SharedCollection::prelim(
                 init.unwrap_or_default(),
             )))
         }
@@ -69,33 +229,46 @@ impl YText {
     /// Preliminary instances can be nested into other shared data types such as `YArray` and `YMap`.
     /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
     /// document store and cannot be nested again: attempt to do so will result in an exception.
-    #[inline]
-    pub fn prelim(&self) -> bool {
-        self.inner.borrow().is_prelim()
+    pub fn prelim(&self) -> Result<bool> {
+        Ok(self.inner.borrow()?.is_prelim())
     }
 
     /// Checks if current YArray reference is alive and has not been deleted by its parent collection.
     /// This method only works on already integrated shared types and will return false is current
     /// type is preliminary (has not been integrated into document).
-    #[inline]
-    pub fn alive(&self, txn: &YTransaction) -> bool {
-        self.inner.borrow().is_alive(txn)
+    pub fn alive(&self, txn: &YTransaction) -> Result<bool> {
+        self.inner.borrow()?.is_alive(txn)
     }
 
     /// Returns length of an underlying string stored in this `YText` instance,
-    /// understood as a number of UTF-8 encoded bytes.
+    /// understood as a number of UTF-16 code units (see `bytes_offset`).
     #[uniffi::method(default(txn=None))]
     pub fn length(&self, txn: Option<Arc<YTransaction>>) -> Result<u32> {
-        match self.inner.borrow().deref() {
-            SharedCollection::Prelim(c) => Ok(c.len() as u32),
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(str_len(c)),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.len(txn))),
         }
     }
 
+    /// Returns contents of this integrated `YText` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<String> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).text(c).to_string())
+            }),
+        }
+    }
+
     /// Returns an underlying shared string stored in this data type.
     #[uniffi::method(name = "toText", default(txn=None))]
     pub fn to_string(&self, txn: Option<Arc<YTransaction>>) -> Result<String> {
-        match self.inner.borrow().deref() {
+        match self.inner.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.clone()),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.get_string(txn))),
         }
@@ -103,9 +276,11 @@ impl YText {
 
     /// Inserts a given `chunk` of text into this `YText` instance, starting at a given `index`.
     ///
-    /// Optional object with defined `attributes` will be used to wrap provided text `chunk`
+    /// Optional JSON object with defined `attributes` will be used to wrap provided text `chunk`
     /// with a formatting blocks.`attributes` are only supported for a `YText` instance which
     /// already has been integrated into document store.
+    ///
+    /// Deprecated: use `insert_with_attributes`, which accepts typed `YAttributes`.
     #[uniffi::method(default(attributes=None, txn=None))]
     pub fn insert(
         &self,
@@ -113,33 +288,65 @@ impl YText {
         chunk: &str,
         attributes: Option<String>,
         txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        self.insert_attrs(index, chunk, parse_attrs(attributes)?, txn)
+    }
+
+    /// Inserts a given `chunk` of text into this `YText` instance, starting at a given `index`,
+    /// wrapping it with a formatting blocks described by `attributes`. Non-empty `attributes` are
+    /// only supported for a `YText` instance which already has been integrated into document
+    /// store.
+    #[uniffi::method(default(txn=None))]
+    pub fn insert_with_attributes(
+        &self,
+        index: u32,
+        chunk: &str,
+        attributes: YAttributes,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        self.insert_attrs(index, chunk, from_yattrs_non_empty(&attributes), txn)
+    }
+
+    /// Inserts a given `embed` object into this `YText` instance, starting at a given `index`.
+    /// Embed can be either a plain value or a preliminary instance of another shared type, which
+    /// will be integrated into document together with its contents.
+    ///
+    /// Optional JSON object with defined `attributes` will be used to wrap provided `embed`
+    /// with a formatting blocks. This method only works for `YText` instances that already have
+    /// been integrated into document store.
+    ///
+    /// Deprecated: use `insert_embed_with_attributes`, which accepts typed `YAttributes`.
+    #[uniffi::method(default(attributes=None, txn=None))]
+    pub fn insert_embed(
+        &self,
+        index: u32,
+        embed: YIn,
+        attributes: Option<String>,
+        txn: Option<Arc<YTransaction>>,
     ) -> Result<()> {
         let attributes = parse_attrs(attributes)?;
+        self.insert_embed_attrs(index, embed, attributes, txn)
+    }
 
-        match self.inner.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
-                if let None = attributes {
-                    c.insert_str(index as usize, chunk);
-                    Ok(())
-                } else {
-                    Err(Error::InvalidPrelimOp)
-                }
-            }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
-                if let Some(attrs) = attributes {
-                    c.insert_with_attributes(txn, index, chunk, attrs);
-                    Ok(())
-                } else {
-                    c.insert(txn, index, chunk);
-                    Ok(())
-                }
-            }),
-        }
+    /// Inserts a given `embed` object into this `YText` instance, starting at a given `index`,
+    /// wrapping it with a formatting blocks described by `attributes`. This method only works for
+    /// `YText` instances that already have been integrated into document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn insert_embed_with_attributes(
+        &self,
+        index: u32,
+        embed: YIn,
+        attributes: YAttributes,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        self.insert_embed_attrs(index, embed, from_yattrs_non_empty(&attributes), txn)
     }
 
     /// Wraps an existing piece of text within a range described by `index`-`length` parameters with
-    /// formatting blocks containing provided `attributes` metadata. This method only works for
+    /// formatting blocks containing provided `attributes` JSON object. This method only works for
     /// `YText` instances that already have been integrated into document store.
+    ///
+    /// Deprecated: use `format_with_attributes`, which accepts typed `YAttributes`.
     #[uniffi::method(default(txn=None))]
     pub fn format(
         &self,
@@ -152,21 +359,31 @@ impl YText {
             Some(attrs) => attrs,
             None => return Err(Error::InvalidFmt),
         };
+        self.format_attrs(index, length, attrs, txn)
+    }
 
-        match &self.inner.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
-                c.format(txn, index, length, attrs);
-                Ok(())
-            }),
-        }
+    /// Wraps an existing piece of text within a range described by `index`-`length` parameters with
+    /// formatting blocks containing provided `attributes`. Attributes set to `Null` remove
+    /// formatting. This method only works for `YText` instances that already have been integrated
+    /// into document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn format_with_attributes(
+        &self,
+        index: u32,
+        length: u32,
+        attributes: YAttributes,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        self.format_attrs(index, length, from_yattrs(&attributes), txn)
     }
 
     /// Appends a given `chunk` of text at the end of current `YText` instance.
     ///
-    /// Optional object with defined `attributes` will be used to wrap provided text `chunk`
+    /// Optional JSON object with defined `attributes` will be used to wrap provided text `chunk`
     /// with a formatting blocks.`attributes` are only supported for a `YText` instance which
     /// already has been integrated into document store.
+    ///
+    /// Deprecated: use `push_with_attributes`, which accepts typed `YAttributes`.
     #[uniffi::method(default(attributes=None, txn=None))]
     pub fn push(
         &self,
@@ -174,47 +391,43 @@ impl YText {
         attributes: Option<String>,
         txn: Option<Arc<YTransaction>>,
     ) -> Result<()> {
-        let attributes = parse_attrs(attributes)?;
+        self.push_attrs(chunk, parse_attrs(attributes)?, txn)
+    }
 
-        match self.inner.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(ref mut c) => {
-                if let Some(_) = attributes {
-                    Err(Error::InvalidPrelimOp)
-                } else {
-                    c.push_str(chunk);
-                    Ok(())
-                }
-            }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
-                if let Some(attrs) = attributes {
-                    let len = c.len(txn);
-                    c.insert_with_attributes(txn, len, chunk, attrs);
-                    Ok(())
-                } else {
-                    c.push(txn, chunk);
-                    Ok(())
-                }
-            }),
-        }
+    /// Appends a given `chunk` of text at the end of current `YText` instance, wrapping it with
+    /// a formatting blocks described by `attributes`. Non-empty `attributes` are only supported
+    /// for a `YText` instance which already has been integrated into document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn push_with_attributes(
+        &self,
+        chunk: &str,
+        attributes: YAttributes,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<()> {
+        self.push_attrs(chunk, from_yattrs_non_empty(&attributes), txn)
     }
 
     /// Deletes a specified range of of characters, starting at a given `index`.
-    /// Both `index` and `length` are counted in terms of a number of UTF-8 character bytes.
+    /// Both `index` and `length` are counted in terms of a number of UTF-16 code units
+    /// (see `bytes_offset`).
     #[uniffi::method(default(txn=None))]
     pub fn delete(&self, index: u32, length: u32, txn: Option<Arc<YTransaction>>) -> Result<()> {
-        match self.inner.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(ref mut c) => {
-                c.drain((index as usize)..((index + length) as usize));
+        match self.inner.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                let range = check_str_range(&c, index, length)?;
+                c.drain(range);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
                 c.remove_range(txn, index, length);
                 Ok(())
             }),
         }
     }
 
-    /// Returns the Delta representation of this YText type.
+    /// Returns the Delta representation of this YText type. Deltas between `snapshot` and
+    /// `prev_snapshot` can only be computed within read-write transactions.
     #[uniffi::method(default(snapshot=None,prev_snapshot=None,txn=None))]
     pub fn to_delta(
         &self,
@@ -222,8 +435,14 @@ impl YText {
         prev_snapshot: Option<Arc<YSnapshot>>,
         txn: Option<Arc<YTransaction>>,
     ) -> Result<Vec<YDelta>> {
-        match self.inner.borrow().deref() {
+        match self.inner.borrow()?.deref() {
             SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) if snapshot.is_none() && prev_snapshot.is_none() => {
+                c.readonly(txn, |c, txn| {
+                    let delta = c.diff(txn, |change| change);
+                    Ok(y_diffs(delta, &txn.doc().clone().into()))
+                })
+            }
             SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
                 let hi: Option<Snapshot> = if let Some(snap) = snapshot {
                     let snap = snap.clone().deref().deref().clone();
@@ -239,42 +458,110 @@ impl YText {
                 };
 
                 let delta = c.diff_range(txn, hi.as_ref(), lo.as_ref(), |change| change);
-
-                let mut array: Vec<YDelta> = vec![];
-                for d in delta {
-                    let attrs = match d.attributes {
-                        None => None,
-                        Some(attrs) => Some(into_yattrs(*attrs))
-                    };
-
-                    if let Out::Any(any) = d.insert {
-                        let d = YInsert(into_yvalue(&any), attrs);
-                        array.push(d);
-                    } else {
-                        return Err(Error::InvalidData(d.insert.to_string(txn)));
-                    }
-                }
-                Ok(array)
+                Ok(y_diffs(delta, &txn.doc().clone().into()))
             }),
         }
     }
 
     #[uniffi::method(default(txn=None))]
     pub fn apply_delta(&self, delta: Vec<YDelta>, txn: Option<Arc<YTransaction>>) -> Result<()> {
-        match self.inner.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(_) => {
+        match self.inner.borrow_write()? {
+            Writable::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 let mut result = Vec::new();
                 for yd in delta.iter() {
-                    let d = y_into_delta(yd);
+                    let d = y_into_delta(yd)?;
                     result.push(d);
                 }
+                check_delta(&result, c.len(txn), txn.doc().offset_kind())?;
                 c.apply_delta(txn, result);
 
                 Ok(())
             }),
         }
     }
+
+    /// Subscribes to all operations happening over this instance of `YText`. All changes are
+    /// batched and eventually triggered during transaction commit phase. Returned subscription
+    /// keeps the observer alive: drop it or call `unsubscribe` to stop receiving events.
+    ///
+    /// This method only works for `YText` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe(
+        &self,
+        observer: Box<dyn YTextObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<Arc<YSubscription>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe(move |txn, e| {
+                    observer.on_change(YTextEvent::new(e, txn));
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+
+    /// Returns a sticky index pointing to a given `index` of this text (counted the same way as in `insert`), which
+    /// will remain valid when concurrent changes are made. Returns null if `index` is beyond the
+    /// length of this `YText`.
+    ///
+    /// This method only works for `YText` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn sticky_index(
+        &self,
+        index: u32,
+        assoc: YAssoc,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<Option<Arc<YStickyIndex>>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(YStickyIndex::at(txn, c, index, assoc))
+            }),
+        }
+    }
+
+    /// Returns a preliminary `YWeakLink` quoting a range of characters between `start` and `end`
+    /// indexes. By default both sides of the range are inclusive. Even if a side of the range is
+    /// exclusive, an index of that side must point to an existing element, as it's used as
+    /// a point of reference. The returned link can be inserted into any other shared collection.
+    ///
+    /// This method only works on `YText` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(bounds=None, txn=None))]
+    pub fn quote(
+        &self,
+        start: u32,
+        end: u32,
+        bounds: Option<YQuoteBounds>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<Arc<YWeakLink>> {
+        match self.inner.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let range = YQuoteBounds::check(bounds, start, end, c.len(txn))?;
+                let prelim = c.quote(txn, range).map_err(|_| Error::OutOfBounds {
+                    index: end,
+                    len: c.len(txn),
+                })?;
+                Ok(Arc::new(YWeakLink::from_prelim(prelim)))
+            }),
+        }
+    }
+}
+
+fn y_diffs(delta: Vec<Diff<YChange>>, doc: &DocRef) -> Vec<YDelta> {
+    let mut array: Vec<YDelta> = vec![];
+    for d in delta {
+        let attrs = d.attributes.map(|attrs| into_yattrs(*attrs));
+
+        array.push(y_insert(d.insert, attrs, doc.clone()));
+    }
+    array
 }
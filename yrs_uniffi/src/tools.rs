@@ -1,5 +1,8 @@
 use crate::tools;
 use thiserror::Error;
+use yrs::encoding::read;
+use yrs::error::UpdateError;
+use yrs::sync::awareness;
 use yrs::OffsetKind;
 
 #[cfg(target_arch = "wasm32")]
@@ -13,8 +16,29 @@ pub(crate) fn offset_kind() -> OffsetKind {
 }
 
 
+/// Category of a failure, which happened while decoding binary or JSON payloads.
+#[derive(uniffi::Enum)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YDecodeErrorKind {
+    /// Payload ended before all expected data could be read.
+    EndOfBuffer,
+    /// Variable length integer was malformed or didn't fit into expected type.
+    InvalidVarInt,
+    /// Payload contained an unexpected value, eg. unknown tag or type reference.
+    UnexpectedValue,
+    /// Payload declared more elements than could be allocated.
+    NotEnoughMemory,
+    /// Payload contained malformed JSON.
+    InvalidJson,
+    /// Payload contained malformed XML.
+    InvalidXml,
+    /// Decoded value didn't match expected type.
+    TypeMismatch,
+    Other,
+}
+
 #[derive(uniffi::Error, Error, Debug)]
-pub(crate) enum Error {
+pub enum Error {
     #[error("cannot modify transaction in this context")]
     InvalidTransactionCtx,
     #[error("shared collection has been destroyed")]
@@ -25,16 +49,24 @@ pub(crate) enum Error {
     AnotherTx,
     #[error("another read-write transaction is in progress")]
     AnotherRwTx,
-    // OutOfBounds, //"index outside of the bounds of an array";
-    // KeyNotFound, //"key was not found in a map";
+    #[error("index {index} is outside of the bounds of a collection of length {len}")]
+    OutOfBounds { index: u32, len: u32 },
+    #[error("key '{key}' was not found in a map")]
+    KeyNotFound { key: String },
     #[error("preliminary type doesn't support this operation")]
     InvalidPrelimOp,
     #[error("given object cannot be used as formatting attributes")]
     InvalidFmt,
-    // InvalidXmlAttrs, //"given object cannot be used as XML attributes";
-    // NotXmlType, //"provided object is not a valid XML shared type";
+    #[error("given object cannot be used as XML attributes")]
+    InvalidXmlAttrs,
+    #[error("'{name}' is not a valid XML name")]
+    InvalidXmlName { name: String },
+    #[error("provided object is not a valid XML shared type")]
+    NotXmlType,
     #[error("this operation only works on preliminary types")]
-    NotPrelim, 
+    NotPrelim,
+    #[error("the same preliminary instance cannot be inserted more than once")]
+    DuplicatePrelim,
 
     #[error("invalid delta format")]
     InvalidDelta,
@@ -44,8 +76,153 @@ pub(crate) enum Error {
     #[error("Invalid data: {0}")]
     InvalidData(String),
 
+    #[error("failed to decode payload ({kind:?}): {reason}")]
+    Decode { kind: YDecodeErrorKind, reason: String },
+
     #[error("Invalid parent")]
     InvalidParent,
+
+    #[error("document history is not available when garbage collection is enabled")]
+    GcEnabled,
+
+    #[error("document is read-only")]
+    ReadOnlyDoc,
+
+    #[error("permission denied: {reason}")]
+    PermissionDenied { reason: String },
+
+    #[error("I/O error: {0}")]
+    Io(String),
+}
+
+impl From<read::Error> for Error {
+    fn from(e: read::Error) -> Self {
+        let kind = match &e {
+            read::Error::EndOfBuffer(_) => YDecodeErrorKind::EndOfBuffer,
+            read::Error::InvalidVarInt => YDecodeErrorKind::InvalidVarInt,
+            read::Error::UnexpectedValue => YDecodeErrorKind::UnexpectedValue,
+            read::Error::NotEnoughMemory(_) => YDecodeErrorKind::NotEnoughMemory,
+            read::Error::InvalidJSON(_) => YDecodeErrorKind::InvalidJson,
+            read::Error::TypeMismatch(_) => YDecodeErrorKind::TypeMismatch,
+            read::Error::Custom(_) => YDecodeErrorKind::Other,
+        };
+        Error::Decode {
+            kind,
+            reason: e.to_string(),
+        }
+    }
+}
+
+impl From<uniffi::UnexpectedUniFFICallbackError> for Error {
+    fn from(e: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        Error::Custom(e.reason)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode {
+            kind: YDecodeErrorKind::InvalidJson,
+            reason: e.to_string(),
+        }
+    }
+}
+
+impl From<awareness::Error> for Error {
+    fn from(e: awareness::Error) -> Self {
+        match e {
+            awareness::Error::Serde(e) => e.into(),
+            other => Error::InvalidData(other.to_string()),
+        }
+    }
+}
+
+impl From<UpdateError> for Error {
+    fn from(e: UpdateError) -> Self {
+        match e {
+            UpdateError::InvalidParent(_, _) => Error::InvalidParent,
+        }
+    }
 }
 
 pub(crate) type Result<T> = std::result::Result<T, tools::Error>;
+
+/// Checks if `index` can be used to insert elements into a collection of a given length. Index
+/// equal to `len` is valid, as it points to the end of a collection.
+pub(crate) fn check_index(index: u32, len: u32) -> Result<()> {
+    if index > len {
+        Err(Error::OutOfBounds { index, len })
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks if `index` points to an existing element of a collection of a given length.
+pub(crate) fn check_element(index: u32, len: u32) -> Result<()> {
+    if index >= len {
+        Err(Error::OutOfBounds { index, len })
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks if a range of `length` elements starting at `index` fits within a collection of a given
+/// length.
+pub(crate) fn check_range(index: u32, length: u32, len: u32) -> Result<()> {
+    match index.checked_add(length) {
+        Some(end) if end <= len => Ok(()),
+        Some(end) => Err(Error::OutOfBounds { index: end, len }),
+        None => Err(Error::OutOfBounds { index: u32::MAX, len }),
+    }
+}
+
+/// Returns length of a preliminary string, counted in the same units as offsets of integrated
+/// texts (see [offset_kind]).
+pub(crate) fn str_len(s: &str) -> u32 {
+    match offset_kind() {
+        OffsetKind::Bytes => s.len() as u32,
+        OffsetKind::Utf16 => s.encode_utf16().count() as u32,
+    }
+}
+
+/// Converts `index` counted in units of [offset_kind] into a byte index within a string. Returns
+/// `None` if it's past the end of a string or it splits a character.
+fn str_byte_index(s: &str, index: u32) -> Option<usize> {
+    let mut offset = 0;
+    for (i, c) in s.char_indices() {
+        if offset == index {
+            return Some(i);
+        } else if offset > index {
+            return None;
+        }
+        offset += match offset_kind() {
+            OffsetKind::Bytes => c.len_utf8() as u32,
+            OffsetKind::Utf16 => c.len_utf16() as u32,
+        };
+    }
+    (offset == index).then_some(s.len())
+}
+
+/// Checks if `index` can be used to insert into a preliminary string: it must not exceed the
+/// string's length and must not split a character. Returns a corresponding byte index.
+pub(crate) fn check_str_index(s: &str, index: u32) -> Result<usize> {
+    str_byte_index(s, index).ok_or_else(|| Error::OutOfBounds {
+        index,
+        len: str_len(s),
+    })
+}
+
+/// Checks if a range of `length` units starting at `index` can be removed from a preliminary
+/// string without splitting characters. Returns a corresponding range of bytes.
+pub(crate) fn check_str_range(s: &str, index: u32, length: u32) -> Result<std::ops::Range<usize>> {
+    check_range(index, length, str_len(s))?;
+    let start = check_str_index(s, index)?;
+    let end = check_str_index(s, index + length)?;
+    Ok(start..end)
+}
@@ -1,63 +1,103 @@
+use crate::doc::DocRef;
 use crate::tools;
 use crate::tools::Error;
 use crate::tools::Result;
-use std::cell::RefCell;
+use crate::lock::TryLock;
 use std::collections::HashMap;
 use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::sync::{Arc, RwLockReadGuard, RwLockWriteGuard};
 use yrs::block::ClientID;
-use yrs::error::UpdateError;
 use yrs::updates::decoder::Decode;
 use yrs::updates::encoder::Encode;
-use yrs::{ReadTxn, StateVector, TransactionMut, Update};
+use yrs::{ReadTxn, StateVector, Store, Transaction, TransactionMut, Update};
 
-pub struct YTransactionInner {
-    // SAFETY NOTE: We erase the lifetime of TransactionMut to 'static below and rely on the
-    // embedding application's guarantee that there are no concurrent calls into this library,
-    // and that any given YTransaction is only used from one thread at a time (although the
-    // thread may change between calls). Misuse outside those guarantees can cause UB.
-    pub inner: ManuallyDrop<TransactionMut<'static>>,
-    // pub cached_before_state: Option<PyObject>,
-    pub committed: bool,
+/// A view over a transaction used by read operations, which can be backed by either read-only or
+/// read-write transaction.
+pub struct ReadTxnRef<'a> {
+    store: &'a Store,
+    doc: &'a DocRef,
 }
 
-impl ReadTxn for YTransactionInner {
-    fn store(&self) -> &yrs::Store {
-        self.deref().store()
+impl<'a> ReadTxnRef<'a> {
+    pub fn new<T: ReadTxn>(txn: &'a T, doc: &'a DocRef) -> Self {
+        ReadTxnRef {
+            store: txn.store(),
+            doc,
+        }
     }
-}
 
-impl Deref for YTransactionInner {
-    type Target = TransactionMut<'static>;
+    #[inline]
+    pub fn doc(&self) -> &'a DocRef {
+        self.doc
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+impl ReadTxn for ReadTxnRef<'_> {
+    #[inline]
+    fn store(&self) -> &Store {
+        self.store
     }
 }
 
-impl DerefMut for YTransactionInner {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
+enum Txn {
+    ReadOnly(Transaction<'static>),
+    ReadWrite(Box<TransactionMut<'static>>),
+}
+
+pub struct YTransactionInner {
+    // SAFETY NOTE: We erase the lifetime of a transaction to 'static below. This is sound, as
+    // we hold our own reference to a document owning the store locked by the transaction,
+    // which is dropped only after the transaction itself.
+    // Once committed, the transaction is dropped and must no longer be accessed: YTransaction
+    // only exposes it via `borrow`/`borrow_mut`, which check that first.
+    txn: ManuallyDrop<Txn>,
+    doc: DocRef,
+    // pub cached_before_state: Option<PyObject>,
+    pub committed: bool,
+}
+
+impl ReadTxn for YTransactionInner {
+    fn store(&self) -> &Store {
+        match self.txn.deref() {
+            Txn::ReadOnly(txn) => txn.store(),
+            Txn::ReadWrite(txn) => txn.store(),
+        }
     }
 }
 
 impl Drop for YTransactionInner {
     fn drop(&mut self) {
         if !self.committed {
-            self.commit();
+            let _ = self.commit();
         }
     }
 }
 
 impl YTransactionInner {
-    pub fn new(txn: TransactionMut<'static>) -> Self {
+    fn new(txn: Txn, doc: DocRef) -> Self {
         YTransactionInner {
-            inner: ManuallyDrop::new(txn),
+            txn: ManuallyDrop::new(txn),
+            doc,
             // cached_before_state: None,
             committed: false,
         }
     }
+
+    /// Returns a view used by read operations, available for both read-only and read-write
+    /// transactions.
+    pub fn read(&self) -> ReadTxnRef<'_> {
+        ReadTxnRef::new(self, &self.doc)
+    }
+
+    /// Returns an underlying read-write transaction. Fails with [Error::InvalidTransactionCtx] if
+    /// this is a read-only transaction.
+    pub fn write(&mut self) -> Result<&mut TransactionMut<'static>> {
+        match self.txn.deref_mut() {
+            Txn::ReadWrite(txn) => Ok(txn),
+            Txn::ReadOnly(_) => Err(Error::InvalidTransactionCtx),
+        }
+    }
 }
 
 impl YTransactionInner {
@@ -76,12 +116,15 @@ impl YTransactionInner {
 
     /// Triggers a post-update series of operations without `free`ing the transaction. This includes
     /// compaction and optimization of internal representation of updates, triggering events etc.
+    /// Read-only transactions are simply released.
     /// Ypy transactions are auto-committed when they are `free`d.
     pub fn commit(&mut self) -> Result<()> {
         if !self.committed {
-            self.deref_mut().commit();
+            if let Txn::ReadWrite(txn) = self.txn.deref_mut() {
+                txn.commit();
+            }
             self.committed = true;
-            unsafe { ManuallyDrop::drop(&mut self.inner) };
+            unsafe { ManuallyDrop::drop(&mut self.txn) };
 
             Ok(())
         } else {
@@ -92,64 +135,89 @@ impl YTransactionInner {
 
 #[derive(uniffi::Object)]
 pub struct YTransaction {
-    inner: Arc<RefCell<YTransactionInner>>,
+    inner: Arc<TryLock<YTransactionInner>>,
+    read_only: bool,
 }
 
-// SAFETY: The outer Arc<RefCell<...>> is not Send nor Sync by default. We provide
-// these impls relying on the embedding application's guarantees:
-// - Calls into this library are not concurrent.
-// - A given object is only used by one thread at a time (though the thread may change between calls).
-// Violating these guarantees can cause undefined behavior.
-unsafe impl Sync for YTransaction {}
-unsafe impl Send for YTransaction {}
 
 impl YTransaction {
-    pub fn get_inner(&self) -> Arc<RefCell<YTransactionInner>> {
-        self.inner.clone()
+    /// Acquires shared access to an underlying transaction. Fails with [Error::AnotherTx] if it's
+    /// being modified at the moment, or with [Error::TxnCommitted] if it has been committed.
+    pub fn borrow(&self) -> Result<RwLockReadGuard<'_, YTransactionInner>> {
+        let inner = self.inner.borrow()?;
+        if inner.committed {
+            Err(Error::TxnCommitted)
+        } else {
+            Ok(inner)
+        }
+    }
+
+    /// Acquires exclusive access to an underlying transaction. Fails with [Error::AnotherTx] if
+    /// it's being used at the moment, or with [Error::TxnCommitted] if it has been committed.
+    pub fn borrow_mut(&self) -> Result<RwLockWriteGuard<'_, YTransactionInner>> {
+        let inner = self.inner.borrow_mut()?;
+        if inner.committed {
+            Err(Error::TxnCommitted)
+        } else {
+            Ok(inner)
+        }
+    }
+
+    /// Wraps a read-only transaction of a given `doc`.
+    pub fn new_read_only(txn: Transaction<'_>, doc: DocRef) -> Self {
+        // SAFETY: see the note above YTransactionInner.
+        let txn: Transaction<'static> = unsafe { std::mem::transmute(txn) };
+        YTransaction {
+            inner: Arc::new(TryLock::new(YTransactionInner::new(Txn::ReadOnly(txn), doc))),
+            read_only: true,
+        }
     }
 
     fn try_apply(&self, update: Update) -> Result<()> {
-        self.get_inner()
-            .borrow_mut()
+        self.borrow_mut()?
+            .write()?
             .apply_update(update)
-            .map_err(|e| match e {
-                UpdateError::InvalidParent(_, _) => tools::Error::InvalidParent,
-            })
+            .map_err(tools::Error::from)
     }
 }
 
 #[uniffi::export]
 impl YTransaction {
+    /// Returns true if this transaction has been created with `YDoc.readTransaction` and can only
+    /// be used by read operations.
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
     /// Returns state vector describing the state of the document
     /// at the moment when the transaction began.
-    pub fn before_state(&self) -> HashMap<ClientID, u32> {
-        self.get_inner()
-            .borrow()
-            .before_state()
-            .iter()
-            .map(|(x, y)| (*x, *y))
-            .collect()
+    pub fn before_state(&self) -> Result<HashMap<ClientID, u32>> {
+        let inner = self.borrow()?;
+        let state = match inner.txn.deref() {
+            Txn::ReadWrite(txn) => txn.before_state().clone(),
+            // read-only transactions cannot change the document
+            Txn::ReadOnly(txn) => txn.state_vector(),
+        };
+        Ok(state.iter().map(|(x, y)| (*x, *y)).collect())
     }
 
     /// Returns state vector describing the current state of
     /// the document.
-    pub fn after_state(&self) -> HashMap<ClientID, u32> {
-        let state_map: HashMap<u64, u32> = self
-            .get_inner()
-            .borrow()
-            .after_state()
-            .iter()
-            .map(|(x, y)| (*x, *y))
-            .collect();
-
-        state_map
+    pub fn after_state(&self) -> Result<HashMap<ClientID, u32>> {
+        let inner = self.borrow()?;
+        let state = match inner.txn.deref() {
+            Txn::ReadWrite(txn) => txn.after_state().clone(),
+            Txn::ReadOnly(txn) => txn.state_vector(),
+        };
+        Ok(state.iter().map(|(x, y)| (*x, *y)).collect())
     }
 
-    pub fn origin(&self) -> Option<Vec<u8>> {
-        let inner = self.get_inner();
-        let inner = inner.borrow();
-        let origin = inner.origin()?;
-        Some(origin.as_ref().to_vec())
+    pub fn origin(&self) -> Result<Option<Vec<u8>>> {
+        let inner = self.borrow()?;
+        match inner.txn.deref() {
+            Txn::ReadWrite(txn) => Ok(txn.origin().map(|origin| origin.as_ref().to_vec())),
+            Txn::ReadOnly(_) => Ok(None),
+        }
     }
 
     /// Triggers a post-update series of operations without `free`ing the transaction. This includes
@@ -157,7 +225,7 @@ impl YTransaction {
     /// ywasm transactions are auto-committed when they are `free`d.
     #[uniffi::method]
     pub fn commit(&self) -> Result<()> {
-        self.get_inner().borrow_mut().commit()?;
+        self.inner.borrow_mut()?.commit()?;
         Ok(())
     }
 
@@ -188,14 +256,14 @@ impl YTransaction {
     ///     remoteTxn.free()
     /// }
     /// ```
-    pub fn state_vector_v1(&self) -> Vec<u8> {
-        let sv = self.get_inner().borrow().state_vector();
-        sv.encode_v1()
+    pub fn state_vector_v1(&self) -> Result<Vec<u8>> {
+        let sv = self.borrow()?.state_vector();
+        Ok(sv.encode_v1())
     }
 
-    pub fn state_vector_v2(&self) -> Vec<u8> {
-        let sv = self.get_inner().borrow().state_vector();
-        sv.encode_v2()
+    pub fn state_vector_v2(&self) -> Result<Vec<u8>> {
+        let sv = self.borrow()?.state_vector();
+        Ok(sv.encode_v2())
     }
 
     /// Encodes all updates that have happened since a given version `vector` into a compact delta
@@ -227,8 +295,8 @@ impl YTransaction {
     /// ```
     pub fn diff_v1(&self, vector: Vec<u8>) -> Result<Vec<u8>> {
         match StateVector::decode_v1(vector.to_vec().as_slice()) {
-            Ok(sv) => Ok(self.get_inner().borrow().encode_diff_v1(&sv)),
-            Err(e) => Err(tools::Error::InvalidData(e.to_string())),
+            Ok(sv) => Ok(self.borrow()?.encode_diff_v1(&sv)),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -261,8 +329,8 @@ impl YTransaction {
     /// ```
     pub fn diff_v2(&self, vector: Vec<u8>) -> Result<Vec<u8>> {
         match StateVector::decode_v1(vector.to_vec().as_slice()) {
-            Ok(sv) => Ok(self.get_inner().borrow().encode_diff_v2(&sv)),
-            Err(e) => Err(tools::Error::InvalidData(e.to_string())),
+            Ok(sv) => Ok(self.borrow()?.encode_diff_v2(&sv)),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -294,7 +362,7 @@ impl YTransaction {
     pub fn apply_v1(&self, diff: Vec<u8>) -> Result<()> {
         match Update::decode_v1(diff.as_slice()) {
             Ok(update) => self.try_apply(update),
-            Err(e) => Err(tools::Error::InvalidData(e.to_string())),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -326,33 +394,34 @@ impl YTransaction {
     pub fn apply_v2(&self, diff: Vec<u8>) -> Result<()> {
         match Update::decode_v2(diff.as_slice()) {
             Ok(update) => self.try_apply(update),
-            Err(e) => Err(tools::Error::InvalidData(e.to_string())),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn encode_update(&self) -> Vec<u8> {
-        self.get_inner().borrow().encode_update_v1()
+    pub fn encode_update(&self) -> Result<Vec<u8>> {
+        Ok(self.borrow_mut()?.write()?.encode_update_v1())
     }
 
-    pub fn encode_update_v2(&self) -> Vec<u8> {
-        self.get_inner().borrow().encode_update_v2()
+    pub fn encode_update_v2(&self) -> Result<Vec<u8>> {
+        Ok(self.borrow_mut()?.write()?.encode_update_v2())
     }
 
     /// Force garbage collection of the deleted elements, regardless of a parent doc was created
     /// with `gc` option turned on or off.
     pub fn gc(&self) -> Result<()> {
-        self.get_inner().borrow_mut().gc(None);
+        self.borrow_mut()?.write()?.gc(None);
         Ok(())
     }
 }
 
 impl<'doc> From<TransactionMut<'doc>> for YTransaction {
     fn from(value: TransactionMut<'doc>) -> Self {
-        // SAFETY: We extend the lifetime of TransactionMut here based on external guarantees.
-        // See the note above YTransactionInner and the unsafe Send/Sync impls for details.
+        let doc = DocRef::from(value.doc().clone());
+        // SAFETY: see the note above YTransactionInner.
         let txn: TransactionMut<'static> = unsafe { std::mem::transmute(value) };
         YTransaction {
-            inner: Arc::new(RefCell::new(YTransactionInner::new(txn))),
+            inner: Arc::new(TryLock::new(YTransactionInner::new(Txn::ReadWrite(Box::new(txn)), doc))),
+            read_only: false,
         }
     }
 }
@@ -0,0 +1,192 @@
+use crate::attrs::YAttributes;
+use crate::doc::YDoc;
+use crate::out::YOut;
+use crate::subscription::YSubscription;
+use crate::tools::{Error, Result};
+use crate::lock::TryLock;
+use std::sync::Arc;
+use yrs::undo::{Event, EventKind, Options, UndoManager};
+use yrs::{Origin, Transact, TransactionMut};
+
+/// Configuration of a [YUndoManager].
+#[derive(uniffi::Record)]
+pub struct YUndoManagerOptions {
+    /// Changes made within this time window (in milliseconds) since the last captured change are
+    /// merged together into a single undo step. Defaults to 500ms.
+    #[uniffi(default = None)]
+    pub capture_timeout_millis: Option<u64>,
+
+    /// Transaction origins tracked by undo manager. If not provided, only changes made within
+    /// transactions without any origin are tracked.
+    #[uniffi(default = None)]
+    pub tracked_origins: Option<Vec<Vec<u8>>>,
+}
+
+#[derive(uniffi::Enum)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum YUndoEventKind {
+    Undo,
+    Redo,
+}
+
+/// Event emitted by [YUndoManager] whenever a stack item has been added, updated or popped.
+#[derive(uniffi::Record)]
+pub struct YUndoEvent {
+    /// Informs whether related stack item belongs to undo or redo stack.
+    pub kind: YUndoEventKind,
+    /// Origin of the transaction that produced this event, if any was provided.
+    pub origin: Option<Vec<u8>>,
+    /// Custom metadata stored together with related stack item, ie. cursor position that should
+    /// be restored once the item is popped.
+    pub meta: YAttributes,
+}
+
+/// Callback interface used to receive [YUndoEvent]s. When called for added or updated stack items,
+/// returned value (if any) replaces metadata stored within related stack item.
+#[uniffi::export(callback_interface)]
+pub trait YUndoObserver: Send + Sync {
+    fn on_change(&self, event: YUndoEvent) -> Option<YAttributes>;
+}
+
+/// Undo manager is a structure used to perform undo/redo operations over the changes made on
+/// a selected set of shared types (a scope) within a single document. Changes made within a
+/// capture timeout window are grouped together into a single undo step.
+///
+/// Callbacks registered on undo manager should not call its methods back, as they are invoked
+/// while undo manager is performing an operation: such calls fail with `AnotherTx`.
+#[derive(uniffi::Object)]
+pub struct YUndoManager(TryLock<UndoManager<YAttributes>>);
+
+impl YUndoManager {
+    fn observer(
+        observer: Box<dyn YUndoObserver>,
+    ) -> impl Fn(&TransactionMut, &mut Event<YAttributes>) + Send + Sync + 'static {
+        move |_, e| {
+            let kind = match e.kind() {
+                EventKind::Undo => YUndoEventKind::Undo,
+                EventKind::Redo => YUndoEventKind::Redo,
+            };
+            let event = YUndoEvent {
+                kind,
+                origin: e.origin().map(|origin| origin.as_ref().to_vec()),
+                meta: e.meta().clone(),
+            };
+            if let Some(meta) = observer.on_change(event) {
+                *e.meta_mut() = meta;
+            }
+        }
+    }
+}
+
+#[uniffi::export]
+impl YUndoManager {
+    /// Creates a new undo manager tracking changes made over given `scopes`, which must be shared
+    /// types already integrated into `doc`.
+    #[uniffi::constructor(default(options=None))]
+    pub fn new(doc: &YDoc, scopes: Vec<YOut>, options: Option<YUndoManagerOptions>) -> Result<Self> {
+        let mut opts = Options::default();
+        if let Some(options) = options {
+            if let Some(timeout) = options.capture_timeout_millis {
+                opts.capture_timeout_millis = timeout;
+            }
+            for origin in options.tracked_origins.unwrap_or_default() {
+                opts.tracked_origins.insert(Origin::from(origin.as_slice()));
+            }
+        }
+
+        let branches = {
+            let txn = doc.0.try_transact().map_err(|_| Error::AnotherRwTx)?;
+            let mut branches = Vec::with_capacity(scopes.len());
+            for scope in scopes.iter() {
+                branches.push(scope.branch(&txn)?);
+            }
+            branches
+        };
+
+        let mut mgr = UndoManager::with_options(&doc.0, opts);
+        for branch in branches.iter() {
+            mgr.expand_scope(branch);
+        }
+        Ok(YUndoManager(TryLock::new(mgr)))
+    }
+
+    /// Extends a list of shared types tracked by this undo manager.
+    pub fn expand_scope(&self, scope: YOut) -> Result<()> {
+        let mut mgr = self.0.borrow_mut()?;
+        let branch = {
+            let txn = mgr.doc().try_transact().map_err(|_| Error::AnotherRwTx)?;
+            scope.branch(&txn)?
+        };
+        mgr.expand_scope(&branch);
+        Ok(())
+    }
+
+    /// Extends a list of transaction origins tracked by this undo manager.
+    pub fn include_origin(&self, origin: Vec<u8>) -> Result<()> {
+        self.0.borrow_mut()?.include_origin(origin.as_slice());
+        Ok(())
+    }
+
+    /// Removes an `origin` from the list of transaction origins tracked by this undo manager.
+    pub fn exclude_origin(&self, origin: Vec<u8>) -> Result<()> {
+        self.0.borrow_mut()?.exclude_origin(origin.as_slice());
+        Ok(())
+    }
+
+    /// Undoes the last tracked change. Returns true if any changes have been made.
+    pub fn undo(&self) -> Result<bool> {
+        self.0.borrow_mut()?.try_undo().map_err(|_| Error::AnotherTx)
+    }
+
+    /// Redoes the last change reverted by `undo`. Returns true if any changes have been made.
+    pub fn redo(&self) -> Result<bool> {
+        self.0.borrow_mut()?.try_redo().map_err(|_| Error::AnotherTx)
+    }
+
+    /// Returns true if there are any undo steps available.
+    pub fn can_undo(&self) -> Result<bool> {
+        Ok(self.0.borrow()?.can_undo())
+    }
+
+    /// Returns true if there are any redo steps available.
+    pub fn can_redo(&self) -> Result<bool> {
+        Ok(self.0.borrow()?.can_redo())
+    }
+
+    /// Makes the next tracked change create a new undo step instead of being merged with the
+    /// previous one, even if it happened within capture timeout window.
+    pub fn stop_capturing(&self) -> Result<()> {
+        self.0.borrow_mut()?.reset();
+        Ok(())
+    }
+
+    /// Removes all undo and redo steps stored within this undo manager.
+    pub fn clear(&self) -> Result<()> {
+        let mut mgr = self.0.borrow_mut()?;
+        // undo manager acquires a blocking transaction on its own
+        drop(mgr.doc().try_transact().map_err(|_| Error::AnotherRwTx)?);
+        mgr.clear();
+        Ok(())
+    }
+
+    /// Subscribes to notifications about new stack items being added. Returned metadata is stored
+    /// within added stack item.
+    pub fn observe_item_added(&self, observer: Box<dyn YUndoObserver>) -> Result<Arc<YSubscription>> {
+        let subscription = self.0.borrow()?.observe_item_added(Self::observer(observer));
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Subscribes to notifications about existing stack items being extended with new changes made
+    /// within capture timeout window. Returned metadata replaces the one stored within the item.
+    pub fn observe_item_updated(&self, observer: Box<dyn YUndoObserver>) -> Result<Arc<YSubscription>> {
+        let subscription = self.0.borrow()?.observe_item_updated(Self::observer(observer));
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+
+    /// Subscribes to notifications about stack items being popped as a result of `undo` or `redo`
+    /// operations. Event metadata can be used ie. to restore cursor position.
+    pub fn observe_item_popped(&self, observer: Box<dyn YUndoObserver>) -> Result<Arc<YSubscription>> {
+        let subscription = self.0.borrow()?.observe_item_popped(Self::observer(observer));
+        Ok(Arc::new(YSubscription::from(subscription)))
+    }
+}
@@ -0,0 +1,332 @@
+use crate::doc::DocRef;
+use crate::collection::{Integrated, SharedCollection};
+use crate::out::YOut;
+use crate::subscription::YSubscription;
+use crate::tools::{check_element, Error, Result};
+use crate::transaction::YTransaction;
+use crate::json::JsonEncoder;
+use crate::lock::TryLock;
+use std::ops::{Bound, Deref};
+use std::sync::{Arc, Mutex};
+use yrs::branch::BranchPtr;
+use yrs::types::weak::{LinkSource, WeakEvent};
+use yrs::types::{Delta, Event, Events, TypeRef, TYPE_REFS_WEAK};
+use yrs::{
+    Any, ArrayRef, Assoc, DeepObservable, MapRef, Observable, OffsetKind, Out, ReadTxn, StickyIndex,
+    TextRef, TransactionMut, WeakPrelim, WeakRef, ID,
+};
+
+/// Bounds of a range quoted by `YText.quote` or `YArray.quote`. Inclusive bound means that
+/// the element at a given index is part of the quoted range. Boundaries also decide whether
+/// elements inserted concurrently at the edges of the range become part of the quote.
+#[derive(uniffi::Record)]
+#[derive(Clone, Copy)]
+pub struct YQuoteBounds {
+    #[uniffi(default = true)]
+    pub start_inclusive: bool,
+    #[uniffi(default = true)]
+    pub end_inclusive: bool,
+}
+
+impl YQuoteBounds {
+    /// Returns a range quoting elements between `start` and `end` indexes of a collection of
+    /// a given length. Both indexes must point to existing elements, even if a given side of
+    /// the range is exclusive.
+    pub(crate) fn check(
+        bounds: Option<YQuoteBounds>,
+        start: u32,
+        end: u32,
+        len: u32,
+    ) -> Result<(Bound<u32>, Bound<u32>)> {
+        check_element(start, len)?;
+        check_element(end, len)?;
+        let bounds = bounds.unwrap_or(YQuoteBounds {
+            start_inclusive: true,
+            end_inclusive: true,
+        });
+        let start = if bounds.start_inclusive {
+            Bound::Included(start)
+        } else {
+            Bound::Excluded(start)
+        };
+        let end = if bounds.end_inclusive {
+            Bound::Included(end)
+        } else {
+            Bound::Excluded(end)
+        };
+        Ok((start, end))
+    }
+}
+
+/// Event generated by [YWeakLink::observe] method, whenever quoted content has changed.
+/// Emitted during transaction commit phase.
+#[derive(uniffi::Record)]
+pub struct YWeakLinkEvent {
+    /// The `YWeakLink` instance which quoted content has changed.
+    pub target: Arc<YWeakLink>,
+    /// Origin of the transaction that produced this event, if any was provided.
+    pub origin: Option<Vec<u8>>,
+}
+
+impl YWeakLinkEvent {
+    fn new(event: &WeakEvent, txn: &TransactionMut) -> Self {
+        Self::with_target(event.as_target(), txn)
+    }
+
+    fn with_target(target: WeakRef<BranchPtr>, txn: &TransactionMut) -> Self {
+        YWeakLinkEvent {
+            target: Arc::new(YWeakLink::from_ref(target, txn.doc().clone().into())),
+            origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+        }
+    }
+}
+
+/// Callback interface used to receive [YWeakLinkEvent]s. Callbacks are invoked synchronously
+/// while the transaction is being committed, so they should not read the document without the
+/// transaction that triggered them.
+#[uniffi::export(callback_interface)]
+pub trait YWeakLinkObserver: Send + Sync {
+    fn on_change(&self, event: YWeakLinkEvent);
+}
+
+/// Weak link to another value stored somewhere in the document.
+///
+/// Weak links are created by quoting a range of `YText` or `YArray` elements, or by linking an
+/// entry of `YMap`. Newly created link is preliminary: it can be inserted into any other shared
+/// collection, after which it can be used to read the current state of the quoted content.
+#[derive(uniffi::Object)]
+#[repr(transparent)]
+pub struct YWeakLink(pub(crate) Arc<TryLock<SharedCollection<WeakPrelim<BranchPtr>, WeakRef<BranchPtr>>>>);
+
+impl YWeakLink {
+    pub fn from_ref(weak_ref: WeakRef<BranchPtr>, doc: DocRef) -> Self {
+        YWeakLink(Arc::new(TryLock::new(SharedCollection::integrated(weak_ref, doc))))
+    }
+
+    pub(crate) fn from_prelim<P>(prelim: WeakPrelim<P>) -> Self {
+        YWeakLink(Arc::new(TryLock::new(SharedCollection::prelim(prelim.into_inner()))))
+    }
+
+    /// Returns a source of the quoted content of a preliminary weak link.
+    pub(crate) fn prelim_source(&self) -> Result<Arc<LinkSource>> {
+        match self.0.lock().deref() {
+            SharedCollection::Prelim(c) => Ok(c.source().clone()),
+            SharedCollection::Integrated(_) => Err(Error::NotPrelim),
+        }
+    }
+
+    pub fn integrate(&self, txn: &mut TransactionMut, weak_ref: WeakRef<BranchPtr>) {
+        let doc = DocRef::from(txn.doc().clone());
+        *self.0.lock_mut() = SharedCollection::Integrated(Integrated::new(weak_ref, doc));
+    }
+}
+
+#[uniffi::export]
+impl YWeakLink {
+    #[inline]
+    pub fn get_type(&self) -> u8 {
+        TYPE_REFS_WEAK
+    }
+
+    /// Returns true if this is a preliminary instance of `YWeakLink`.
+    ///
+    /// Preliminary instances can be nested into other shared data types such as `YArray` and `YMap`.
+    /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
+    /// document store and cannot be nested again: attempt to do so will result in an exception.
+    pub fn prelim(&self) -> Result<bool> {
+        Ok(self.0.borrow()?.is_prelim())
+    }
+
+    /// Checks if current shared type reference is alive and has not been deleted by its parent collection.
+    /// This method only works on already integrated shared types and will return false is current
+    /// type is preliminary (has not been integrated into document).
+    pub fn alive(&self, txn: &YTransaction) -> Result<bool> {
+        self.0.borrow()?.is_alive(txn)
+    }
+
+    /// Returns a current value of the `YMap` entry linked by this `YWeakLink`, or null if that
+    /// entry has been removed. Fails with `InvalidData` if this link quotes another collection.
+    ///
+    /// This method only works for `YWeakLink` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(name = "deref", default(txn=None))]
+    pub fn try_deref(&self, txn: Option<Arc<YTransaction>>) -> Result<Option<YOut>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                check_quoted(c, txn, "a map entry", |t| matches!(t, TypeRef::Map))?;
+                let link: WeakRef<MapRef> = WeakRef::from(c.clone());
+                let value = link.try_deref_value(txn);
+                Ok(value.map(|out| YOut::from_out(out, txn.doc().clone())))
+            }),
+        }
+    }
+
+    /// Returns a list of current values of the `YArray` range quoted by this `YWeakLink`. Fails
+    /// with `InvalidData` if this link quotes another collection.
+    ///
+    /// This method only works for `YWeakLink` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn unquote(&self, txn: Option<Arc<YTransaction>>) -> Result<Vec<YOut>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                check_quoted(c, txn, "an array range", |t| matches!(t, TypeRef::Array))?;
+                let link: WeakRef<ArrayRef> = WeakRef::from(c.clone());
+                let doc = txn.doc().clone();
+                Ok(link.unquote(txn).map(|out| YOut::from_out(out, doc.clone())).collect())
+            }),
+        }
+    }
+
+    /// Returns a current string content of the `YText` range quoted by this `YWeakLink`. Fails
+    /// with `InvalidData` if this link quotes another collection.
+    ///
+    /// This method only works for `YWeakLink` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(name = "toText", default(txn=None))]
+    pub fn to_string(&self, txn: Option<Arc<YTransaction>>) -> Result<String> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                check_quoted(c, txn, "a text range", is_text)?;
+                let link: WeakRef<TextRef> = WeakRef::from(c.clone());
+                Ok(link.source().to_string(txn))
+            }),
+        }
+    }
+
+    /// Returns content linked by this integrated `YWeakLink` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<String> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).weak(c).to_string())
+            }),
+        }
+    }
+
+    /// Subscribes to changes of the content quoted by this `YWeakLink`. All changes are batched
+    /// and eventually triggered during transaction commit phase. Returned subscription keeps the
+    /// observer alive: drop it or call `unsubscribe` to stop receiving events.
+    ///
+    /// For quoted `YText` and `YXmlText` ranges, an event is emitted whenever the quoted string
+    /// is changed, or formatting or embeds are changed within the quoted range.
+    ///
+    /// This method only works for `YWeakLink` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe(
+        &self,
+        observer: Box<dyn YWeakLinkObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let subscription = match quoted_branch(c, txn) {
+                    // Yrs doesn't notify text quotes about changes made at their edges, so
+                    // instead the quoted range is checked after every change of the text
+                    Some(branch) if is_text(branch.type_ref()) => {
+                        let link = c.clone();
+                        let source = c.source().clone();
+                        let last = Mutex::new(source.to_string(txn));
+                        TextRef::from(branch).observe_deep(move |txn, events| {
+                            // only walks the quoted range, unlike the formatted string
+                            let current = source.to_string(txn);
+                            let mut last = last.lock().unwrap_or_else(|e| e.into_inner());
+                            if *last != current || restyled(&link, txn, events) {
+                                *last = current;
+                                observer.on_change(YWeakLinkEvent::with_target(link.clone(), txn));
+                            }
+                        })
+                    }
+                    _ => c.observe(move |txn, e| {
+                        observer.on_change(YWeakLinkEvent::new(e, txn));
+                    }),
+                };
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+}
+
+/// Returns a collection containing content quoted by a given weak link, or `None` if it cannot
+/// be resolved anymore.
+fn quoted_branch<T: ReadTxn>(link: &WeakRef<BranchPtr>, txn: &T) -> Option<BranchPtr> {
+    let start = StickyIndex::from_id(*link.start_id()?, Assoc::After);
+    Some(start.get_offset(txn)?.branch)
+}
+
+fn is_text(type_ref: &TypeRef) -> bool {
+    matches!(type_ref, TypeRef::Text | TypeRef::XmlText)
+}
+
+/// Checks if a given weak link quotes a collection of an `expected` kind. Content which cannot be
+/// resolved anymore is accepted, as it's read as empty.
+fn check_quoted<T: ReadTxn>(
+    link: &WeakRef<BranchPtr>,
+    txn: &T,
+    expected: &str,
+    is_expected: impl Fn(&TypeRef) -> bool,
+) -> Result<()> {
+    match quoted_branch(link, txn) {
+        Some(branch) if !is_expected(branch.type_ref()) => Err(Error::InvalidData(format!(
+            "weak link doesn't quote {}",
+            expected
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns a range of offsets quoted by a given weak link within its text. Its edges are
+/// included even if they are exclusive bounds of a quote.
+fn quoted_range<T: ReadTxn>(link: &WeakRef<BranchPtr>, txn: &T) -> Option<(u32, u32)> {
+    let offset = |id: &ID, assoc| StickyIndex::from_id(*id, assoc).get_offset(txn).map(|o| o.index);
+    Some((offset(link.start_id()?, Assoc::After)?, offset(link.end_id()?, Assoc::Before)?))
+}
+
+/// Checks if text `events` changed formatting or embeds within a range quoted by a given weak
+/// link. Changes of the quoted string itself are not reported here.
+fn restyled(link: &WeakRef<BranchPtr>, txn: &TransactionMut, events: &Events) -> bool {
+    let mut range = None;
+    for event in events.iter() {
+        let delta = match event {
+            Event::Text(e) if e.path().is_empty() => e.delta(txn),
+            Event::XmlText(e) if e.path().is_empty() => e.delta(txn),
+            _ => continue,
+        };
+        let (start, end) = match *range.get_or_insert_with(|| quoted_range(link, txn)) {
+            Some(range) => range,
+            None => return false,
+        };
+        let mut pos = 0;
+        for change in delta {
+            let (len, touched) = match change {
+                Delta::Retain(len, attrs) => (*len, attrs.is_some()),
+                Delta::Inserted(Out::Any(Any::String(s)), _) => (str_len(txn, s), false),
+                Delta::Inserted(_, _) => (1, true),
+                Delta::Deleted(_) => (0, pos > start && pos < end),
+            };
+            if touched && pos < end && pos + len.max(1) > start {
+                return true;
+            }
+            pos += len;
+        }
+    }
+    false
+}
+
+/// Returns length of a string inserted into a text, in units used by a given document.
+fn str_len(txn: &TransactionMut, s: &str) -> u32 {
+    match txn.doc().offset_kind() {
+        OffsetKind::Bytes => s.len() as u32,
+        OffsetKind::Utf16 => s.encode_utf16().count() as u32,
+    }
+}
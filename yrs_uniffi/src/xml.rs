@@ -1,17 +1,21 @@
-use crate::attrs::{YAttributes, YValue};
+use crate::doc::DocRef;
+use crate::attrs::{into_yattrs3, into_yvalue, YAttributes, YValue};
 use crate::collection::SharedCollection;
+use crate::input::PrelimSet;
+use crate::out::YOut;
 use crate::tools::Error;
 use crate::xml_elem::YXmlElement;
 use crate::xml_frag::YXmlFragment;
 use crate::xml_text::YXmlText;
-use std::cell::RefCell;
+use crate::lock::TryLock;
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::Arc;
 use yrs::block::{EmbedPrelim, ItemContent, Prelim};
 use yrs::branch::{Branch, BranchPtr};
-use yrs::types::xml::XmlPrelim;
-use yrs::types::TypeRef;
-use yrs::{Doc, TransactionMut, XmlElementRef, XmlFragmentRef, XmlOut, XmlTextRef};
+use yrs::types::xml::{XmlEvent, XmlPrelim, XmlTextEvent};
+use yrs::types::{Change, Delta, EntryChange, Event, Path, PathSegment, TypeRef};
+use yrs::{Out, TransactionMut, XmlElementRef, XmlFragmentRef, XmlOut, XmlTextRef};
 
 #[derive(uniffi::Enum)]
 #[derive(Clone)]
@@ -36,6 +40,8 @@ pub enum YDeltaXmlChild {
     Element(Arc<YXmlElement>),
     Fragment(Arc<YXmlFragment>),
     Text(Arc<YXmlText>),
+    /// Embedded shared type other than XML node (eg. nested `YMap`).
+    Shared(YOut, Option<YAttributes>),
 }
 
 
@@ -46,13 +52,171 @@ pub enum YXmlDelta {
     YRetain(u32, Option<YAttributes>),
 }
 
+/// A single step of a path leading from an observed shared type to a nested one: either a key of
+/// a map entry or an index of a sequence element.
+#[derive(uniffi::Enum)]
+#[derive(Clone, PartialEq)]
+pub enum YPathSegment {
+    Key(String),
+    Index(u32),
+}
+
+/// Change of a single XML node attribute. `old_value` is absent when attribute has been inserted,
+/// while `new_value` is absent when attribute has been removed.
+#[derive(uniffi::Record)]
+pub struct YXmlAttributeChange {
+    pub old_value: Option<YValue>,
+    pub new_value: Option<YValue>,
+}
+
+/// Event generated by `observe` and `observe_deep` methods of XML shared types. Emitted during
+/// transaction commit phase.
+#[derive(uniffi::Record)]
+pub struct YXmlEvent {
+    /// XML node which changes triggered this event.
+    pub target: YXmlChild,
+    /// Path from the observed XML node to the event target. Empty when the observed node is the
+    /// target itself.
+    pub path: Vec<YPathSegment>,
+    /// Changes made over the child nodes of an XML element or fragment, or over the contents
+    /// of an XML text.
+    pub delta: Vec<YXmlDelta>,
+    /// Attributes changed within a committed transaction, keyed by their names.
+    pub keys: HashMap<String, YXmlAttributeChange>,
+    /// Origin of the transaction that produced this event, if any was provided.
+    pub origin: Option<Vec<u8>>,
+}
+
+impl YXmlEvent {
+    pub(crate) fn from_xml_event(event: &XmlEvent, txn: &TransactionMut) -> Self {
+        let doc = DocRef::from(txn.doc().clone());
+        let delta = event
+            .delta(txn)
+            .iter()
+            .flat_map(|change| match change {
+                Change::Added(values) => values
+                    .iter()
+                    .map(|v| YXmlDelta::YInsert(y_xml_insert(v.clone(), None, doc.clone())))
+                    .collect(),
+                Change::Removed(len) => vec![YXmlDelta::YDelete(*len)],
+                Change::Retain(len) => vec![YXmlDelta::YRetain(*len, None)],
+            })
+            .collect();
+        YXmlEvent {
+            target: YXmlChild::from_xml(event.target().clone(), doc.clone()),
+            path: y_path(event.path()),
+            delta,
+            keys: y_attribute_changes(event.keys(txn)),
+            origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+        }
+    }
+
+    pub(crate) fn from_text_event(event: &XmlTextEvent, txn: &TransactionMut) -> Self {
+        let doc = DocRef::from(txn.doc().clone());
+        let delta = event
+            .delta(txn)
+            .iter()
+            .map(|d| match d {
+                Delta::Inserted(value, attrs) => {
+                    let attrs = attrs.as_ref().map(|attrs| into_yattrs3(attrs));
+                    YXmlDelta::YInsert(y_xml_insert(value.clone(), attrs, doc.clone()))
+                }
+                Delta::Deleted(len) => YXmlDelta::YDelete(*len),
+                Delta::Retain(len, attrs) => {
+                    YXmlDelta::YRetain(*len, attrs.as_ref().map(|attrs| into_yattrs3(attrs)))
+                }
+            })
+            .collect();
+        YXmlEvent {
+            target: YXmlChild::Text(Arc::new(YXmlText::from_ref(event.target().clone(), doc))),
+            path: y_path(event.path()),
+            delta,
+            keys: y_attribute_changes(event.keys(txn)),
+            origin: txn.origin().map(|origin| origin.as_ref().to_vec()),
+        }
+    }
+
+    /// Converts a deep event into [YXmlEvent]. Events produced by non-XML shared types nested
+    /// inside of an XML tree (eg. `YMap` embedded in `YXmlText`) are skipped.
+    pub(crate) fn from_event(event: &Event, txn: &TransactionMut) -> Option<Self> {
+        match event {
+            Event::XmlFragment(e) => Some(Self::from_xml_event(e, txn)),
+            Event::XmlText(e) => Some(Self::from_text_event(e, txn)),
+            _ => None,
+        }
+    }
+}
+
+/// Callback interface used to receive [YXmlEvent]s produced by changes made directly over
+/// observed XML node.
+#[uniffi::export(callback_interface)]
+pub trait YXmlObserver: Send + Sync {
+    fn on_change(&self, event: YXmlEvent);
+}
+
+/// Callback interface used to receive all [YXmlEvent]s produced by changes made within the
+/// observed XML node and any of its descendants, batched per transaction.
+#[uniffi::export(callback_interface)]
+pub trait YXmlDeepObserver: Send + Sync {
+    fn on_change(&self, events: Vec<YXmlEvent>);
+}
+
+pub(crate) fn y_xml_insert(value: Out, attrs: Option<YAttributes>, doc: DocRef) -> YDeltaXmlChild {
+    match value {
+        Out::Any(any) => YDeltaXmlChild::Embed(into_yvalue(&any), attrs),
+        Out::YXmlText(v) => YDeltaXmlChild::Text(Arc::new(YXmlText::from_ref(v, doc))),
+        Out::YXmlElement(v) => YDeltaXmlChild::Element(Arc::new(YXmlElement::from_ref(v, doc))),
+        Out::YXmlFragment(v) => YDeltaXmlChild::Fragment(Arc::new(YXmlFragment::from_ref(v, doc))),
+        other => YDeltaXmlChild::Shared(YOut::from_out(other, doc), attrs),
+    }
+}
+
+pub(crate) fn y_path(path: Path) -> Vec<YPathSegment> {
+    path.into_iter()
+        .map(|segment| match segment {
+            PathSegment::Key(key) => YPathSegment::Key(key.to_string()),
+            PathSegment::Index(index) => YPathSegment::Index(index),
+        })
+        .collect()
+}
+
+fn y_attribute_changes(keys: &HashMap<Arc<str>, EntryChange>) -> HashMap<String, YXmlAttributeChange> {
+    let attr = |value: &Out| match value {
+        Out::Any(any) => Some(into_yvalue(any)),
+        _ => None,
+    };
+    keys.iter()
+        .map(|(key, change)| {
+            let change = match change {
+                EntryChange::Inserted(new) => YXmlAttributeChange {
+                    old_value: None,
+                    new_value: attr(new),
+                },
+                EntryChange::Updated(old, new) => YXmlAttributeChange {
+                    old_value: attr(old),
+                    new_value: attr(new),
+                },
+                EntryChange::Removed(old) => YXmlAttributeChange {
+                    old_value: attr(old),
+                    new_value: None,
+                },
+            };
+            (key.to_string(), change)
+        })
+        .collect()
+}
+
 impl Prelim for YXmlChild {
     type Return = XmlOut;
 
-    fn into_content(self, txn: &mut TransactionMut) -> (ItemContent, Option<Self>) {
-        let type_ref = self.type_ref(txn);
-        let branch = Branch::new(type_ref);
-        (ItemContent::Type(branch), Some(self))
+    fn into_content(self, _txn: &mut TransactionMut) -> (ItemContent, Option<Self>) {
+        match self.type_ref() {
+            Ok(type_ref) => (ItemContent::Type(Branch::new(type_ref)), Some(self)),
+            // nodes are checked with `assert_xml_prelim` before being inserted, so an element can
+            // only get here if it was concurrently integrated elsewhere: its name is no longer
+            // known, so an empty fragment is inserted in its place and the handle is left intact
+            Err(_) => (ItemContent::Type(Branch::new(TypeRef::XmlFragment)), None),
+        }
     }
 
     fn integrate(self, txn: &mut TransactionMut, inner_ref: BranchPtr) {
@@ -74,39 +238,55 @@ impl Prelim for YXmlChild {
 }
 
 impl YXmlChild {
-    pub fn from_xml(value: XmlOut, doc: Doc) -> Self {
+    pub fn from_xml(value: XmlOut, doc: DocRef) -> Self {
         match value {
-            XmlOut::Element(v) => YXmlChild::Element(Arc::new(YXmlElement(Arc::new(RefCell::new(SharedCollection::integrated(v, doc)))))),
+            XmlOut::Element(v) => YXmlChild::Element(Arc::new(YXmlElement(Arc::new(TryLock::new(SharedCollection::integrated(v, doc)))))),
             XmlOut::Fragment(v) => YXmlChild::Fragment(Arc::new(YXmlFragment::new_with_collection(SharedCollection::integrated(v, doc)))),
-            XmlOut::Text(v) => YXmlChild::Text(Arc::new(YXmlText(Arc::new(RefCell::new(SharedCollection::integrated(v, doc)))))),
+            XmlOut::Text(v) => YXmlChild::Text(Arc::new(YXmlText(Arc::new(TryLock::new(SharedCollection::integrated(v, doc)))))),
         }
     }
 
     pub fn assert_xml_prelim(&self) -> crate::tools::Result<()> {
-        let prelim = match self {
-            YXmlChild::Element(e) => e.prelim(),
-            YXmlChild::Fragment(e) => e.prelim(),
-            YXmlChild::Text(e) => e.prelim(),
-        };
-
-        if !prelim {
-            return Err(Error::NotPrelim);
+        self.visit_prelim(&mut PrelimSet::default())
+    }
+
+    /// Checks that this node and all of its descendants are preliminary and haven't been already
+    /// visited as part of the same operation.
+    pub(crate) fn visit_prelim(&self, visited: &mut PrelimSet) -> crate::tools::Result<()> {
+        match self {
+            YXmlChild::Element(e) if e.prelim()? => {
+                visited.visit(e)?;
+                e.visit_prelims(visited)
+            }
+            YXmlChild::Fragment(e) if e.prelim()? => {
+                visited.visit(e)?;
+                e.visit_prelims(visited)
+            }
+            YXmlChild::Text(e) if e.prelim()? => visited.visit(e),
+            _ => Err(Error::NotPrelim),
         }
+    }
 
+    /// Checks a list of nodes, which are going to be inserted by the same operation.
+    pub(crate) fn assert_xml_prelims<'a>(nodes: impl IntoIterator<Item = &'a YXmlChild>) -> crate::tools::Result<()> {
+        let mut visited = PrelimSet::default();
+        for node in nodes {
+            node.visit_prelim(&mut visited)?;
+        }
         Ok(())
     }
 
-    fn type_ref(&self, txn: &TransactionMut) -> TypeRef {
+    fn type_ref(&self) -> crate::tools::Result<TypeRef> {
         match self {
             YXmlChild::Element(v) => {
-                let name = match &v.0.borrow().deref() {
-                    SharedCollection::Integrated(_) => panic!("{}", Error::NotPrelim),
+                let name = match &v.0.lock().deref() {
+                    SharedCollection::Integrated(_) => return Err(Error::NotPrelim),
                     SharedCollection::Prelim(p) => Arc::from(p.name.as_str()),
                 };
-                TypeRef::XmlElement(name)
+                Ok(TypeRef::XmlElement(name))
             }
-            YXmlChild::Fragment(_) => TypeRef::XmlFragment,
-            YXmlChild::Text(_) => TypeRef::XmlText,
+            YXmlChild::Fragment(_) => Ok(TypeRef::XmlFragment),
+            YXmlChild::Text(_) => Ok(TypeRef::XmlText),
         }
     }
 }
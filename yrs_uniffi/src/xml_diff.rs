@@ -0,0 +1,359 @@
+use crate::doc::DocRef;
+use crate::attrs::{into_yattrs3, into_yvalue, YValue};
+use crate::snapshots::{restore_snapshot, YId};
+use crate::tools::Result;
+use crate::xml::{y_xml_insert, YDeltaXmlChild, YXmlAttributeChange};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use yrs::branch::{Branch, BranchID, BranchPtr};
+use yrs::types::text::{ChangeKind, Diff};
+use yrs::{
+    Any, Doc, Out, ReadTxn, Snapshot, Text, Transact, Transaction, TransactionMut, Xml, XmlFragment,
+    XmlFragmentRef, XmlOut, ID,
+};
+
+/// Kind of change made to a block between two snapshots.
+#[derive(uniffi::Enum)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YChangeKind {
+    Added,
+    Removed,
+}
+
+/// Attribution of a change made between two snapshots: identifier of a block, which has been
+/// added or removed. Its `client` field identifies a peer that created that block.
+#[derive(uniffi::Record)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct YChange {
+    pub kind: YChangeKind,
+    pub id: YId,
+}
+
+impl From<yrs::types::text::YChange> for YChange {
+    fn from(change: yrs::types::text::YChange) -> Self {
+        let kind = match change.kind {
+            ChangeKind::Added => YChangeKind::Added,
+            ChangeKind::Removed => YChangeKind::Removed,
+        };
+        YChange {
+            kind,
+            id: change.id.into(),
+        }
+    }
+}
+
+/// A chunk of `YXmlText` contents compared between two snapshots. `ychange` is absent for
+/// contents which exist in both of them.
+#[derive(uniffi::Record)]
+pub struct YXmlTextDiff {
+    pub insert: YDeltaXmlChild,
+    pub ychange: Option<YChange>,
+}
+
+/// XML node compared between two snapshots. Nodes which have been added or removed have their
+/// `ychange` set, while nodes existing in both snapshots have it absent, but their attributes,
+/// children or text contents could still have changed.
+///
+/// `attributes` contain the attributes of a node in the later snapshot, or in the earlier one if
+/// node has been removed. `attribute_changes` only list attributes changed between snapshots of
+/// a node existing in both of them.
+#[derive(uniffi::Enum)]
+pub enum YXmlDiff {
+    Element {
+        id: YId,
+        tag: String,
+        ychange: Option<YChange>,
+        attributes: HashMap<String, YValue>,
+        attribute_changes: HashMap<String, YXmlAttributeChange>,
+        children: Vec<YXmlDiff>,
+    },
+    Fragment {
+        id: YId,
+        ychange: Option<YChange>,
+        children: Vec<YXmlDiff>,
+    },
+    Text {
+        id: YId,
+        ychange: Option<YChange>,
+        attributes: HashMap<String, YValue>,
+        attribute_changes: HashMap<String, YXmlAttributeChange>,
+        delta: Vec<YXmlTextDiff>,
+    },
+}
+
+/// Compares children of an XML `node` between `prev_snapshot` and `snapshot` (or the current
+/// state of a document, if none was given).
+pub(crate) fn diff_children<T: ReadTxn>(
+    doc: &Doc,
+    txn: &T,
+    node: &Branch,
+    prev_snapshot: &Snapshot,
+    snapshot: Option<&Snapshot>,
+) -> Result<Vec<YXmlDiff>> {
+    let (prev_doc, next_doc) = restore(doc, txn, prev_snapshot, snapshot)?;
+    let mut next = next_doc.transact_mut();
+    let next_doc = DocRef::new(next_doc.clone(), true);
+    let mut differ = XmlDiffer::new(&prev_doc, next_doc, &mut next, prev_snapshot);
+    Ok(differ.node_children(node))
+}
+
+/// Compares an XML `node` between `prev_snapshot` and `snapshot` (or the current state of
+/// a document, if none was given). Returns `None` if node didn't exist in any of them.
+pub(crate) fn diff_node<T: ReadTxn>(
+    doc: &Doc,
+    txn: &T,
+    node: &Branch,
+    prev_snapshot: &Snapshot,
+    snapshot: Option<&Snapshot>,
+) -> Result<Option<YXmlDiff>> {
+    let (prev_doc, next_doc) = restore(doc, txn, prev_snapshot, snapshot)?;
+    let mut next = next_doc.transact_mut();
+    let next_doc = DocRef::new(next_doc.clone(), true);
+    let mut differ = XmlDiffer::new(&prev_doc, next_doc, &mut next, prev_snapshot);
+    Ok(differ.root_node(node))
+}
+
+/// Restores documents at both of the compared snapshots. Current state is restored from
+/// a snapshot as well, so that comparison only needs a read-only transaction.
+fn restore<T: ReadTxn>(
+    doc: &Doc,
+    txn: &T,
+    prev_snapshot: &Snapshot,
+    snapshot: Option<&Snapshot>,
+) -> Result<(Doc, Doc)> {
+    let prev = restore_snapshot(doc, txn, prev_snapshot)?;
+    let next = match snapshot {
+        Some(snapshot) => restore_snapshot(doc, txn, snapshot)?,
+        None => restore_snapshot(doc, txn, &txn.snapshot())?,
+    };
+    Ok((prev, next))
+}
+
+/// Returns a branch identified by `id` if it has been integrated into a document.
+fn get_branch<T: ReadTxn>(txn: &T, id: &BranchID) -> Option<BranchPtr> {
+    if let BranchID::Nested(id) = id {
+        // looking up blocks beyond the known state is not supported by the block store
+        if txn.state_vector().get(&id.client) <= id.clock {
+            return None;
+        }
+    }
+    id.get_branch(txn)
+}
+
+/// Walks XML trees of two documents restored from snapshots of the same document, matching their
+/// nodes by identifiers of blocks they were created with.
+struct XmlDiffer<'a, 'txn> {
+    /// Restored documents are read-only, just like nodes returned from them.
+    prev_doc: DocRef,
+    next_doc: DocRef,
+    prev: Transaction<'a>,
+    /// Blocks of a later document contain everything that existed in the earlier one, so it's
+    /// used to compute text deltas, which need to be able to split them.
+    next: &'a mut TransactionMut<'txn>,
+    prev_snapshot: &'a Snapshot,
+    next_snapshot: Snapshot,
+}
+
+impl<'a, 'txn> XmlDiffer<'a, 'txn> {
+    fn new(
+        prev: &'a Doc,
+        next_doc: DocRef,
+        next: &'a mut TransactionMut<'txn>,
+        prev_snapshot: &'a Snapshot,
+    ) -> Self {
+        XmlDiffer {
+            prev_doc: DocRef::new(prev.clone(), true),
+            next_doc,
+            prev: prev.transact(),
+            next_snapshot: next.snapshot(),
+            next,
+            prev_snapshot,
+        }
+    }
+
+    fn node_children(&mut self, node: &Branch) -> Vec<YXmlDiff> {
+        let id = node.id();
+        let prev = get_branch(&self.prev, &id);
+        let next = get_branch(&*self.next, &id);
+        self.children(prev, next)
+    }
+
+    fn root_node(&mut self, node: &Branch) -> Option<YXmlDiff> {
+        let id = node.id();
+        let prev = get_branch(&self.prev, &id).and_then(|b| XmlOut::try_from(b).ok());
+        let next = get_branch(&*self.next, &id).and_then(|b| XmlOut::try_from(b).ok());
+        if prev.is_none() && next.is_none() {
+            return None;
+        }
+        // node itself doesn't belong to a compared range, so it's never attributed
+        Some(self.node(prev, next, None))
+    }
+
+    fn children(&mut self, prev: Option<BranchPtr>, next: Option<BranchPtr>) -> Vec<YXmlDiff> {
+        let prev: Vec<XmlOut> = match prev {
+            Some(branch) => XmlFragmentRef::from(branch).children(&self.prev).collect(),
+            None => Vec::new(),
+        };
+        let next: Vec<XmlOut> = match next {
+            Some(branch) => XmlFragmentRef::from(branch).children(&*self.next).collect(),
+            None => Vec::new(),
+        };
+        let prev_ids: HashSet<ID> = prev.iter().map(node_id).collect();
+        let next_ids: HashSet<ID> = next.iter().map(node_id).collect();
+
+        // both lists are ordered the same way, so it's enough to merge them: nodes removed
+        // within a given gap are listed before the ones added in it
+        let mut result = Vec::with_capacity(prev.len().max(next.len()));
+        let mut prev = prev.into_iter().peekable();
+        let mut next = next.into_iter().peekable();
+        loop {
+            if let Some(node) = prev.next_if(|node| !next_ids.contains(&node_id(node))) {
+                let ychange = change(YChangeKind::Removed, &node);
+                result.push(self.node(Some(node), None, Some(ychange)));
+            } else if let Some(node) = next.next_if(|node| !prev_ids.contains(&node_id(node))) {
+                let ychange = change(YChangeKind::Added, &node);
+                result.push(self.node(None, Some(node), Some(ychange)));
+            } else {
+                match (prev.next(), next.next()) {
+                    (None, None) => break,
+                    (prev, next) => result.push(self.node(prev, next, None)),
+                }
+            }
+        }
+        result
+    }
+
+    fn node(&mut self, prev: Option<XmlOut>, next: Option<XmlOut>, ychange: Option<YChange>) -> YXmlDiff {
+        let latest = next.as_ref().or(prev.as_ref()).expect("node exists in either snapshot");
+        let id = node_id(latest).into();
+        match latest.clone() {
+            XmlOut::Element(element) => {
+                let (attributes, attribute_changes) = self.attributes(&prev, &next);
+                let children = self.children(branch(&prev), branch(&next));
+                YXmlDiff::Element {
+                    id,
+                    tag: element.tag().to_string(),
+                    ychange,
+                    attributes,
+                    attribute_changes,
+                    children,
+                }
+            }
+            XmlOut::Fragment(_) => YXmlDiff::Fragment {
+                id,
+                ychange,
+                children: self.children(branch(&prev), branch(&next)),
+            },
+            XmlOut::Text(text) => {
+                let (attributes, attribute_changes) = self.attributes(&prev, &next);
+                let delta = if next.is_some() {
+                    let (hi, lo) = (Some(&self.next_snapshot), Some(self.prev_snapshot));
+                    let diff = text.diff_range(self.next, hi, lo, YChange::from);
+                    y_xml_text_diffs(diff, &self.next_doc)
+                } else {
+                    let diff = text.diff(&self.prev, YChange::from);
+                    y_xml_text_diffs(diff, &self.prev_doc)
+                };
+                YXmlDiff::Text {
+                    id,
+                    ychange,
+                    attributes,
+                    attribute_changes,
+                    delta,
+                }
+            }
+        }
+    }
+
+    fn attributes(
+        &self,
+        prev: &Option<XmlOut>,
+        next: &Option<XmlOut>,
+    ) -> (HashMap<String, YValue>, HashMap<String, YXmlAttributeChange>) {
+        let prev = prev.as_ref().map(|node| attributes(node, &self.prev));
+        let next = next.as_ref().map(|node| attributes(node, &*self.next));
+        let mut changes = HashMap::new();
+        if let (Some(prev), Some(next)) = (&prev, &next) {
+            for (key, value) in prev {
+                if next.get(key) != Some(value) {
+                    let change = YXmlAttributeChange {
+                        old_value: Some(into_yvalue(value)),
+                        new_value: next.get(key).map(into_yvalue),
+                    };
+                    changes.insert(key.clone(), change);
+                }
+            }
+            for (key, value) in next {
+                if !prev.contains_key(key) {
+                    let change = YXmlAttributeChange {
+                        old_value: None,
+                        new_value: Some(into_yvalue(value)),
+                    };
+                    changes.insert(key.clone(), change);
+                }
+            }
+        }
+        let attributes = next
+            .or(prev)
+            .unwrap_or_default()
+            .iter()
+            .map(|(key, value)| (key.clone(), into_yvalue(value)))
+            .collect();
+        (attributes, changes)
+    }
+}
+
+fn as_branch(node: &XmlOut) -> &Branch {
+    match node {
+        XmlOut::Element(v) => v.as_ref(),
+        XmlOut::Fragment(v) => v.as_ref(),
+        XmlOut::Text(v) => v.as_ref(),
+    }
+}
+
+fn node_id(node: &XmlOut) -> ID {
+    match as_branch(node).id() {
+        BranchID::Nested(id) => id,
+        // root types are never children of other nodes
+        BranchID::Root(_) => unreachable!("XML child node cannot be a root type"),
+    }
+}
+
+fn branch(node: &Option<XmlOut>) -> Option<BranchPtr> {
+    node.as_ref().map(|node| BranchPtr::from(as_branch(node)))
+}
+
+fn change(kind: YChangeKind, node: &XmlOut) -> YChange {
+    YChange {
+        kind,
+        id: node_id(node).into(),
+    }
+}
+
+fn attributes<T: ReadTxn>(node: &XmlOut, txn: &T) -> HashMap<String, Any> {
+    let attributes: Vec<(&str, Out)> = match node {
+        XmlOut::Element(v) => v.attributes(txn).collect(),
+        XmlOut::Text(v) => v.attributes(txn).collect(),
+        XmlOut::Fragment(_) => Vec::new(),
+    };
+    attributes
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Out::Any(any) => Some((key.to_string(), any)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn y_xml_text_diffs(delta: Vec<Diff<YChange>>, doc: &DocRef) -> Vec<YXmlTextDiff> {
+    delta
+        .into_iter()
+        .map(|d| {
+            let attrs = d.attributes.map(|attrs| into_yattrs3(attrs.deref()));
+            YXmlTextDiff {
+                insert: y_xml_insert(d.insert, attrs, doc.clone()),
+                ychange: d.ychange,
+            }
+        })
+        .collect()
+}
@@ -1,14 +1,21 @@
+use crate::doc::DocRef;
 use crate::attrs::{into_yvalue, YValue};
-use crate::collection::{Integrated, SharedCollection};
-use crate::tools::{Error, Result};
+use crate::collection::{Integrated, SharedCollection, Writable};
+use crate::input::PrelimSet;
+use crate::tools::{check_index, check_range, Error, Result};
+use crate::subscription::YSubscription;
 use crate::transaction::YTransaction;
-use crate::xml::YXmlChild;
-use std::cell::RefCell;
+use crate::snapshots::YSnapshot;
+use crate::xml::{YXmlChild, YXmlDeepObserver, YXmlEvent, YXmlObserver};
+use crate::xml_diff::{diff_node, YXmlDiff};
+use crate::json::JsonEncoder;
+use crate::lock::TryLock;
 use std::collections::HashMap;
 use std::mem;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::sync::Arc;
-use yrs::{Doc, GetString, Out, TransactionMut, Xml, XmlElementRef, XmlFragment};
+use yrs::branch::Branch;
+use yrs::{DeepObservable, GetString, Observable, Out, TransactionMut, Xml, XmlElementRef, XmlFragment, XmlOut};
 
 impl Clone for PrelimXmElement {
     fn clone(&self) -> Self {
@@ -57,22 +64,29 @@ impl PrelimXmElement {
 ///   using interleave-resistant algorithm, where order of concurrent inserts at the same index
 ///   is established using peer's document id seniority.
 #[derive(uniffi::Object)]
-pub struct YXmlElement(pub(crate) Arc<RefCell<SharedCollection<PrelimXmElement, XmlElementRef>>>);
-
-unsafe impl Sync for YXmlElement {}
-unsafe impl Send for YXmlElement {}
+pub struct YXmlElement(pub(crate) Arc<TryLock<SharedCollection<PrelimXmElement, XmlElementRef>>>);
 
 
 impl YXmlElement {
-    pub fn from_ref(elem_ref: XmlElementRef, doc: Doc) -> Self {
-        YXmlElement(Arc::new(RefCell::new(SharedCollection::integrated(elem_ref, doc))))
+    pub fn from_ref(elem_ref: XmlElementRef, doc: DocRef) -> Self {
+        YXmlElement(Arc::new(TryLock::new(SharedCollection::integrated(elem_ref, doc))))
+    }
+
+    /// Visits all descendants of this preliminary element.
+    pub(crate) fn visit_prelims(&self, visited: &mut PrelimSet) -> Result<()> {
+        if let SharedCollection::Prelim(p) = self.0.borrow()?.deref() {
+            for child in p.children.iter() {
+                child.visit_prelim(visited)?;
+            }
+        }
+        Ok(())
     }
     
     pub fn integrate(&self, txn: &mut TransactionMut, xml_element: XmlElementRef) {
-        let doc = txn.doc().clone();
+        let doc = DocRef::from(txn.doc().clone());
 
         let old_value = {
-            let mut guard = self.0.borrow_mut();
+            let mut guard = self.0.lock_mut();
             mem::replace(&mut *guard, SharedCollection::Integrated(Integrated::new(
                 xml_element.clone(),
                 doc,
@@ -96,11 +110,8 @@ impl YXmlElement {
     #[uniffi::constructor(default(attributes=None, children=None))]
     pub fn new(name: String, attributes: Option<HashMap<String, YValue>>, children: Option<Vec<YXmlChild>>) -> Result<YXmlElement> {
         let c = children.unwrap_or_default();
-
-        for child in c.iter() {
-            child.assert_xml_prelim()?;
-        }
-        Ok(YXmlElement(Arc::new(RefCell::new(SharedCollection::prelim(PrelimXmElement {
+        YXmlChild::assert_xml_prelims(c.iter())?;
+        Ok(YXmlElement(Arc::new(TryLock::new(SharedCollection::prelim(PrelimXmElement {
             name,
             attributes: attributes.unwrap_or_default(),
             children: c,
@@ -112,23 +123,21 @@ impl YXmlElement {
     /// Preliminary instances can be nested into other shared data types.
     /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
     /// document store and cannot be nested again: attempt to do so will result in an exception.
-    #[inline]
-    pub fn prelim(&self) -> bool {
-        self.0.borrow().is_prelim()
+    pub fn prelim(&self) -> crate::tools::Result<bool> {
+        Ok(self.0.borrow()?.is_prelim())
     }
 
     /// Checks if current shared type reference is alive and has not been deleted by its parent collection.
     /// This method only works on already integrated shared types and will return false is current
     /// type is preliminary (has not been integrated into document).
-    #[inline]
-    pub fn alive(&self, txn: &YTransaction) -> bool {
-        self.0.borrow().is_alive(txn)
+    pub fn alive(&self, txn: &YTransaction) -> crate::tools::Result<bool> {
+        self.0.borrow()?.is_alive(txn)
     }
 
     /// Returns a tag name of this XML node.
     #[uniffi::method(default(txn=None))]
     pub fn name(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<String> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.name.clone()),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, _| Ok(c.tag().to_string())),
         }
@@ -137,12 +146,27 @@ impl YXmlElement {
     /// Returns a number of child XML nodes stored within this `YXMlElement` instance.
     #[uniffi::method(default(txn=None))]
     pub fn length(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<u32> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.children.len() as u32),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.len(txn))),
         }
     }
 
+    /// Returns this integrated `YXmlElement` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<String> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).xml(&XmlOut::Element(c.clone())).to_string())
+            }),
+        }
+    }
+
     #[uniffi::method(default(txn=None))]
     pub fn insert(
         &self,
@@ -151,12 +175,14 @@ impl YXmlElement {
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
         xml_node.assert_xml_prelim()?;
 
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_index(index, c.children.len() as u32)?;
                 c.children.insert(index as usize, xml_node);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
                 c.insert(txn, index, xml_node);
                 Ok(())
             }),
@@ -167,12 +193,12 @@ impl YXmlElement {
     pub fn push(&self, xml_node: YXmlChild, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
         xml_node.assert_xml_prelim()?;
 
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 c.children.push(xml_node);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 c.push_back(txn, xml_node);
                 Ok(())
             }),
@@ -186,13 +212,15 @@ impl YXmlElement {
         length: Option<u32>,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
         let length = length.unwrap_or(1);
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_range(index, length, c.children.len() as u32)?;
                 c.children
                     .drain((index as usize)..((index + length) as usize));
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
                 c.remove_range(txn, index, length);
                 Ok(())
             }),
@@ -201,13 +229,15 @@ impl YXmlElement {
 
     /// Returns a first child of this XML node.
     /// It can be either `YXmlElement`, `YXmlText` or `undefined` if current node has not children.
+    /// Fails with `NotXmlType` if the first child is not an XML node.
     #[uniffi::method(default(txn=None))]
     pub fn first_child(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => {
                 Ok(c.children.first().cloned())
             }
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| match c.first_child() {
+                None if c.len(txn) > 0 => Err(Error::NotXmlType),
                 None => Ok(None),
                 Some(xml) => Ok(YXmlChild::from_xml(xml, txn.doc().clone()).into()),
             }),
@@ -219,7 +249,7 @@ impl YXmlElement {
     /// parent XML node.
     #[uniffi::method(default(txn=None))]
     pub fn next_sibling(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
@@ -238,7 +268,7 @@ impl YXmlElement {
     /// of parent XML node.
     #[uniffi::method(default(txn=None))]
     pub fn prev_sibling(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
@@ -255,7 +285,7 @@ impl YXmlElement {
     /// Returns a parent `YXmlElement` node or `undefined` if current node has no parent assigned.
     #[uniffi::method(default(txn=None))]
     pub fn parent(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
@@ -266,9 +296,32 @@ impl YXmlElement {
         }
     }
 
+    /// Compares this XML element between `prev_snapshot` and `snapshot` - or the current state of
+    /// a document if no `snapshot` was given - including its attributes, child nodes and text
+    /// contents. Added and removed nodes and text chunks are attributed with a `ychange`.
+    /// Returns null if this element didn't exist in any of the compared snapshots.
+    ///
+    /// Snapshots can only be compared within documents created with garbage collection disabled.
+    #[uniffi::method(default(snapshot=None, txn=None))]
+    pub fn diff(
+        &self,
+        prev_snapshot: Arc<YSnapshot>,
+        snapshot: Option<Arc<YSnapshot>>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> Result<Option<YXmlDiff>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let branch: &Branch = c.as_ref();
+                let snapshot = snapshot.as_ref().map(|snapshot| snapshot.deref().deref());
+                diff_node(txn.doc(), txn, branch, &prev_snapshot, snapshot)
+            }),
+        }
+    }
+
     #[uniffi::method(name = "toText", default(txn=None))]
     pub fn to_string(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<String> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => c.to_string(txn),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.get_string(txn))),
         }
@@ -283,12 +336,12 @@ impl YXmlElement {
         value: YValue,
         txn: Option<Arc<YTransaction>>)
         -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 c.attributes.insert(name.to_string(), value);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 c.insert_attribute(txn, name, value);
                 Ok(())
             }),
@@ -299,7 +352,7 @@ impl YXmlElement {
     /// `null` will be returned.
     #[uniffi::method(default(txn=None))]
     pub fn get_attribute(&self, name: &str, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YValue>> {
-       match &self.0.borrow().deref() {
+       match &self.0.borrow()?.deref() {
             SharedCollection::Integrated(c) => {
                 c.readonly(txn, |c, txn| {
                     let out = c.get_attribute(txn, name);
@@ -309,7 +362,7 @@ impl YXmlElement {
                             if let Out::Any(attr) = out {
                                 Ok(Some(into_yvalue(&attr)))
                             } else {
-                                return Err(Error::InvalidData("attr value".to_string()));
+                                return Err(Error::InvalidXmlAttrs);
                             }
                         }
                     }
@@ -331,12 +384,12 @@ impl YXmlElement {
         &self,
         name: String,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 c.attributes.remove(&name);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 c.remove_attribute(txn, &name);
                 Ok(())
             }),
@@ -347,7 +400,7 @@ impl YXmlElement {
     /// unspecified order.
     #[uniffi::method(default(txn=None))]
     pub fn attributes(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<HashMap<String, YValue>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.clone().attributes),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
                 let mut map = HashMap::new();
@@ -355,7 +408,7 @@ impl YXmlElement {
                     if let Out::Any(attr) = value {
                         map.insert(name.to_string(), into_yvalue(&attr));
                     } else {
-                        return Err(Error::InvalidData("attr value".to_string()));
+                        return Err(Error::InvalidXmlAttrs);
                     }
                 };
 
@@ -363,4 +416,49 @@ impl YXmlElement {
             }),
         }
     }
-}
\ No newline at end of file
+
+    /// Subscribes to all operations happening over this instance of `YXmlElement`. All changes are
+    /// batched and eventually triggered during transaction commit phase. Returned subscription
+    /// keeps the observer alive: drop it or call `unsubscribe` to stop receiving events.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe(
+        &self,
+        observer: Box<dyn YXmlObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe(move |txn, e| {
+                    observer.on_change(YXmlEvent::from_xml_event(e, txn));
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+
+    /// Subscribes to all operations happening over this instance of `YXmlElement` and all of its
+    /// descendant nodes. All changes are batched and eventually triggered during transaction
+    /// commit phase as a single list of events. Returned subscription keeps the observer alive:
+    /// drop it or call `unsubscribe` to stop receiving events.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe_deep(
+        &self,
+        observer: Box<dyn YXmlDeepObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe_deep(move |txn, events| {
+                    let events = events
+                        .iter()
+                        .filter_map(|e| YXmlEvent::from_event(e, txn))
+                        .collect();
+                    observer.on_change(events);
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+}
@@ -1,12 +1,22 @@
-use crate::collection::{Integrated, SharedCollection};
+use crate::doc::DocRef;
+use crate::collection::{Integrated, SharedCollection, Writable};
+use crate::input::PrelimSet;
+use crate::sticky::{YAssoc, YStickyIndex};
+use crate::subscription::YSubscription;
+use crate::tools::{check_index, check_range, Error};
 use crate::transaction::YTransaction;
-use crate::xml::YXmlChild;
-use std::cell::RefCell;
+use crate::snapshots::YSnapshot;
+use crate::xml::{YXmlChild, YXmlDeepObserver, YXmlEvent, YXmlObserver};
+use crate::xml_diff::{diff_children, YXmlDiff};
+use crate::xml_markup::{parse_xml, XmlWriter};
+use crate::json::JsonEncoder;
+use crate::lock::TryLock;
 use std::mem;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::sync::Arc;
+use yrs::branch::Branch;
 use yrs::types::TYPE_REFS_XML_FRAGMENT;
-use yrs::{Doc, GetString, TransactionMut, XmlFragment, XmlFragmentRef};
+use yrs::{DeepObservable, GetString, Observable, TransactionMut, XmlFragment, XmlFragmentRef, XmlOut};
 
 /// Represents a list of `YXmlElement` and `YXmlText` types.
 /// A `YXmlFragment` is similar to a `YXmlElement`, but it does not have a
@@ -14,26 +24,33 @@ use yrs::{Doc, GetString, TransactionMut, XmlFragment, XmlFragmentRef};
 /// element - in this case the attributes and the nodeName are not shared
 #[derive(uniffi::Object)]
 #[repr(transparent)]
-pub struct YXmlFragment(pub(crate)  Arc<RefCell<SharedCollection<Vec<YXmlChild>, XmlFragmentRef>>>);
-
-unsafe impl Sync for YXmlFragment {}
-unsafe impl Send for YXmlFragment {}
+pub struct YXmlFragment(pub(crate)  Arc<TryLock<SharedCollection<Vec<YXmlChild>, XmlFragmentRef>>>);
 
 
 impl YXmlFragment {
-    pub fn from_ref(fragment_ref: XmlFragmentRef, doc: Doc) -> Self {
-        YXmlFragment(Arc::new(RefCell::new(SharedCollection::integrated(fragment_ref, doc))))
+    pub fn from_ref(fragment_ref: XmlFragmentRef, doc: DocRef) -> Self {
+        YXmlFragment(Arc::new(TryLock::new(SharedCollection::integrated(fragment_ref, doc))))
     }
     
     pub fn new_with_collection(init: SharedCollection<Vec<YXmlChild>, XmlFragmentRef>) -> Self {
-        YXmlFragment(Arc::new(RefCell::new(init)))
+        YXmlFragment(Arc::new(TryLock::new(init)))
+    }
+
+    /// Visits all descendants of this preliminary fragment.
+    pub(crate) fn visit_prelims(&self, visited: &mut PrelimSet) -> crate::tools::Result<()> {
+        if let SharedCollection::Prelim(children) = self.0.borrow()?.deref() {
+            for child in children.iter() {
+                child.visit_prelim(visited)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn integrate(&self, txn: &mut TransactionMut, xml_fragment: XmlFragmentRef) {
-        let doc = txn.doc().clone();
+        let doc = DocRef::from(txn.doc().clone());
 
         let old_value = {
-            let mut guard = self.0.borrow_mut();
+            let mut guard = self.0.lock_mut();
             mem::replace(&mut *guard, SharedCollection::Integrated(Integrated::new(
                 xml_fragment.clone(),
                 doc,
@@ -54,12 +71,8 @@ impl YXmlFragment {
 impl YXmlFragment {
     #[uniffi::constructor]
     pub fn new(children: Vec<YXmlChild>) -> crate::tools::Result<Self> {
-        let mut nodes = Vec::with_capacity(children.len());
-        for xml_node in children {
-            xml_node.assert_xml_prelim()?;
-            nodes.push(xml_node);
-        }
-        Ok(YXmlFragment::new_with_collection(SharedCollection::prelim(nodes)))
+        YXmlChild::assert_xml_prelims(children.iter())?;
+        Ok(YXmlFragment::new_with_collection(SharedCollection::prelim(children)))
     }
 
     #[inline]
@@ -72,28 +85,41 @@ impl YXmlFragment {
     /// Preliminary instances can be nested into other shared data types.
     /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
     /// document store and cannot be nested again: attempt to do so will result in an exception.
-    #[inline]
-    pub fn prelim(&self) -> bool {
-        self.0.borrow().is_prelim()
+    pub fn prelim(&self) -> crate::tools::Result<bool> {
+        Ok(self.0.borrow()?.is_prelim())
     }
 
     /// Checks if current shared type reference is alive and has not been deleted by its parent collection.
     /// This method only works on already integrated shared types and will return false is current
     /// type is preliminary (has not been integrated into document).
-    #[inline]
-    pub fn alive(&self, txn: &YTransaction) -> bool {
-        self.0.borrow().is_alive(txn)
+    pub fn alive(&self, txn: &YTransaction) -> crate::tools::Result<bool> {
+        self.0.borrow()?.is_alive(txn)
     }
 
     /// Returns a number of child XML nodes stored within this `YXMlElement` instance.
     #[uniffi::method(default(txn=None))]
     pub fn length(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<u32> {
-        match self.0.borrow().deref() {
+        match self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.len() as u32),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.len(txn))),
         }
     }
 
+    /// Returns this integrated `YXmlFragment` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<String> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).xml(&XmlOut::Fragment(c.clone())).to_string())
+            }),
+        }
+    }
+
     #[uniffi::method(default(txn=None))]
     pub fn insert(
         &self,
@@ -103,12 +129,14 @@ impl YXmlFragment {
     ) -> crate::tools::Result<()> {
         xml_node.assert_xml_prelim()?;
 
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_index(index, c.len() as u32)?;
                 c.insert(index as usize, xml_node);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
                 c.insert(txn, index, xml_node);
                 Ok(())
             }),
@@ -120,12 +148,12 @@ impl YXmlFragment {
                 txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
         xml_node.assert_xml_prelim()?;
 
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 c.push(xml_node);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 c.push_back(txn, xml_node);
                 Ok(())
             }),
@@ -140,12 +168,14 @@ impl YXmlFragment {
         txn: Option<Arc<YTransaction>>,
     ) -> crate::tools::Result<()> {
         let length = length.unwrap_or(1);
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                check_range(index, length, c.len() as u32)?;
                 c.drain((index as usize)..((index + length) as usize));
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
                 c.remove_range(txn, index, length);
                 Ok(())
             }),
@@ -154,24 +184,105 @@ impl YXmlFragment {
 
     /// Returns a first child of this XML node.
     /// It can be either `YXmlElement`, `YXmlText` or `undefined` if current node has not children.
+    /// Fails with `NotXmlType` if the first child is not an XML node.
     #[uniffi::method(default(txn=None))]
     pub fn first_child(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match self.0.borrow().deref() {
+        match self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(match c.first() {
                 None => None,
                 Some(found) => Some(found.clone())
             }),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| match c.first_child() {
+                None if c.len(txn) > 0 => Err(Error::NotXmlType),
                 None => Ok(None),
                 Some(xml) => Ok(Some(YXmlChild::from_xml(xml, txn.doc().clone())))
             }),
         }
     }
 
+    /// Compares child nodes of this XML fragment between `prev_snapshot` and `snapshot` - or
+    /// the current state of a document if no `snapshot` was given - including their attributes,
+    /// descendants and text contents. Added and removed nodes and text chunks are attributed with
+    /// a `ychange`.
+    ///
+    /// Snapshots can only be compared within documents created with garbage collection disabled.
+    #[uniffi::method(default(snapshot=None, txn=None))]
+    pub fn diff(
+        &self,
+        prev_snapshot: Arc<YSnapshot>,
+        snapshot: Option<Arc<YSnapshot>>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Vec<YXmlDiff>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                let branch: &Branch = c.as_ref();
+                let snapshot = snapshot.as_ref().map(|snapshot| snapshot.deref().deref());
+                diff_children(txn.doc(), txn, branch, &prev_snapshot, snapshot)
+            }),
+        }
+    }
+
+    /// Parses a given `xml` string and appends resulting nodes at the end of this fragment. All of
+    /// them are inserted within a single transaction, after the whole input has been parsed:
+    /// malformed input fails with `Decode` without changing this fragment.
+    ///
+    /// Comments, processing instructions and document type declarations are skipped. If `html`
+    /// is set, input is parsed leniently: tag and attribute names are lowercased, attribute values
+    /// don't need to be quoted, void elements (eg. `<br>`) and other unclosed elements are closed
+    /// implicitly, while stray end tags are ignored.
+    #[uniffi::method(default(html=false, txn=None))]
+    pub fn import_xml(
+        &self,
+        xml: &str,
+        html: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<()> {
+        let nodes = parse_xml(xml, html)?;
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                c.extend(nodes);
+                Ok(())
+            }
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                for node in nodes {
+                    c.push_back(txn, node);
+                }
+                Ok(())
+            }),
+        }
+    }
+
+    /// Returns child nodes of this fragment serialized as an XML string, which can be parsed
+    /// back using `import_xml`. Special characters are escaped and attributes are written in
+    /// alphabetical order. Formatting attributes of text nodes are not exported. Tags and
+    /// attributes, which names are not valid in XML, fail with `InvalidXmlName`.
+    ///
+    /// If `pretty` is set, elements containing only other elements have each of them written in
+    /// a separate, indented line. It doesn't change elements containing text, so their contents
+    /// remain intact. Added line breaks and indentation are read back by `import_xml` as text
+    /// nodes, so pretty output is meant for reading rather than a round trip.
+    ///
+    /// This method only works for `YXmlFragment` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(pretty=false, txn=None))]
+    pub fn export_xml(
+        &self,
+        pretty: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<String> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                XmlWriter::new(txn, pretty).write(c)
+            }),
+        }
+    }
+
     /// Returns a string representation of this XML node.
     #[uniffi::method(name = "toText", default(txn=None))]
     pub fn to_string(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<String> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => {
                 let mut str = String::new();
                 for child in c.iter() {
@@ -187,5 +298,70 @@ impl YXmlFragment {
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.get_string(txn))),
         }
     }
-}
 
+    /// Subscribes to all operations happening over this instance of `YXmlFragment`. All changes are
+    /// batched and eventually triggered during transaction commit phase. Returned subscription
+    /// keeps the observer alive: drop it or call `unsubscribe` to stop receiving events.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe(
+        &self,
+        observer: Box<dyn YXmlObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe(move |txn, e| {
+                    observer.on_change(YXmlEvent::from_xml_event(e, txn));
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+
+    /// Returns a sticky index pointing to a given `index` among child nodes of this fragment, which
+    /// will remain valid when concurrent changes are made. Returns null if `index` is beyond the
+    /// length of this `YXmlFragment`.
+    ///
+    /// This method only works for `YXmlFragment` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn sticky_index(
+        &self,
+        index: u32,
+        assoc: YAssoc,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Option<Arc<YStickyIndex>>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(YStickyIndex::at(txn, c, index, assoc))
+            }),
+        }
+    }
+
+    /// Subscribes to all operations happening over this instance of `YXmlFragment` and all of its
+    /// descendant nodes. All changes are batched and eventually triggered during transaction
+    /// commit phase as a single list of events. Returned subscription keeps the observer alive:
+    /// drop it or call `unsubscribe` to stop receiving events.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe_deep(
+        &self,
+        observer: Box<dyn YXmlDeepObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe_deep(move |txn, events| {
+                    let events = events
+                        .iter()
+                        .filter_map(|e| YXmlEvent::from_event(e, txn))
+                        .collect();
+                    observer.on_change(events);
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+}
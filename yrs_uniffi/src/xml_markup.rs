@@ -0,0 +1,567 @@
+use crate::attrs::YValue;
+use crate::tools::{Error, Result, YDecodeErrorKind};
+use crate::xml::YXmlChild;
+use crate::xml_elem::YXmlElement;
+use crate::xml_text::YXmlText;
+use std::collections::HashMap;
+use std::sync::Arc;
+use yrs::types::text::{Diff, YChange};
+use yrs::{Out, ReadTxn, Text, Xml, XmlElementRef, XmlFragment, XmlOut, XmlTextRef};
+
+/// HTML elements, which never have any children and don't need to be closed.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// HTML elements, which contents are not parsed but read as a plain text.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Indentation used by pretty printed XML.
+const INDENT: &str = "  ";
+
+/// Maximum length (in bytes) of a character or entity reference, including `&` and `;`.
+const MAX_ENTITY_LEN: usize = 33;
+
+/// Maximum nesting depth of parsed elements. Deeper documents are rejected, as preliminary types
+/// are later integrated recursively.
+const MAX_DEPTH: usize = 256;
+
+/// XML node parsed from a string, before it's turned into a preliminary shared type.
+enum Node {
+    Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<Node>,
+    },
+    Text(String),
+}
+
+impl Node {
+    fn into_prelim(self) -> Result<YXmlChild> {
+        match self {
+            Node::Element {
+                name,
+                attributes,
+                children,
+            } => {
+                let attributes: HashMap<String, YValue> = attributes
+                    .into_iter()
+                    .map(|(k, v)| (k, YValue::String(v)))
+                    .collect();
+                let children = children
+                    .into_iter()
+                    .map(Node::into_prelim)
+                    .collect::<Result<Vec<_>>>()?;
+                let element = YXmlElement::new(name, Some(attributes), Some(children))?;
+                Ok(YXmlChild::Element(Arc::new(element)))
+            }
+            Node::Text(text) => Ok(YXmlChild::Text(Arc::new(YXmlText::new(text, None)))),
+        }
+    }
+}
+
+/// Element, which start tag has been read, but its end tag hasn't been reached yet.
+struct OpenElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+/// Parses a given `xml` string into a list of preliminary XML nodes.
+///
+/// Comments, processing instructions and document type declarations are skipped, while CDATA
+/// sections are read as a text. In `html` mode, parser is lenient: tag and attribute names are
+/// case-insensitive, attribute values don't need to be quoted, void elements (eg. `<br>`) don't
+/// need to be closed, unclosed elements are closed implicitly and stray end tags are ignored.
+pub(crate) fn parse_xml(xml: &str, html: bool) -> Result<Vec<YXmlChild>> {
+    Parser { src: xml, pos: 0, html }
+        .parse()?
+        .into_iter()
+        .map(Node::into_prelim)
+        .collect()
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    html: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<Vec<Node>> {
+        let mut root = Vec::new();
+        let mut stack: Vec<OpenElement> = Vec::new();
+        let mut text = String::new();
+
+        while let Some(c) = self.peek() {
+            if self.rest().starts_with("<!--") {
+                self.skip_past("-->", "comment")?;
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.pos += "<![CDATA[".len();
+                let start = self.pos;
+                self.skip_past("]]>", "CDATA section")?;
+                text.push_str(&self.src[start..self.pos - "]]>".len()]);
+            } else if self.rest().starts_with("<?") {
+                self.skip_past("?>", "processing instruction")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">", "declaration")?;
+            } else if self.rest().starts_with("</") {
+                flush(&mut text, &mut stack, &mut root);
+                self.end_tag(&mut stack, &mut root)?;
+            } else if c == '<' && self.peek_at(1).is_some_and(is_name_start) {
+                flush(&mut text, &mut stack, &mut root);
+                self.start_tag(&mut stack, &mut root)?;
+            } else if c == '<' && !self.html {
+                return Err(self.error("unexpected '<'"));
+            } else if c == '&' {
+                let decoded = self.entity()?;
+                text.push_str(&decoded);
+            } else {
+                text.push(c);
+                self.pos += c.len_utf8();
+            }
+        }
+
+        flush(&mut text, &mut stack, &mut root);
+        if let Some(open) = stack.last() {
+            if !self.html {
+                return Err(self.error(&format!("element <{}> is not closed", open.name)));
+            }
+        }
+        while let Some(open) = stack.pop() {
+            close(open, &mut stack, &mut root);
+        }
+        Ok(root)
+    }
+
+    fn start_tag(&mut self, stack: &mut Vec<OpenElement>, root: &mut Vec<Node>) -> Result<()> {
+        self.pos += 1; // '<'
+        let name = self.name();
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let self_closing = loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Err(self.error(&format!("start tag <{}> is not closed", name))),
+                Some('>') => {
+                    self.pos += 1;
+                    break false;
+                }
+                Some('/') if self.peek_at(1) == Some('>') => {
+                    self.pos += 2;
+                    break true;
+                }
+                Some(c) if is_name_start(c) => {
+                    let (key, value) = self.attribute()?;
+                    if attributes.iter().any(|(k, _)| *k == key) {
+                        if !self.html {
+                            return Err(self.error(&format!("duplicate attribute '{}'", key)));
+                        }
+                    } else {
+                        attributes.push((key, value));
+                    }
+                }
+                Some(c) if self.html => {
+                    // skip garbage within a tag
+                    self.pos += c.len_utf8();
+                }
+                Some(c) => return Err(self.error(&format!("unexpected '{}' in a start tag", c))),
+            }
+        };
+
+        let mut element = OpenElement {
+            name,
+            attributes,
+            children: Vec::new(),
+        };
+        if stack.len() >= MAX_DEPTH {
+            return Err(self.error(&format!("elements are nested deeper than {} levels", MAX_DEPTH)));
+        } else if self.html && RAW_TEXT_ELEMENTS.contains(&element.name.as_str()) && !self_closing {
+            let end = format!("</{}", element.name);
+            let len = self
+                .rest()
+                .to_ascii_lowercase()
+                .find(&end)
+                .unwrap_or(self.rest().len());
+            if len > 0 {
+                element.children.push(Node::Text(self.rest()[..len].to_string()));
+            }
+            self.pos += len;
+            stack.push(element);
+        } else if self_closing || (self.html && VOID_ELEMENTS.contains(&element.name.as_str())) {
+            close(element, stack, root);
+        } else {
+            stack.push(element);
+        }
+        Ok(())
+    }
+
+    fn end_tag(&mut self, stack: &mut Vec<OpenElement>, root: &mut Vec<Node>) -> Result<()> {
+        let start = self.pos;
+        self.pos += 2; // '</'
+        let name = self.name();
+        self.skip_whitespace();
+        if self.peek() != Some('>') {
+            if !self.html {
+                return Err(self.error(&format!("end tag </{}> is not closed", name)));
+            }
+            // skip to the end of a malformed tag
+            self.pos += self.rest().find('>').map(|i| i + 1).unwrap_or(self.rest().len());
+        } else {
+            self.pos += 1;
+        }
+
+        if self.html {
+            // close all elements opened after the matching one, ignore stray end tags
+            if let Some(i) = stack.iter().rposition(|e| e.name == name) {
+                while stack.len() > i {
+                    let open = stack.pop().unwrap();
+                    close(open, stack, root);
+                }
+            }
+            Ok(())
+        } else {
+            match stack.pop() {
+                Some(open) if open.name == name => {
+                    close(open, stack, root);
+                    Ok(())
+                }
+                Some(open) => {
+                    self.pos = start;
+                    Err(self.error(&format!(
+                        "end tag </{}> doesn't match start tag <{}>",
+                        name, open.name
+                    )))
+                }
+                None => {
+                    self.pos = start;
+                    Err(self.error(&format!("unexpected end tag </{}>", name)))
+                }
+            }
+        }
+    }
+
+    fn attribute(&mut self) -> Result<(String, String)> {
+        let key = self.name();
+        self.skip_whitespace();
+        if self.peek() != Some('=') {
+            return if self.html {
+                // attribute without a value, eg. `<input disabled>`
+                Ok((key, String::new()))
+            } else {
+                Err(self.error(&format!("attribute '{}' has no value", key)))
+            };
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+
+        let mut value = String::new();
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.pos += 1;
+                loop {
+                    match self.peek() {
+                        None => return Err(self.error(&format!("value of '{}' is not closed", key))),
+                        Some(c) if c == quote => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some('<') if !self.html => {
+                            return Err(self.error("unexpected '<' in an attribute value"))
+                        }
+                        Some('&') => value.push_str(&self.entity()?),
+                        Some(c) => {
+                            value.push(c);
+                            self.pos += c.len_utf8();
+                        }
+                    }
+                }
+            }
+            _ if self.html => {
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || c == '>' {
+                        break;
+                    } else if c == '&' {
+                        value.push_str(&self.entity()?);
+                    } else {
+                        value.push(c);
+                        self.pos += c.len_utf8();
+                    }
+                }
+            }
+            _ => return Err(self.error(&format!("value of '{}' is not quoted", key))),
+        }
+        Ok((key, value))
+    }
+
+    /// Reads a character or entity reference starting at current position.
+    fn entity(&mut self) -> Result<String> {
+        let rest = self.rest();
+        // only look for the end of a reference within its maximum length
+        let mut len = rest.len().min(MAX_ENTITY_LEN);
+        while !rest.is_char_boundary(len) {
+            len -= 1;
+        }
+        let end = rest[..len].find(';');
+        let decoded = end.and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" if self.html => Some('\u{a0}'),
+                _ => {
+                    let code = if let Some(hex) = entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        u32::from_str_radix(hex, 16).ok()
+                    } else if let Some(dec) = entity.strip_prefix('#') {
+                        dec.parse::<u32>().ok()
+                    } else {
+                        None
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            c.map(|c| (c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                self.pos += len;
+                Ok(c.to_string())
+            }
+            None if self.html => {
+                // unknown entities are left as they are
+                self.pos += 1;
+                Ok("&".to_string())
+            }
+            None => Err(self.error("invalid entity reference")),
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let len = self
+            .rest()
+            .find(|c: char| !is_name_char(c))
+            .unwrap_or(self.rest().len());
+        let name = &self.rest()[..len];
+        self.pos += len;
+        if self.html {
+            name.to_ascii_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Moves current position past a given `end` marker of a construct, which contents are ignored.
+    fn skip_past(&mut self, end: &str, what: &str) -> Result<()> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None if self.html => {
+                self.pos = self.src.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("{} is not closed", what))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        let before = &self.src[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        Error::Decode {
+            kind: YDecodeErrorKind::InvalidXml,
+            reason: format!("{} at line {}, column {}", reason, line, column),
+        }
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-' || c == '.'
+}
+
+/// Checks if a given tag or attribute name can be written without breaking the XML markup.
+fn check_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_name_start(c) && chars.all(is_name_char) => Ok(()),
+        _ => Err(Error::InvalidXmlName {
+            name: name.to_string(),
+        }),
+    }
+}
+
+/// Moves text read so far into the children of a currently open element.
+fn flush(text: &mut String, stack: &mut [OpenElement], root: &mut Vec<Node>) {
+    if !text.is_empty() {
+        let node = Node::Text(std::mem::take(text));
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => root.push(node),
+        }
+    }
+}
+
+fn close(element: OpenElement, stack: &mut [OpenElement], root: &mut Vec<Node>) {
+    let node = Node::Element {
+        name: element.name,
+        attributes: element.attributes,
+        children: element.children,
+    };
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(node),
+        None => root.push(node),
+    }
+}
+
+/// Serializes XML nodes into a string. Attributes are written in alphabetical order. Formatting
+/// attributes of text nodes are not written. Tag and attribute names, which are not valid XML
+/// names, are rejected with [Error::InvalidXmlName].
+///
+/// If `pretty` is set, elements containing only other elements have each of them written in
+/// a separate, indented line. Elements containing text are written as they are, so that no
+/// whitespace is added to their contents. Only output written without `pretty` can be parsed back
+/// by [parse_xml] into the same nodes, as the parser keeps the added whitespace as text nodes.
+pub(crate) struct XmlWriter<'a, T: ReadTxn> {
+    txn: &'a T,
+    pretty: bool,
+    buf: String,
+}
+
+impl<'a, T: ReadTxn> XmlWriter<'a, T> {
+    pub fn new(txn: &'a T, pretty: bool) -> Self {
+        XmlWriter {
+            txn,
+            pretty,
+            buf: String::new(),
+        }
+    }
+
+    /// Writes child nodes of a given `node` and returns a resulting string.
+    pub fn write<X: XmlFragment>(mut self, node: &X) -> Result<String> {
+        let children: Vec<XmlOut> = node.children(self.txn).collect();
+        self.children(&children, 0)?;
+        Ok(self.buf)
+    }
+
+    fn children(&mut self, children: &[XmlOut], depth: usize) -> Result<()> {
+        let block = self.pretty && children.iter().all(|c| !matches!(c, XmlOut::Text(_)));
+        for (i, child) in children.iter().enumerate() {
+            if block && (depth > 0 || i > 0) {
+                self.new_line(depth);
+            }
+            self.node(child, depth)?;
+        }
+        if block && depth > 0 && !children.is_empty() {
+            self.new_line(depth - 1);
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &XmlOut, depth: usize) -> Result<()> {
+        match node {
+            XmlOut::Element(e) => self.element(e, depth),
+            XmlOut::Fragment(f) => {
+                // fragment nested within an element has no tag of its own
+                let children: Vec<XmlOut> = f.children(self.txn).collect();
+                self.children(&children, depth)
+            }
+            XmlOut::Text(t) => self.text(t, depth),
+        }
+    }
+
+    fn element(&mut self, element: &XmlElementRef, depth: usize) -> Result<()> {
+        let tag = element.tag().to_string();
+        check_name(&tag)?;
+        let mut attributes: Vec<(String, String)> = element
+            .attributes(self.txn)
+            .map(|(k, v)| (k.to_string(), attribute_value(v, self.txn)))
+            .collect();
+        attributes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.buf.push('<');
+        self.buf.push_str(&tag);
+        for (k, v) in attributes {
+            check_name(&k)?;
+            self.buf.push(' ');
+            self.buf.push_str(&k);
+            self.buf.push_str("=\"");
+            escape(&v, true, &mut self.buf);
+            self.buf.push('"');
+        }
+        self.buf.push('>');
+        let children: Vec<XmlOut> = element.children(self.txn).collect();
+        self.children(&children, depth + 1)?;
+        self.buf.push_str("</");
+        self.buf.push_str(&tag);
+        self.buf.push('>');
+        Ok(())
+    }
+
+    fn text(&mut self, text: &XmlTextRef, depth: usize) -> Result<()> {
+        let delta: Vec<Diff<YChange>> = text.diff(self.txn, |change| change);
+        for chunk in delta {
+            match chunk.insert {
+                Out::Any(yrs::Any::String(s)) => escape(&s, false, &mut self.buf),
+                Out::YXmlElement(e) => self.element(&e, depth)?,
+                Out::YXmlText(t) => self.text(&t, depth)?,
+                Out::YXmlFragment(f) => self.node(&XmlOut::Fragment(f), depth)?,
+                // other embeds have no XML representation
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn new_line(&mut self, depth: usize) {
+        self.buf.push('\n');
+        for _ in 0..depth {
+            self.buf.push_str(INDENT);
+        }
+    }
+}
+
+fn attribute_value<T: ReadTxn>(value: Out, txn: &T) -> String {
+    match value {
+        Out::Any(yrs::Any::String(s)) => s.to_string(),
+        value => value.to_string(txn),
+    }
+}
+
+/// Escapes characters which have a special meaning in XML text or (if `attribute` is set) quoted
+/// attribute values.
+fn escape(s: &str, attribute: bool, buf: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' if attribute => buf.push_str("&quot;"),
+            c => buf.push(c),
+        }
+    }
+}
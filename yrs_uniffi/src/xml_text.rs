@@ -1,18 +1,24 @@
+use crate::doc::DocRef;
 use crate::attrs::{from_yattrs, into_yattrs3, into_yvalue, YValue};
-use crate::collection::{Integrated, SharedCollection};
+use crate::collection::{Integrated, SharedCollection, Writable};
+use crate::out::YOut;
 use crate::snapshots::YSnapshot;
-use crate::tools::Error;
+use crate::sticky::{YAssoc, YStickyIndex};
+use crate::subscription::YSubscription;
+use crate::tools::{check_index, check_range, check_str_index, check_str_range, str_len, Error};
 use crate::transaction::YTransaction;
-use crate::xml::{YDeltaXmlChild, YXmlChild, YXmlDelta};
+use crate::xml::{YDeltaXmlChild, YXmlChild, YXmlDeepObserver, YXmlDelta, YXmlEvent, YXmlObserver};
 use crate::xml_elem::YXmlElement;
 use crate::xml_frag::YXmlFragment;
-use std::cell::RefCell;
+use crate::json::JsonEncoder;
+use crate::lock::TryLock;
 use std::collections::HashMap;
 use std::mem;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::sync::Arc;
+use yrs::types::text::{Diff, YChange};
 use yrs::types::TYPE_REFS_XML_TEXT;
-use yrs::{Doc, GetString, Out, Snapshot, Text, TransactionMut, Xml, XmlTextRef};
+use yrs::{DeepObservable, GetString, Observable, Out, Snapshot, Text, TransactionMut, Xml, XmlTextRef};
 
 #[derive(Clone)]
 pub(crate) struct PrelimXmlText {
@@ -22,22 +28,19 @@ pub(crate) struct PrelimXmlText {
 
 #[derive(uniffi::Object)]
 #[repr(transparent)]
-pub struct YXmlText(pub(crate) Arc<RefCell<SharedCollection<PrelimXmlText, XmlTextRef>>>);
-
-unsafe impl Sync for YXmlText {}
-unsafe impl Send for YXmlText {}
+pub struct YXmlText(pub(crate) Arc<TryLock<SharedCollection<PrelimXmlText, XmlTextRef>>>);
 
 
 impl YXmlText {
-    pub fn from_ref(xml_text_ref: XmlTextRef, doc: Doc) -> Self {
-        YXmlText(Arc::new(RefCell::new(SharedCollection::integrated(xml_text_ref, doc))))
+    pub fn from_ref(xml_text_ref: XmlTextRef, doc: DocRef) -> Self {
+        YXmlText(Arc::new(TryLock::new(SharedCollection::integrated(xml_text_ref, doc))))
     }
 
     pub fn integrate(&self, txn: &mut TransactionMut, xml_text: XmlTextRef) {
-        let doc = txn.doc().clone();
+        let doc = DocRef::from(txn.doc().clone());
 
         let old_value = {
-            let mut guard = self.0.borrow_mut();
+            let mut guard = self.0.lock_mut();
             mem::replace(&mut *guard, SharedCollection::Integrated(Integrated::new(
                 xml_text.clone(),
                 doc,
@@ -57,7 +60,7 @@ impl YXmlText {
 impl YXmlText {
     #[uniffi::constructor(default(attributes=None))]
     pub fn new(text: String, attributes: Option<HashMap<String, YValue>>) -> Self {
-        YXmlText(Arc::new(RefCell::new(SharedCollection::prelim(PrelimXmlText {
+        YXmlText(Arc::new(TryLock::new(SharedCollection::prelim(PrelimXmlText {
             text: text,
             attributes: attributes.unwrap_or_default(),
         }))))
@@ -73,29 +76,42 @@ impl YXmlText {
     /// Preliminary instances can be nested into other shared data types.
     /// Once a preliminary instance has been inserted this way, it becomes integrated into ywasm
     /// document store and cannot be nested again: attempt to do so will result in an exception.
-    #[inline]
-    pub fn prelim(&self) -> bool {
-        self.0.borrow().is_prelim()
+    pub fn prelim(&self) -> crate::tools::Result<bool> {
+        Ok(self.0.borrow()?.is_prelim())
     }
 
     /// Checks if current shared type reference is alive and has not been deleted by its parent collection.
     /// This method only works on already integrated shared types and will return false is current
     /// type is preliminary (has not been integrated into document).
-    #[inline]
-    pub fn alive(&self, txn: &YTransaction) -> bool {
-        self.0.borrow().is_alive(txn)
+    pub fn alive(&self, txn: &YTransaction) -> crate::tools::Result<bool> {
+        self.0.borrow()?.is_alive(txn)
     }
 
     /// Returns length of an underlying string stored in this `YXmlText` instance,
-    /// understood as a number of UTF-8 encoded bytes.
+    /// understood as a number of UTF-16 code units (see `bytes_offset`).
     #[uniffi::method(default(txn=None))]
     pub fn length(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<u32> {
-        match &self.0.borrow().deref() {
-            SharedCollection::Prelim(c) => Ok(c.text.len() as u32),
+        match &self.0.borrow()?.deref() {
+            SharedCollection::Prelim(c) => Ok(str_len(&c.text)),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.len(txn))),
         }
     }
 
+    /// Returns contents of this integrated `YXmlText` as a JSON string (see `YDoc.to_json`).
+    #[uniffi::method(default(include_formatting=false, txn=None))]
+    pub fn to_json(
+        &self,
+        include_formatting: bool,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<String> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(JsonEncoder::new(txn, include_formatting).text(c).to_string())
+            }),
+        }
+    }
+
     /// Inserts a given `chunk` of text into this `YXmlText` instance, starting at a given `index`.
     ///
     /// Optional object with defined `attributes` will be used to wrap provided text `chunk`
@@ -107,16 +123,18 @@ impl YXmlText {
         chunk: &str,
         attributes: Option<HashMap<String, YValue>>,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 if let None = attributes {
-                    c.text.insert_str(index as usize, chunk);
+                    let index = check_str_index(&c.text, index)?;
+                    c.text.insert_str(index, chunk);
                     Ok(())
                 } else {
                     Err(Error::InvalidPrelimOp)
                 }
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
                 if let None = attributes {
                     c.insert(txn, index, chunk);
                     Ok(())
@@ -142,11 +160,14 @@ impl YXmlText {
         embed: YXmlChild,
         attributes: Option<HashMap<String, YValue>>,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(_) => {
+        embed.assert_xml_prelim()?;
+
+        match self.0.borrow_write()? {
+            Writable::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_index(index, c.len(txn))?;
                 if attributes.is_none() {
                     c.insert_embed(txn, index, embed);
                     Ok(())
@@ -175,11 +196,12 @@ impl YXmlText {
         };
         let attrs = from_yattrs(&attrs);
 
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(_) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
                 c.format(txn, index, length, attrs);
                 Ok(())
             }),
@@ -196,8 +218,8 @@ impl YXmlText {
         chunk: &str,
         attributes: Option<HashMap<String, YValue>>,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 if let None = attributes {
                     c.text.push_str(chunk);
                     Ok(())
@@ -205,7 +227,7 @@ impl YXmlText {
                     Err(Error::InvalidPrelimOp)
                 }
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 if let None = attributes {
                     c.push(txn, chunk);
                     Ok(())
@@ -221,19 +243,22 @@ impl YXmlText {
     }
 
     /// Deletes a specified range of characters, starting at a given `index`.
-    /// Both `index` and `length` are counted in terms of a number of UTF-8 character bytes.
+    /// Both `index` and `length` are counted in terms of a number of UTF-16 code units
+    /// (see `bytes_offset`).
     #[uniffi::method(default(txn=None))]
     pub fn delete(
         &self,
         index: u32,
         length: u32,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
-                c.text.drain((index as usize)..((index + length) as usize));
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
+                let range = check_str_range(&c.text, index, length)?;
+                c.text.drain(range);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
+                check_range(index, length, c.len(txn))?;
                 c.remove_range(txn, index, length);
                 Ok(())
             }),
@@ -245,7 +270,7 @@ impl YXmlText {
     /// parent XML node.
     #[uniffi::method(default(txn=None))]
     pub fn next_sibling(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
@@ -264,7 +289,7 @@ impl YXmlText {
     /// of parent XML node.
     #[uniffi::method(default(txn=None))]
     pub fn prev_sibling(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
@@ -281,7 +306,7 @@ impl YXmlText {
     /// Returns a parent `YXmlElement` node or `undefined` if current node has no parent assigned.
     #[uniffi::method(default(txn=None))]
     pub fn parent(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YXmlChild>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
@@ -294,7 +319,7 @@ impl YXmlText {
 
     #[uniffi::method(name = "toText", default(txn=None))]
     pub fn to_string(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<String> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.text.to_string()),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| Ok(c.get_string(txn))),
         }
@@ -308,12 +333,12 @@ impl YXmlText {
         name: &str,
         value: YValue,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 c.attributes.insert(name.to_string(), value);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 c.insert_attribute(txn, name, value);
                 Ok(())
             }),
@@ -324,7 +349,7 @@ impl YXmlText {
     /// `null` will be returned.
     #[uniffi::method(default(txn=None))]
     pub fn get_attribute(&self, name: &str, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<Option<YValue>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Integrated(c) => {
                 c.readonly(txn, |c, txn| {
                     let out = c.get_attribute(txn, name);
@@ -334,7 +359,7 @@ impl YXmlText {
                             if let Out::Any(attr) = out {
                                 Ok(Some(into_yvalue(&attr)))
                             } else {
-                                return Err(Error::InvalidData("attr value".to_string()));
+                                return Err(Error::InvalidXmlAttrs);
                             }
                         }
                     };
@@ -356,12 +381,12 @@ impl YXmlText {
         &self,
         name: String,
         txn: Option<Arc<YTransaction>>) -> crate::tools::Result<()> {
-        match &mut self.0.borrow_mut().deref_mut() {
-            SharedCollection::Prelim(c) => {
+        match self.0.borrow_write()? {
+            Writable::Prelim(mut c) => {
                 c.attributes.remove(&name);
                 Ok(())
             }
-            SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
+            Writable::Integrated(c) => c.mutably(txn, |c, txn| {
                 c.remove_attribute(txn, &name);
                 Ok(())
             }),
@@ -372,7 +397,7 @@ impl YXmlText {
     /// unspecified order.
     #[uniffi::method(default(txn=None))]
     pub fn attributes(&self, txn: Option<Arc<YTransaction>>) -> crate::tools::Result<HashMap<String, YValue>> {
-        match &self.0.borrow().deref() {
+        match &self.0.borrow()?.deref() {
             SharedCollection::Prelim(c) => Ok(c.attributes.clone()),
             SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
                 let mut map = HashMap::new();
@@ -380,7 +405,7 @@ impl YXmlText {
                     if let Out::Any(attr) = value {
                         map.insert(name.to_string(), into_yvalue(&attr));
                     } else {
-                        return Err(Error::InvalidData("attr value".to_string()));
+                        return Err(Error::InvalidXmlAttrs);
                     }
                 };
 
@@ -389,7 +414,8 @@ impl YXmlText {
         }
     }
 
-    /// Returns the Delta representation of this YXmlText type.
+    /// Returns the Delta representation of this YXmlText type. Deltas between `snapshot` and
+    /// `prev_snapshot` can only be computed within read-write transactions.
     #[uniffi::method(default(snapshot=None,prev_snapshot=None,txn=None))]
     pub fn to_delta(
         &self,
@@ -397,10 +423,16 @@ impl YXmlText {
         prev_snapshot: Option<Arc<YSnapshot>>,
         txn: Option<Arc<YTransaction>>,
     ) -> crate::tools::Result<Vec<YXmlDelta>> {
-        match self.0.borrow().deref() {
+        match self.0.borrow()?.deref() {
             SharedCollection::Prelim(_) => {
                 Err(Error::InvalidPrelimOp)
             }
+            SharedCollection::Integrated(c) if snapshot.is_none() && prev_snapshot.is_none() => {
+                c.readonly(txn, |c, txn| {
+                    let delta = c.diff(txn, |change| change);
+                    Ok(y_xml_diffs(delta, &txn.doc().clone().into()))
+                })
+            }
             SharedCollection::Integrated(c) => c.mutably(txn, |c, txn| {
                 let hi: Option<Snapshot> = if let Some(snap) = snapshot {
                     let snap = snap.clone().deref().deref().clone();
@@ -415,30 +447,95 @@ impl YXmlText {
                     None
                 };
 
-                let doc = txn.doc().clone();
                 let delta = c.diff_range(txn, hi.as_ref(), lo.as_ref(), |change| change);
+                Ok(y_xml_diffs(delta, &txn.doc().clone().into()))
+            }),
+        }
+    }
 
-                let mut array: Vec<YXmlDelta> = vec![];
-                for d in delta {
-                    if let Out::Any(any) = d.insert {
-                        let attrs = if let Some(attrs) = d.attributes {
-                            Some(into_yattrs3(attrs.deref()))
-                        } else {
-                            None
-                        };
-                        array.push(YXmlDelta::YInsert(YDeltaXmlChild::Embed(into_yvalue(&any), attrs)));
-                    } else if let Out::YXmlText(textRef) = d.insert {
-                        array.push(YXmlDelta::YInsert(YDeltaXmlChild::Text(Arc::new(YXmlText::from_ref(textRef, doc.clone())))));
-                    } else if let Out::YXmlElement(element_ref) = d.insert {
-                        array.push(YXmlDelta::YInsert(YDeltaXmlChild::Element(Arc::new(YXmlElement::from_ref(element_ref, doc.clone())))));
-                    } else if let Out::YXmlFragment(fragment_ref) = d.insert {
-                        array.push(YXmlDelta::YInsert(YDeltaXmlChild::Fragment(Arc::new(YXmlFragment::from_ref(fragment_ref, doc.clone())))));
-                    } else {
-                        return Err(Error::InvalidData(d.insert.to_string(txn)));
-                    }
-                }
-                Ok(array)
+    /// Subscribes to all operations happening over this instance of `YXmlText`. All changes are
+    /// batched and eventually triggered during transaction commit phase. Returned subscription
+    /// keeps the observer alive: drop it or call `unsubscribe` to stop receiving events.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe(
+        &self,
+        observer: Box<dyn YXmlObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe(move |txn, e| {
+                    observer.on_change(YXmlEvent::from_text_event(e, txn));
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
             }),
         }
     }
-}
\ No newline at end of file
+
+    /// Returns a sticky index pointing to a given `index` of this text (counted the same way as in `insert`), which
+    /// will remain valid when concurrent changes are made. Returns null if `index` is beyond the
+    /// length of this `YXmlText`.
+    ///
+    /// This method only works for `YXmlText` instances that already have been integrated into
+    /// document store.
+    #[uniffi::method(default(txn=None))]
+    pub fn sticky_index(
+        &self,
+        index: u32,
+        assoc: YAssoc,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Option<Arc<YStickyIndex>>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, txn| {
+                Ok(YStickyIndex::at(txn, c, index, assoc))
+            }),
+        }
+    }
+
+    /// Subscribes to all operations happening over this instance of `YXmlText` and all of its
+    /// descendant nodes. All changes are batched and eventually triggered during transaction
+    /// commit phase as a single list of events. Returned subscription keeps the observer alive:
+    /// drop it or call `unsubscribe` to stop receiving events.
+    #[uniffi::method(default(txn=None))]
+    pub fn observe_deep(
+        &self,
+        observer: Box<dyn YXmlDeepObserver>,
+        txn: Option<Arc<YTransaction>>,
+    ) -> crate::tools::Result<Arc<YSubscription>> {
+        match self.0.borrow()?.deref() {
+            SharedCollection::Prelim(_) => Err(Error::InvalidPrelimOp),
+            SharedCollection::Integrated(c) => c.readonly(txn, |c, _| {
+                let subscription = c.observe_deep(move |txn, events| {
+                    let events = events
+                        .iter()
+                        .filter_map(|e| YXmlEvent::from_event(e, txn))
+                        .collect();
+                    observer.on_change(events);
+                });
+                Ok(Arc::new(YSubscription::from(subscription)))
+            }),
+        }
+    }
+}
+
+fn y_xml_diffs(delta: Vec<Diff<YChange>>, doc: &DocRef) -> Vec<YXmlDelta> {
+    let mut array: Vec<YXmlDelta> = vec![];
+    for d in delta {
+        if let Out::Any(any) = d.insert {
+            let attrs = d.attributes.map(|attrs| into_yattrs3(attrs.deref()));
+            array.push(YXmlDelta::YInsert(YDeltaXmlChild::Embed(into_yvalue(&any), attrs)));
+        } else if let Out::YXmlText(text_ref) = d.insert {
+            array.push(YXmlDelta::YInsert(YDeltaXmlChild::Text(Arc::new(YXmlText::from_ref(text_ref, doc.clone())))));
+        } else if let Out::YXmlElement(element_ref) = d.insert {
+            array.push(YXmlDelta::YInsert(YDeltaXmlChild::Element(Arc::new(YXmlElement::from_ref(element_ref, doc.clone())))));
+        } else if let Out::YXmlFragment(fragment_ref) = d.insert {
+            array.push(YXmlDelta::YInsert(YDeltaXmlChild::Fragment(Arc::new(YXmlFragment::from_ref(fragment_ref, doc.clone())))));
+        } else {
+            let attrs = d.attributes.map(|attrs| into_yattrs3(attrs.deref()));
+            array.push(YXmlDelta::YInsert(YDeltaXmlChild::Shared(YOut::from_out(d.insert, doc.clone()), attrs)));
+        }
+    }
+    array
+}